@@ -6,18 +6,29 @@ local FILLER = string.rep("╱", 500)
 --- Ensure treesitter is attached for a buffer/filetype.
 --- @param buf number
 --- @param ft string
+--- @return boolean started Whether a treesitter parser is highlighting the buffer
 local function ensure_treesitter(buf, ft)
     if not vim.api.nvim_buf_is_valid(buf) then
-        return
+        return false
     end
 
     if vim.bo[buf].filetype ~= ft then
         vim.bo[buf].filetype = ft
     end
 
-    pcall(vim.treesitter.start, buf, ft)
+    return (pcall(vim.treesitter.start, buf, ft))
 end
 
+--- Maps difftastic token classes to highlight groups for changed tokens.
+local TOKEN_GROUPS = {
+    keyword = "DifftTokenKeyword",
+    string = "DifftTokenString",
+    type = "DifftTokenType",
+    comment = "DifftTokenComment",
+    delimiter = "DifftTokenDelimiter",
+    tree_sitter_error = "DifftTokenError",
+}
+
 --- Line positions where hunks start (1-indexed)
 --- @type number[]
 M.hunk_positions = {}
@@ -61,6 +72,32 @@ local function setup_diff_window(win)
     vim.wo[win].cursorbind = true
     vim.wo[win].number = true
     vim.wo[win].signcolumn = "no"
+    vim.wo[win].foldmethod = "manual"
+    vim.wo[win].foldtext = "v:lua.require'difftastic-nvim.diff'.foldtext()"
+end
+
+--- Replace a window's folds with the unchanged runs reported for the file.
+--- @param win number Window handle
+--- @param folds table[] `{ start, end }` row ranges (0-indexed, end exclusive)
+local function apply_folds(win, folds)
+    if not vim.api.nvim_win_is_valid(win) then
+        return
+    end
+
+    vim.api.nvim_win_call(win, function()
+        vim.cmd("silent! normal! zE")
+        for _, fold in ipairs(folds) do
+            vim.cmd(string.format("%d,%dfold", fold.start + 1, fold["end"]))
+        end
+    end)
+    vim.wo[win].foldenable = #folds > 0
+end
+
+--- Fold text for collapsed unchanged runs.
+--- @return string
+function M.foldtext()
+    local count = vim.v.foldend - vim.v.foldstart + 1
+    return string.format("  ··· %d unchanged lines ···", count)
 end
 
 local function is_full_line_highlight(hl)
@@ -115,7 +152,7 @@ local function set_range_highlight(buf, ns, line, start_col, end_col, hl_group,
     })
 end
 
-local function apply_diff_highlights(buf, ns, line, content, highlights, range_hl, line_hl)
+local function apply_diff_highlights(buf, ns, line, content, highlights, range_hl, line_hl, token_hl)
     if #highlights == 0 then
         return
     end
@@ -125,6 +162,17 @@ local function apply_diff_highlights(buf, ns, line, content, highlights, range_h
         return
     end
 
+    -- Without a treesitter parser, color changed tokens by difftastic's token class.
+    -- These only set a foreground, so they combine with the change backgrounds.
+    if token_hl then
+        for _, hl in ipairs(highlights) do
+            local group = TOKEN_GROUPS[hl.kind]
+            if group then
+                set_range_highlight(buf, ns, line, hl.start, hl["end"], group, 150)
+            end
+        end
+    end
+
     if covers_all_non_whitespace(content, highlights) then
         set_line_background(buf, ns, line, line_hl, 100)
         return
@@ -179,6 +227,8 @@ function M.render(state, file)
         vim.api.nvim_buf_set_lines(state.right_buf, 0, -1, false, { "-- Empty --" })
         vim.bo[state.left_buf].modifiable = false
         vim.bo[state.right_buf].modifiable = false
+        apply_folds(state.left_win, {})
+        apply_folds(state.right_win, {})
         return
     end
 
@@ -197,13 +247,16 @@ function M.render(state, file)
 
     -- Apply syntax highlighting based on mode
     local use_treesitter = config.highlight_mode ~= "difftastic"
+    local has_parser = false
     if use_treesitter then
         local ft = FILETYPES[file.language] or vim.filetype.match({ filename = vim.fn.fnamemodify(file.path, ":t"), })
         if ft then
-            ensure_treesitter(state.left_buf, ft)
-            ensure_treesitter(state.right_buf, ft)
+            local left_started = ensure_treesitter(state.left_buf, ft)
+            local right_started = ensure_treesitter(state.right_buf, ft)
+            has_parser = left_started and right_started
         end
     end
+    local token_hl = use_treesitter and not has_parser
 
     local left_ns = vim.api.nvim_create_namespace("difft-left")
     local right_ns = vim.api.nvim_create_namespace("difft-right")
@@ -222,8 +275,8 @@ function M.render(state, file)
     for i, row in ipairs(rows) do
         local line = i - 1
 
-        apply_diff_highlights(state.left_buf, left_ns, line, row.left.content, row.left.highlights, removed_hl, removed_line_hl)
-        apply_diff_highlights(state.right_buf, right_ns, line, row.right.content, row.right.highlights, added_hl, added_line_hl)
+        apply_diff_highlights(state.left_buf, left_ns, line, row.left.content, row.left.highlights, removed_hl, removed_line_hl, token_hl)
+        apply_diff_highlights(state.right_buf, right_ns, line, row.right.content, row.right.highlights, added_hl, added_line_hl, token_hl)
 
         if row.left.is_filler then
             vim.api.nvim_buf_set_extmark(state.left_buf, left_ns, line, 0, {
@@ -240,6 +293,42 @@ function M.render(state, file)
         end
     end
 
+    -- Name the definition each hunk sits in, like git's hunk headers. Both sides get a
+    -- line so the panes stay aligned.
+    for _, hunk in ipairs(file.hunks or {}) do
+        if hunk.left_scope or hunk.right_scope then
+            for _, side in ipairs({
+                { state.left_buf, left_ns, hunk.left_scope },
+                { state.right_buf, right_ns, hunk.right_scope },
+            }) do
+                vim.api.nvim_buf_set_extmark(side[1], side[2], hunk.start, 0, {
+                    virt_lines = { { { side[3] and ("@@ " .. side[3]) or "", "DifftHunkScope" } } },
+                    virt_lines_above = true,
+                })
+            end
+        end
+    end
+
+    -- Point moved blocks at their other end: removed blocks on the left, added on the right.
+    for _, block in ipairs(file.moves or {}) do
+        local removed = block.kind == "removed"
+        local line = block.counterpart_lines.start + 1
+        local target = block.counterpart_path == file.path and ("line " .. line)
+            or (block.counterpart_path .. ":" .. line)
+        local label = (removed and "moved to " or "moved from ") .. target
+        local buf, ns = state.right_buf, right_ns
+        if removed then
+            buf, ns = state.left_buf, left_ns
+        end
+        vim.api.nvim_buf_set_extmark(buf, ns, block.start, 0, {
+            virt_text = { { label, "DifftMoved" } },
+            virt_text_pos = "eol",
+        })
+    end
+
+    apply_folds(state.left_win, file.folds or {})
+    apply_folds(state.right_win, file.folds or {})
+
     vim.api.nvim_win_set_cursor(state.left_win, { 1, 0 })
     vim.api.nvim_win_set_cursor(state.right_win, { 1, 0 })
 end
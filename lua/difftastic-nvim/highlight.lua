@@ -61,12 +61,24 @@ M.linked = {
     DifftTreeRenamed = { link = "Directory" },
     DifftTreeRange = { link = "BlueItalic" },
 
+    -- Diff highlights
+    DifftHunkScope = { link = "Comment" },
+    DifftMoved = { link = "Special" },
+
     -- Picker text highlights
     DifftPickerJjIconCurrent = { link = "Added" },
     DifftPickerJjIconImmutable = { link = "Removed" },
     DifftPickerJjIconNormal = { link = "Directory" },
     DifftPickerJjRevset = { link = "Identifier" },
     DifftPickerJjAge = { link = "Comment" },
+
+    -- Changed token highlights, used when no treesitter parser is available
+    DifftTokenKeyword = { link = "Keyword" },
+    DifftTokenString = { link = "String" },
+    DifftTokenType = { link = "Type" },
+    DifftTokenComment = { link = "Comment" },
+    DifftTokenDelimiter = { link = "Delimiter" },
+    DifftTokenError = { link = "Error" },
 }
 
 
@@ -17,6 +17,41 @@ M.config = {
     hunk_wrap_file = true,
     --- When true, scroll to first hunk after opening a file
     scroll_to_first_hunk = true,
+    --- Unchanged lines kept around each hunk; longer unchanged runs are folded (zo to expand).
+    --- nil shows whole files.
+    context_lines = nil,
+    --- When true, blocks of code moved within a file are marked with where they moved to/from
+    detect_moves = false,
+    --- When true, whitespace-only changes are not highlighted and files changed only in
+    --- whitespace are marked in the file tree
+    ignore_whitespace = false,
+    --- Options passed through to difft: ignore_comments, strip_cr, tab_width, override
+    --- (list of "GLOB:LANGUAGE"), byte_limit, graph_limit, parse_error_limit
+    difft = {},
+    --- When true, difft is run by the plugin on the two versions of each file as it is shown,
+    --- instead of by jj/git over the whole diff; a file difft fails on doesn't fail the others
+    direct_difft = false,
+    --- Executables and environment of the commands run for a diff. git, jj and difft are
+    --- paths (nil looks them up on PATH), env is a table of extra environment variables and
+    --- cwd is the directory commands run in (nil is Neovim's working directory)
+    commands = {
+        git = nil,
+        jj = nil,
+        difft = nil,
+        env = {},
+        cwd = nil,
+    },
+    --- Caching of diffs between commits (working-tree diffs are never cached)
+    cache = {
+        enabled = true,
+        --- When true, cached diffs are also written to stdpath("cache") and survive restarts
+        persist = false,
+        --- Number of diffs kept in memory
+        max_entries = 8,
+    },
+    --- When true, the unstaged diff is kept live by watching the working copy for changes,
+    --- including those made outside this Neovim (formatters, other editors, `git add`)
+    watch = false,
     keymaps = {
         next_file = "]f",
         prev_file = "[f",
@@ -47,6 +82,11 @@ M.config = {
 M.state = {
     current_file_idx = 1,
     files = {},
+    session = nil,
+    watcher = nil,
+    revset = nil,
+    --- { lhs = ..., rhs = ..., kind = ... } for a diff of two paths outside any repository
+    paths = nil,
     range_label = nil,
     range_kind = nil,
     tree_win = nil,
@@ -112,6 +152,18 @@ function M.setup(opts)
     if opts.scroll_to_first_hunk ~= nil then
         M.config.scroll_to_first_hunk = opts.scroll_to_first_hunk
     end
+    if opts.context_lines ~= nil then
+        M.config.context_lines = opts.context_lines
+    end
+    if opts.detect_moves ~= nil then
+        M.config.detect_moves = opts.detect_moves
+    end
+    if opts.ignore_whitespace ~= nil then
+        M.config.ignore_whitespace = opts.ignore_whitespace
+    end
+    if opts.direct_difft ~= nil then
+        M.config.direct_difft = opts.direct_difft
+    end
     if opts.keymaps then
         -- Manual merge to preserve explicit false values (tbl_extend ignores them)
         -- Note: nil values are skipped by pairs(), so they keep the default
@@ -127,6 +179,18 @@ function M.setup(opts)
             M.config.tree.width = opts.tree.width
         end
     end
+    if opts.watch ~= nil then
+        M.config.watch = opts.watch
+    end
+    if opts.difft then
+        M.config.difft = vim.tbl_extend("force", M.config.difft, opts.difft)
+    end
+    if opts.commands then
+        M.config.commands = vim.tbl_extend("force", M.config.commands, opts.commands)
+    end
+    if opts.cache then
+        M.config.cache = vim.tbl_extend("force", M.config.cache, opts.cache)
+    end
     if opts.snacks_picker then
         M.config.snacks_picker = vim.tbl_extend("force", M.config.snacks_picker, opts.snacks_picker)
     end
@@ -135,29 +199,281 @@ function M.setup(opts)
     binary.ensure_exists(M.config.download)
 end
 
+--- Handle of the diff job started by the most recent `M.open` or `M.open_paths` call.
+local active_job = nil
+
+--- Call `on_done(result, err)` on the main loop once a background diff job finishes.
+--- Uses the job's wakeup pipe when available, otherwise falls back to a polling timer.
+--- @param job userdata Diff job returned by one of the `*_async` exports
+--- @param on_done function
+local function await_job(job, on_done)
+    local finished = false
+    local function complete()
+        if finished then
+            return
+        end
+        local result, err = job:poll()
+        if result == nil and err == nil then
+            return
+        end
+        finished = true
+        on_done(result, err)
+    end
+
+    local fd = job:wakeup_fd()
+    if fd then
+        local pipe = vim.uv.new_pipe(false)
+        pipe:open(fd)
+        pipe:read_start(function()
+            pipe:read_stop()
+            pipe:close()
+            vim.schedule(complete)
+        end)
+        return
+    end
+
+    local timer = vim.uv.new_timer()
+    timer:start(0, 10, function()
+        if job:is_done() and not timer:is_closing() then
+            timer:stop()
+            timer:close()
+            vim.schedule(complete)
+        end
+    end)
+end
+
+--- Report an error table returned by the native module, with a hint for errors the user can fix.
+--- @param err table Error with `kind` and `message` (plus `program` for "not_installed")
+local function notify_error(err)
+    local hint
+    if err.kind == "not_installed" and err.program == "difft" then
+        hint = "install difftastic (https://difftastic.wilfred.me.uk/) and make sure `difft` is on your PATH, "
+            .. "or set `commands.difft`"
+    elseif err.kind == "not_installed" then
+        hint = "make sure `"
+            .. tostring(err.program)
+            .. "` is installed and on your PATH, or set `commands."
+            .. tostring(err.program)
+            .. "`"
+    elseif err.kind == "not_a_repository" then
+        hint = "run :Difft inside a " .. M.config.vcs .. " repository, or set `vcs` to match it"
+    elseif err.kind == "bad_revision" then
+        hint = "check the revision or range passed to :Difft"
+    end
+
+    local message = "difftastic-nvim: " .. err.message
+    if hint then
+        message = message .. "\nHint: " .. hint
+    end
+    vim.notify(message, vim.log.levels.ERROR)
+end
+
+--- Delay between a working-copy change and the refresh it triggers, so a burst of writes
+--- (a formatter, a checkout) is handled in one go.
+local WATCH_DEBOUNCE_MS = 100
+
+--- Timer debouncing the watcher of the open unstaged diff.
+local watch_timer = nil
+
+--- Watch the working copy and patch the open unstaged diff as files change.
+local function start_watcher()
+    local watcher, err = binary.get().watch(M.config.vcs)
+    if not watcher then
+        notify_error(err)
+        return
+    end
+    M.state.watcher = watcher
+    watch_timer = vim.uv.new_timer()
+    local timer = watch_timer
+
+    local function flush()
+        -- The view was closed or reopened since the change was reported
+        if M.state.watcher ~= watcher then
+            return
+        end
+        local changes = watcher:changes()
+        if changes.all then
+            M.open(nil)
+        elseif #changes.paths > 0 then
+            M.refresh_paths(changes.paths)
+        end
+    end
+
+    local fd = watcher:wakeup_fd()
+    if not fd then
+        timer:start(WATCH_DEBOUNCE_MS, WATCH_DEBOUNCE_MS, vim.schedule_wrap(flush))
+        return
+    end
+
+    local pipe = vim.uv.new_pipe(false)
+    pipe:open(fd)
+    pipe:read_start(function(err, data)
+        -- The pipe reaches EOF once the watcher is stopped
+        if err or not data then
+            pipe:close()
+            return
+        end
+        if not timer:is_closing() then
+            timer:stop()
+            timer:start(WATCH_DEBOUNCE_MS, 0, vim.schedule_wrap(flush))
+        end
+    end)
+end
+
 --- Open diff view for a revision/commit range.
+--- The diff is computed on a background thread; the view opens once it is ready.
 --- @param revset string|nil jj revset or git commit range (nil = unstaged, "--staged" = staged)
 function M.open(revset)
     if M.state.tree_win or M.state.left_win or M.state.right_win then
         M.close()
     end
 
-    local result
+    -- Stop a diff that is still running for an earlier call
+    if active_job then
+        active_job:cancel()
+        active_job = nil
+    end
+
+    binary.get().configure_commands(M.config.commands)
+    binary.get().configure_cache({
+        enabled = M.config.cache.enabled,
+        dir = M.config.cache.persist and (vim.fn.stdpath("cache") .. "/difftastic-nvim") or nil,
+        max_entries = M.config.cache.max_entries,
+    })
+
+    -- Only file metadata is computed up front; rows are fetched as files are shown
+    local opts = {
+        lazy = true,
+        context_lines = M.config.context_lines,
+        detect_moves = M.config.detect_moves,
+        ignore_whitespace = M.config.ignore_whitespace,
+        difft = M.config.difft,
+        direct_difft = M.config.direct_difft,
+    }
+    local job
     if revset == nil then
-        result = binary.get().run_diff_unstaged(M.config.vcs)
+        job = binary.get().run_diff_unstaged_async(M.config.vcs, opts)
     elseif revset == "--staged" then
-        result = binary.get().run_diff_staged(M.config.vcs)
+        job = binary.get().run_diff_staged_async(M.config.vcs, opts)
     else
-        result = binary.get().run_diff(revset, M.config.vcs)
+        job = binary.get().run_diff_async(revset, M.config.vcs, opts)
     end
+    active_job = job
+
+    await_job(job, function(result, err)
+        -- A newer `M.open` call superseded this one
+        if active_job ~= job then
+            return
+        end
+        active_job = nil
+
+        if err then
+            notify_error(err)
+            return
+        end
+        M.show_result(revset, result)
+    end)
+end
+
+--- Open the diff view for two files or directories outside any repository.
+--- The diff is computed on a background thread; the view opens once it is ready.
+--- @param lhs string Old file or directory
+--- @param rhs string New file or directory
+function M.open_paths(lhs, rhs)
+    -- Stop a diff that is still running for an earlier call
+    if active_job then
+        active_job:cancel()
+        active_job = nil
+    end
+
+    binary.get().configure_commands(M.config.commands)
+    local job = binary.get().run_diff_paths_async(lhs, rhs, {
+        context_lines = M.config.context_lines,
+        detect_moves = M.config.detect_moves,
+        ignore_whitespace = M.config.ignore_whitespace,
+        difft = M.config.difft,
+    })
+    active_job = job
+
+    await_job(job, function(result, err)
+        -- A newer `M.open` or `M.open_paths` call superseded this one
+        if active_job ~= job then
+            return
+        end
+        active_job = nil
+
+        if err then
+            notify_error(err)
+            return
+        end
+        M.show_result(nil, result, { lhs = lhs, rhs = rhs })
+    end)
+end
+
+--- Open the diff view of a buffer's unsaved changes against its file on disk.
+--- @param buf number|nil Buffer handle (default: current buffer)
+function M.open_unsaved(buf)
+    buf = buf or vim.api.nvim_get_current_buf()
+    local path = vim.api.nvim_buf_get_name(buf)
+    if path == "" or vim.fn.filereadable(path) == 0 then
+        vim.notify("difftastic-nvim: buffer has no file on disk", vim.log.levels.WARN)
+        return
+    end
+    if not vim.bo[buf].modified then
+        vim.notify("No unsaved changes", vim.log.levels.INFO)
+        return
+    end
+
+    -- Reading with "b" keeps a trailing empty item for a final newline, so joining restores the file
+    local saved = table.concat(vim.fn.readfile(path, "b"), "\n")
+    local text = table.concat(vim.api.nvim_buf_get_lines(buf, 0, -1, false), "\n")
+    if vim.bo[buf].eol or vim.bo[buf].fixeol then
+        text = text .. "\n"
+    end
+
+    binary.get().configure_commands(M.config.commands)
+    local file, err = binary.get().diff_texts({ text = saved, name = path }, { text = text, name = path }, {
+        context_lines = M.config.context_lines,
+        detect_moves = M.config.detect_moves,
+        ignore_whitespace = M.config.ignore_whitespace,
+        difft = M.config.difft,
+    })
+    if not file then
+        notify_error(err)
+        return
+    end
+    M.show_result(nil, { files = { file } }, { lhs = path, rhs = path, kind = "Unsaved" })
+end
+
+--- Open the diff view for a completed diff result.
+--- @param revset string|nil Revset the result was computed for
+--- @param result table Result table with a `files` list and, for lazy diffs, a `session` id
+--- @param paths table|nil `{ lhs = ..., rhs = ..., kind = ... }` if the result is a diff of
+--- two paths outside any repository; `kind` labels it in the tree header (default: "Paths")
+function M.show_result(revset, result, paths)
+    if M.state.tree_win or M.state.left_win or M.state.right_win then
+        M.close()
+    end
+
     if not result.files or #result.files == 0 then
+        if result.session then
+            binary.get().close_session(result.session)
+        end
         vim.notify("No changes found", vim.log.levels.INFO)
         return
     end
 
     M.state.files = result.files
+    M.state.session = result.session
+    M.state.revset = revset
+    M.state.paths = paths
     M.state.current_file_idx = 1
-    M.state.range_kind, M.state.range_label = range_context(revset, M.config.vcs)
+    if paths then
+        M.state.range_kind = paths.kind or "Paths"
+        M.state.range_label = paths.lhs == paths.rhs and paths.lhs or (paths.lhs .. " → " .. paths.rhs)
+    else
+        M.state.range_kind, M.state.range_label = range_context(revset, M.config.vcs)
+    end
 
     -- Store original tabpage and create new one for diff view
     M.state.original_tabpage = vim.api.nvim_get_current_tabpage()
@@ -168,6 +484,20 @@ function M.open(revset)
     diff.open(M.state)
     keymaps.setup(M.state)
 
+    -- Working-tree diffs follow edits: watch the working copy, or at least re-diff a file
+    -- whenever it is written
+    local working_tree = revset == nil and paths == nil
+    if working_tree and M.config.watch then
+        start_watcher()
+    elseif working_tree then
+        vim.api.nvim_create_autocmd("BufWritePost", {
+            group = vim.api.nvim_create_augroup("DifftRefresh", { clear = true }),
+            callback = function(args)
+                M.refresh_paths({ vim.api.nvim_buf_get_name(args.buf) })
+            end,
+        })
+    end
+
     local first_idx = tree.first_file_in_display_order()
     if first_idx then
         M.show_file(first_idx)
@@ -179,10 +509,35 @@ function M.close()
     local diff_tabpage = M.state.diff_tabpage
     local original_tabpage = M.state.original_tabpage
 
+    -- Stop a diff that is still running, so it doesn't reopen the view once it finishes
+    if active_job then
+        active_job:cancel()
+        active_job = nil
+    end
+
+    -- Release the rows cached for the closed diff
+    if M.state.session then
+        binary.get().close_session(M.state.session)
+    end
+
+    pcall(vim.api.nvim_del_augroup_by_name, "DifftRefresh")
+    if M.state.watcher then
+        M.state.watcher:stop()
+    end
+    if watch_timer then
+        watch_timer:stop()
+        watch_timer:close()
+        watch_timer = nil
+    end
+
     -- Reset state first
     M.state = {
         current_file_idx = 1,
         files = {},
+        session = nil,
+        watcher = nil,
+        revset = nil,
+        paths = nil,
         range_label = nil,
         range_kind = nil,
         tree_win = nil,
@@ -214,13 +569,78 @@ function M.show_file(idx)
         return
     end
     M.state.current_file_idx = idx
-    diff.render(M.state, M.state.files[idx])
+    local file = M.state.files[idx]
+    if not file.rows and M.state.session then
+        -- Lazy diffs only carry metadata until a file is first shown
+        local rows, err = binary.get().file_rows(M.state.session, idx)
+        if err then
+            notify_error(err)
+        end
+        file = rows or file
+        M.state.files[idx] = file
+    end
+    if file.error then
+        vim.notify("difftastic-nvim: difft failed on " .. file.path .. ": " .. file.error, vim.log.levels.WARN)
+    end
+    diff.render(M.state, file)
     if M.config.scroll_to_first_hunk then
         diff.first_hunk(M.state)
     end
     tree.highlight_current(M.state)
 end
 
+--- Re-diff files of an open unstaged diff and patch the view in place.
+--- Reopens the whole diff if a file gained or lost all of its changes.
+--- @param paths string[] Absolute or repo-relative paths
+function M.refresh_paths(paths)
+    if M.state.revset ~= nil or M.state.paths ~= nil or #M.state.files == 0 then
+        return
+    end
+
+    local result, err = binary.get().refresh_paths(M.config.vcs, paths, {
+        context_lines = M.config.context_lines,
+        detect_moves = M.config.detect_moves,
+        ignore_whitespace = M.config.ignore_whitespace,
+        difft = M.config.difft,
+        direct_difft = M.config.direct_difft,
+    })
+    if not result then
+        notify_error(err)
+        return
+    end
+
+    local index_by_path = {}
+    for idx, file in ipairs(M.state.files) do
+        index_by_path[file.path] = idx
+    end
+
+    local refreshed = {}
+    for _, file in ipairs(result.files) do
+        if not index_by_path[file.path] then
+            M.open(nil)
+            return
+        end
+        refreshed[file.path] = file
+    end
+    for _, path in ipairs(result.paths) do
+        if index_by_path[path] and not refreshed[path] then
+            M.open(nil)
+            return
+        end
+    end
+
+    -- Refreshed files carry their rows, so lazy sessions never re-fetch them
+    for path, file in pairs(refreshed) do
+        M.state.files[index_by_path[path]] = file
+    end
+    tree.refresh(M.state)
+
+    local current = M.state.files[M.state.current_file_idx]
+    if current and refreshed[current.path] then
+        diff.render(M.state, current)
+    end
+end
+
 --- Show a file and jump to a specific hunk after rendering completes.
 --- @param idx number File index to show
 --- @param hunk_fn function Hunk function to call (e.g., diff.first_hunk or diff.last_hunk)
@@ -368,6 +788,10 @@ function M.goto_file()
     target_line = target_line or 1
 
     local filepath = file.path
+    -- Files of two diffed directories are relative to the new one
+    if state.paths and vim.fn.isdirectory(state.paths.rhs) == 1 then
+        filepath = vim.fs.joinpath(state.paths.rhs, file.path)
+    end
 
     -- Close diff view (switches to original tab, closes diff tab)
     M.close()
@@ -16,6 +16,7 @@ local GLYPHS = {
     deleted = "-",
     changed = "●",
     renamed = "➜",
+    whitespace = "␣",
 }
 
 --- Module state
@@ -60,6 +61,9 @@ local function status_icon(node)
     if node.status == "deleted" then
         return GLYPHS.deleted, "DifftTreeDeleted"
     end
+    if node.whitespace_only then
+        return GLYPHS.whitespace, "DifftTreeMuted"
+    end
     if node.additions > 0 or node.deletions > 0 then
         return GLYPHS.changed, "DifftTreeModified"
     end
@@ -190,6 +194,7 @@ local function build_intermediate_tree(files)
                 node.additions = file.additions or 0
                 node.deletions = file.deletions or 0
                 node.moved_from = file.moved_from
+                node.whitespace_only = file.whitespace_only
             end
         end
     end
@@ -258,6 +263,7 @@ local function convert_to_nui_nodes(node, file_to_node_id)
             additions = child.additions,
             deletions = child.deletions,
             moved_from = child.moved_from,
+            whitespace_only = child.whitespace_only,
         }, grandchildren)
 
         if child.file_idx then
@@ -313,6 +319,9 @@ local function prepare_node(node)
     end
 
     append_stat_chip(line, node.additions, node.deletions)
+    if node.whitespace_only then
+        line:append("  whitespace only", "DifftTreeMuted")
+    end
 
     return line
 end
@@ -386,6 +395,30 @@ local function render_header(state, total_add, total_del)
     vim.api.nvim_buf_add_highlight(state.tree_buf, ns, "DifftTreeDivider", 3, 0, -1)
 end
 
+--- Build nui nodes for the current file list and render the header with its totals.
+--- @param state table Plugin state
+--- @return table[] nui nodes
+local function build_nodes(state)
+    -- Build intermediate tree structure
+    local root = build_intermediate_tree(state.files)
+    propagate_stats(root)
+    flatten_node(root)
+    sort_node(root)
+
+    -- Store totals for header
+    M.total_additions = root.additions
+    M.total_deletions = root.deletions
+
+    -- Convert to nui nodes
+    M.file_to_node_id = {}
+    local nui_nodes = convert_to_nui_nodes(root, M.file_to_node_id)
+
+    -- Render header first
+    render_header(state, root.additions, root.deletions)
+
+    return nui_nodes
+end
+
 function M.open(state)
     vim.cmd("topleft vertical " .. get_config().width .. " new")
     state.tree_win = vim.api.nvim_get_current_win()
@@ -413,22 +446,7 @@ function M.open(state)
     vim.bo[state.tree_buf].filetype = "difft-tree"
     vim.bo[state.tree_buf].modifiable = true
 
-    -- Build intermediate tree structure
-    local root = build_intermediate_tree(state.files)
-    propagate_stats(root)
-    flatten_node(root)
-    sort_node(root)
-
-    -- Store totals for header
-    M.total_additions = root.additions
-    M.total_deletions = root.deletions
-
-    -- Convert to nui nodes
-    M.file_to_node_id = {}
-    local nui_nodes = convert_to_nui_nodes(root, M.file_to_node_id)
-
-    -- Render header first
-    render_header(state, root.additions, root.deletions)
+    local nui_nodes = build_nodes(state)
 
     -- Create nui tree (starts after header)
     M.tree = NuiTree({
@@ -468,6 +486,17 @@ function M.render(state)
     end
 end
 
+--- Rebuild the tree after files in `state.files` were updated in place.
+--- @param state table Plugin state
+function M.refresh(state)
+    if not M.tree or not state.tree_buf or not vim.api.nvim_buf_is_valid(state.tree_buf) then
+        return
+    end
+    M.tree:set_nodes(build_nodes(state))
+    M.tree:render(M.header_lines + 1)
+    M.highlight_current(state)
+end
+
 local function collect_visible_files(tree)
     local files = {}
 
@@ -32,6 +32,27 @@ end, {
     desc = "Open difftastic diff view (no args = unstaged, --staged = staged, or revset/commit)",
 })
 
+vim.api.nvim_create_user_command("DifftPaths", function(opts)
+    if #opts.fargs ~= 2 then
+        vim.notify("DifftPaths: expected two files or directories", vim.log.levels.ERROR)
+        return
+    end
+    local lhs, rhs = vim.fn.expand(opts.fargs[1]), vim.fn.expand(opts.fargs[2])
+    vim.schedule(function()
+        require("difftastic-nvim").open_paths(lhs, rhs)
+    end)
+end, {
+    nargs = "+",
+    complete = "file",
+    desc = "Open difftastic diff view of two files or directories outside any repository",
+})
+
+vim.api.nvim_create_user_command("DifftUnsaved", function()
+    require("difftastic-nvim").open_unsaved()
+end, {
+    desc = "Open difftastic diff view of the current buffer's unsaved changes",
+})
+
 vim.api.nvim_create_user_command("DifftClose", function()
     require("difftastic-nvim").close()
 end, {
@@ -0,0 +1,432 @@
+//! Caching of diffs between immutable commits.
+//!
+//! A diff between two commits can never change, so reopening `HEAD~5..HEAD` should not
+//! re-run difftastic or re-read any blobs. Diff sessions for commit ranges are kept in
+//! memory, keyed by a [`CacheKey`] of the resolved commit ids, the difftastic version,
+//! the processing options and how commands are run, and can optionally be persisted to a
+//! cache directory so they survive restarts.
+//!
+//! Working-tree diffs (unstaged and staged changes) are never cached: their content can
+//! change at any time without any commit id changing.
+//!
+//! ## Disk format
+//!
+//! Each entry is one JSON file named after a stable hash of its key. The key is stored
+//! alongside the data and compared on load, so hash collisions and entries written by an
+//! incompatible version are treated as misses.
+
+use crate::DiffMode;
+use crate::exec::{self, CommandConfig, Program, Runner};
+use crate::processor::ProcessOptions;
+use crate::session::{DiffSession, StoredSession};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex, MutexGuard};
+
+/// Bumped whenever the stored representation changes, invalidating older disk entries.
+const FORMAT_VERSION: u32 = 9;
+
+/// Default number of diffs kept in memory.
+const DEFAULT_MAX_ENTRIES: usize = 8;
+
+/// Identifies a diff between immutable commits.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CacheKey {
+    vcs: String,
+    root: PathBuf,
+    /// Resolved commit ids of the old side.
+    old: Vec<String>,
+    /// Resolved commit ids of the new side.
+    new: Vec<String>,
+    difft_version: String,
+    /// Processed rows depend on these, so each set of options is cached separately.
+    options: ProcessOptions,
+    /// The configured executables and directory, with the environment of
+    /// [`effective_env`]: a different `difft` or `DFT_*` variable changes the output.
+    commands: CommandConfig,
+}
+
+/// How results are cached, as set by `configure_cache`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheOptions {
+    pub enabled: bool,
+    /// Directory for persisted entries; `None` keeps the cache in memory only.
+    pub dir: Option<PathBuf>,
+    pub max_entries: usize,
+}
+
+impl Default for CacheOptions {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            dir: None,
+            max_entries: DEFAULT_MAX_ENTRIES,
+        }
+    }
+}
+
+/// In-memory entries, evicted oldest-first.
+#[derive(Default)]
+struct Memory {
+    options: CacheOptions,
+    entries: HashMap<CacheKey, Arc<DiffSession>>,
+    order: VecDeque<CacheKey>,
+}
+
+impl Memory {
+    fn evict(&mut self) {
+        while self.entries.len() > self.options.max_entries {
+            let Some(oldest) = self.order.pop_front() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+    }
+}
+
+static MEMORY: LazyLock<Mutex<Memory>> = LazyLock::new(|| Mutex::new(Memory::default()));
+
+fn memory() -> MutexGuard<'static, Memory> {
+    MEMORY.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Replaces the cache options, evicting entries beyond the new limit.
+pub fn configure(options: CacheOptions) {
+    let mut memory = memory();
+    if !options.enabled {
+        memory.entries.clear();
+        memory.order.clear();
+    }
+    memory.options = options;
+    memory.evict();
+}
+
+/// Drops all in-memory entries and, if configured, the cache directory's entries.
+pub fn clear() {
+    let dir = {
+        let mut memory = memory();
+        memory.entries.clear();
+        memory.order.clear();
+        memory.options.dir.clone()
+    };
+    if let Some(entries) = dir.and_then(|dir| std::fs::read_dir(dir).ok()) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+}
+
+/// Looks up a diff, loading it from disk into memory if necessary.
+///
+/// `restore` rebuilds a session from a disk entry.
+pub fn get(
+    key: &CacheKey,
+    restore: impl FnOnce(StoredSession) -> DiffSession,
+) -> Option<Arc<DiffSession>> {
+    let dir = {
+        let memory = memory();
+        if !memory.options.enabled {
+            return None;
+        }
+        if let Some(session) = memory.entries.get(key) {
+            return Some(Arc::clone(session));
+        }
+        memory.options.dir.clone()?
+    };
+
+    let session = Arc::new(restore(load(&dir, key)?));
+    insert(key.clone(), Arc::clone(&session));
+    Some(session)
+}
+
+/// Stores a diff in memory.
+pub fn insert(key: CacheKey, session: Arc<DiffSession>) {
+    let mut memory = memory();
+    if !memory.options.enabled {
+        return;
+    }
+    if memory.entries.insert(key.clone(), session).is_none() {
+        memory.order.push_back(key);
+    }
+    memory.evict();
+}
+
+/// Writes a diff to the cache directory, if one is configured.
+pub fn persist(key: &CacheKey, session: &DiffSession) {
+    let dir = {
+        let memory = memory();
+        if !memory.options.enabled {
+            return;
+        }
+        memory.options.dir.clone()
+    };
+    if let Some(dir) = dir {
+        let _ = store(&dir, key, session.to_stored());
+    }
+}
+
+/// A disk entry.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    version: u32,
+    key: CacheKey,
+    session: StoredSession,
+}
+
+fn entry_path(dir: &Path, key: &CacheKey) -> Option<PathBuf> {
+    let key = serde_json::to_vec(key).ok()?;
+    Some(dir.join(format!("{:016x}.json", fnv1a(&key))))
+}
+
+fn load(dir: &Path, key: &CacheKey) -> Option<StoredSession> {
+    let bytes = std::fs::read(entry_path(dir, key)?).ok()?;
+    let entry: Entry = serde_json::from_slice(&bytes).ok()?;
+    (entry.version == FORMAT_VERSION && entry.key == *key).then_some(entry.session)
+}
+
+fn store(dir: &Path, key: &CacheKey, session: StoredSession) -> std::io::Result<()> {
+    let path = entry_path(dir, key).ok_or_else(|| std::io::Error::other("unhashable key"))?;
+    let entry = Entry {
+        version: FORMAT_VERSION,
+        key: key.clone(),
+        session,
+    };
+    let bytes = serde_json::to_vec(&entry).map_err(std::io::Error::other)?;
+
+    // Write to a temporary file first so concurrent readers never see a partial entry.
+    std::fs::create_dir_all(dir)?;
+    let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+    std::fs::write(&tmp, bytes)?;
+    std::fs::rename(tmp, path)
+}
+
+/// 64-bit FNV-1a; stable across builds, unlike `std`'s hasher.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Gets the version of the configured difftastic executable, once per executable.
+fn difft_version() -> Option<String> {
+    static VERSIONS: LazyLock<Mutex<HashMap<Option<PathBuf>, Option<String>>>> =
+        LazyLock::new(|| Mutex::new(HashMap::new()));
+
+    let config = exec::config();
+    let mut versions = VERSIONS.lock().unwrap_or_else(|e| e.into_inner());
+    versions
+        .entry(config.difft.clone())
+        .or_insert_with(|| {
+            config
+                .command(Program::Difft)
+                .arg("--version")
+                .output()
+                .ok()
+                .filter(|o| o.status.success())
+                .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+        })
+        .clone()
+}
+
+/// Computes the cache key for a diff, or `None` if it can't be cached.
+///
+/// Only commit ranges are cacheable. The range is resolved to commit ids so that moving
+/// refs (a branch gaining a commit, `@` being rewritten) produce a different key.
+pub fn key(
+    runner: &Runner,
+    mode: &DiffMode,
+    vcs: &str,
+    root: &Path,
+    options: &ProcessOptions,
+) -> Option<CacheKey> {
+    let DiffMode::Range(range) = mode else {
+        return None;
+    };
+    if !memory().options.enabled {
+        return None;
+    }
+
+    let (old, new) = if vcs == "git" {
+        let (old, new) = crate::parse_git_range(runner, range);
+        (
+            vec![crate::git_commit_id(runner, &old)?],
+            vec![crate::git_commit_id(runner, &new)?],
+        )
+    } else {
+        // The diff covers every commit in the revset, relative to the parents of its roots.
+        (
+            crate::jj_git_commits(runner, &format!("roots({range})-"))?,
+            crate::jj_git_commits(runner, range)?,
+        )
+    };
+
+    Some(CacheKey {
+        vcs: vcs.to_string(),
+        root: root.to_path_buf(),
+        old,
+        new,
+        difft_version: difft_version()?,
+        options: options.clone(),
+        commands: effective_commands(),
+    })
+}
+
+/// The command configuration, with its environment replaced by the effective one.
+fn effective_commands() -> CommandConfig {
+    let config = exec::config();
+    let inherited = std::env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)));
+    CommandConfig {
+        env: effective_env(inherited, &config.env),
+        ..config
+    }
+}
+
+/// The variables that can change a diff as commands see them: `PATH` and `DFT_*` inherited
+/// from Neovim, overridden by the configured ones. Sorted by name.
+fn effective_env(
+    inherited: impl Iterator<Item = (String, String)>,
+    configured: &[(String, String)],
+) -> Vec<(String, String)> {
+    let mut env: BTreeMap<String, String> = inherited
+        .filter(|(name, _)| name == "PATH" || name.starts_with("DFT_"))
+        .collect();
+    env.extend(configured.iter().cloned());
+    env.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_key(name: &str) -> CacheKey {
+        CacheKey {
+            vcs: "git".to_string(),
+            root: PathBuf::from("/repo"),
+            old: vec![format!("{name}-old")],
+            new: vec![format!("{name}-new")],
+            difft_version: "Difftastic 0.0.0".to_string(),
+            options: ProcessOptions::default(),
+            commands: CommandConfig::default(),
+        }
+    }
+
+    fn empty_session() -> Arc<DiffSession> {
+        let source = crate::ContentSource::GitStaged {
+            blobs: crate::cat_file::CatFile::new(PathBuf::from(".")),
+        };
+        Arc::new(DiffSession::restore(
+            StoredSession::default(),
+            source,
+            ProcessOptions::default(),
+            None,
+        ))
+    }
+
+    #[test]
+    fn memory_evicts_oldest_entries() {
+        let mut memory = Memory {
+            options: CacheOptions {
+                max_entries: 2,
+                ..CacheOptions::default()
+            },
+            ..Memory::default()
+        };
+        for name in ["a", "b", "c"] {
+            memory.entries.insert(test_key(name), empty_session());
+            memory.order.push_back(test_key(name));
+            memory.evict();
+        }
+
+        assert_eq!(memory.entries.len(), 2);
+        assert!(!memory.entries.contains_key(&test_key("a")));
+        assert!(memory.entries.contains_key(&test_key("c")));
+    }
+
+    #[test]
+    fn inserted_sessions_are_shared() {
+        let key = test_key("shared");
+        let session = empty_session();
+        insert(key.clone(), Arc::clone(&session));
+
+        let cached = get(&key, |_| unreachable!("entry is in memory")).unwrap();
+        assert!(Arc::ptr_eq(&cached, &session));
+    }
+
+    #[test]
+    fn fnv1a_is_stable() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn entries_round_trip_through_disk() {
+        let dir =
+            std::env::temp_dir().join(format!("difftastic-nvim-cache-{}", std::process::id()));
+        let key = test_key("disk");
+        let other = test_key("other");
+
+        store(&dir, &key, StoredSession::default()).unwrap();
+        assert!(load(&dir, &key).is_some());
+        assert!(load(&dir, &other).is_none());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn mismatched_keys_are_misses() {
+        let dir = std::env::temp_dir().join(format!(
+            "difftastic-nvim-cache-mismatch-{}",
+            std::process::id()
+        ));
+        let key = test_key("a");
+        let other = test_key("b");
+
+        // Simulate a hash collision by writing another key's entry under this key's name.
+        store(&dir, &other, StoredSession::default()).unwrap();
+        std::fs::rename(
+            entry_path(&dir, &other).unwrap(),
+            entry_path(&dir, &key).unwrap(),
+        )
+        .unwrap();
+        assert!(load(&dir, &key).is_none());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn configured_env_overrides_inherited_output_env() {
+        let inherited = [
+            ("PATH", "/usr/bin"),
+            ("HOME", "/home/me"),
+            ("DFT_TAB_WIDTH", "4"),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()));
+        let configured = [("DFT_TAB_WIDTH".to_string(), "8".to_string())];
+
+        assert_eq!(
+            effective_env(inherited, &configured),
+            [
+                ("DFT_TAB_WIDTH".to_string(), "8".to_string()),
+                ("PATH".to_string(), "/usr/bin".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn keys_differ_by_command_config() {
+        let mut key = test_key("commands");
+        key.commands.difft = Some(PathBuf::from("/opt/difft"));
+        assert_ne!(key, test_key("commands"));
+        let dir = Path::new("/cache");
+        assert_ne!(
+            entry_path(dir, &key),
+            entry_path(dir, &test_key("commands"))
+        );
+    }
+}
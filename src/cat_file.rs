@@ -0,0 +1,261 @@
+//! Batched blob reads through a long-lived `git cat-file --batch` process.
+//!
+//! Fetching file content with one `git show` per side per file forks two processes for
+//! every file in the diff. [`CatFile`] instead starts a single `git cat-file --batch`
+//! process on first use and sends it one object name per request, so a large range costs
+//! one fork instead of a thousand. The process is shared by the rayon workers; requests
+//! are serialized through a lock, which is cheap next to a fork.
+//!
+//! ## Protocol
+//!
+//! Each request is an object name such as `HEAD:src/lib.rs` or `:src/lib.rs` (the index)
+//! followed by a newline. git answers with either
+//!
+//! ```text
+//! <oid> <type> <size>\n<contents>\n
+//! ```
+//!
+//! or `<name> missing\n` when the object does not exist.
+//!
+//! If the process cannot be started or the protocol breaks down, the reader is marked as
+//! failed and callers fall back to `git show`.
+//!
+//! With the `native-git` feature, blobs are read in-process through libgit2 instead and
+//! no process is started at all, unless libgit2 cannot open the repository.
+
+use crate::exec::{self, Program, Runner};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Stdio};
+use std::sync::{Mutex, MutexGuard};
+
+/// A running `git cat-file --batch` process.
+struct BatchProcess {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl BatchProcess {
+    fn spawn(root: &Path) -> io::Result<Self> {
+        let mut child = exec::command(Program::Git)
+            .args(["cat-file", "--batch"])
+            .current_dir(root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(io::Error::other("git cat-file pipes unavailable"));
+        };
+
+        Ok(Self {
+            child,
+            stdin,
+            stdout: BufReader::new(stdout),
+        })
+    }
+
+    /// Sends one request and reads the response. `Ok(None)` means the object is missing
+    /// or is not a blob.
+    fn request(&mut self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        self.stdin.write_all(name.as_bytes())?;
+        self.stdin.write_all(b"\n")?;
+        self.stdin.flush()?;
+
+        let mut header = String::new();
+        if self.stdout.read_line(&mut header)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        let header = header.trim_end();
+        // The name is echoed back as-is and may contain spaces, so match on the suffix.
+        if header.ends_with(" missing") || header.ends_with(" ambiguous") {
+            return Ok(None);
+        }
+
+        let mut fields = header.split(' ');
+        let (Some(_oid), Some(kind), Some(size), None) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            return Err(invalid_header(header));
+        };
+        let size: usize = size.parse().map_err(|_| invalid_header(header))?;
+
+        // Contents are followed by a newline that is not part of the object.
+        let mut contents = vec![0; size + 1];
+        self.stdout.read_exact(&mut contents)?;
+        contents.pop();
+
+        Ok((kind == "blob").then_some(contents))
+    }
+}
+
+impl Drop for BatchProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn invalid_header(header: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unexpected git cat-file header: {header:?}"),
+    )
+}
+
+/// Lifecycle of the shared batch process.
+enum State {
+    /// Not needed yet; started on the first read.
+    Idle,
+    Running(BatchProcess),
+    /// Reading in-process through libgit2.
+    #[cfg(feature = "native-git")]
+    Native(git2::Repository),
+    /// Starting or talking to the process failed; reads fall back to `git show`.
+    Failed,
+}
+
+/// Reads git blobs through a shared `git cat-file --batch` process.
+pub struct CatFile {
+    /// Directory the process runs in; object paths resolve relative to the repo root.
+    root: PathBuf,
+    state: Mutex<State>,
+}
+
+impl CatFile {
+    /// Creates a reader for the repository at `root`. No process is started until the
+    /// first read.
+    pub fn new(root: PathBuf) -> Self {
+        Self {
+            root,
+            state: Mutex::new(State::Idle),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Opens the repository in-process if possible, or starts the batch process.
+    fn start(&self) -> State {
+        #[cfg(feature = "native-git")]
+        if let Ok(repo) = crate::native_git::open_at(&self.root) {
+            return State::Native(repo);
+        }
+
+        match BatchProcess::spawn(&self.root) {
+            Ok(process) => State::Running(process),
+            Err(_) => State::Failed,
+        }
+    }
+
+    /// Stops the batch process, if running. The next read starts a new one.
+    pub fn release(&self) {
+        let mut state = self.lock();
+        if !matches!(*state, State::Failed) {
+            *state = State::Idle;
+        }
+    }
+
+    /// Reads the blob named `name`, e.g. `HEAD:src/lib.rs` or `:src/lib.rs`.
+    ///
+    /// Returns `Ok(None)` if the object does not exist. Errors mean the batch process is
+    /// unusable (or the diff was cancelled) and the caller should fall back to `git show`.
+    pub fn read(&self, runner: &Runner, name: &str) -> io::Result<Option<Vec<u8>>> {
+        if runner.is_cancelled() {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "cancelled"));
+        }
+        // Requests are newline-delimited, so such names can't be expressed.
+        if name.contains('\n') {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+
+        let mut state = self.lock();
+        if matches!(*state, State::Idle) {
+            *state = self.start();
+        }
+        let process = match &mut *state {
+            State::Running(process) => process,
+            #[cfg(feature = "native-git")]
+            State::Native(repo) => {
+                return crate::native_git::blob(repo, name).map_err(io::Error::other);
+            }
+            State::Idle | State::Failed => {
+                return Err(io::Error::other("git cat-file unavailable"));
+            }
+        };
+
+        let result = process.request(name);
+        if result.is_err() {
+            // A half-read response leaves the stream out of sync; don't reuse it.
+            *state = State::Failed;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    /// Tests run from the crate root, which is a git checkout.
+    fn reader() -> CatFile {
+        CatFile::new(PathBuf::from("."))
+    }
+
+    fn git_show(name: &str) -> Vec<u8> {
+        Command::new("git")
+            .args(["show", name])
+            .output()
+            .unwrap()
+            .stdout
+    }
+
+    #[test]
+    fn reads_blobs_at_revisions() {
+        let blobs = reader();
+        let runner = Runner::default();
+
+        let contents = blobs.read(&runner, "HEAD:Cargo.toml").unwrap().unwrap();
+        assert_eq!(contents, git_show("HEAD:Cargo.toml"));
+
+        // The same process serves later requests.
+        let contents = blobs.read(&runner, "HEAD:src/lib.rs").unwrap().unwrap();
+        assert_eq!(contents, git_show("HEAD:src/lib.rs"));
+    }
+
+    #[test]
+    fn missing_objects_are_none() {
+        let blobs = reader();
+        let runner = Runner::default();
+
+        assert_eq!(blobs.read(&runner, "HEAD:no/such/file.rs").unwrap(), None);
+        assert!(blobs.read(&runner, "HEAD:Cargo.toml").unwrap().is_some());
+    }
+
+    #[test]
+    fn missing_names_with_spaces_keep_the_stream_in_sync() {
+        let blobs = reader();
+        let runner = Runner::default();
+
+        assert_eq!(blobs.read(&runner, "HEAD:no such file.rs").unwrap(), None);
+        assert!(blobs.read(&runner, "HEAD:Cargo.toml").unwrap().is_some());
+    }
+
+    #[test]
+    fn trees_are_not_blobs() {
+        assert_eq!(reader().read(&Runner::default(), "HEAD:src").unwrap(), None);
+    }
+
+    #[test]
+    fn newline_names_are_rejected() {
+        let err = reader().read(&Runner::default(), "HEAD:a\nb").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
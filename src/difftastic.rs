@@ -16,6 +16,12 @@
 //!
 //! The [`parse`] function handles both formats transparently.
 //!
+//! ## Options
+//!
+//! difftastic is usually run by git or jj rather than directly, so its command line options
+//! are set through the matching `DFT_*` environment variables (see [`DifftOptions`]). The
+//! same variables are set when it is run directly on file contents (see [`crate::direct`]).
+//!
 //! ## Example JSON Structure
 //!
 //! ```json
@@ -33,10 +39,126 @@
 //! }
 //! ```
 
-use serde::Deserialize;
+use mlua::prelude::*;
+use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
-#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+/// Most `--override` mappings difftastic reads from the environment: `DFT_OVERRIDE` and
+/// `DFT_OVERRIDE_1` to `DFT_OVERRIDE_9`.
+pub const MAX_OVERRIDES: usize = 10;
+
+/// difft command line options to pass through.
+///
+/// `None` and empty fields leave difftastic's own default (or the user's `DFT_*`
+/// environment) in place.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DifftOptions {
+    /// `--ignore-comments`: don't report changes to comments.
+    pub ignore_comments: bool,
+
+    /// `--strip-cr`: remove carriage returns before diffing.
+    pub strip_cr: Option<bool>,
+
+    /// `--tab-width`: the number of spaces a tab counts as. Also the width token regions'
+    /// display columns are computed with.
+    pub tab_width: Option<u32>,
+
+    /// `--override`: `GLOB:LANGUAGE` mappings that take precedence over language
+    /// detection, such as `*.h:C++`. At most [`MAX_OVERRIDES`].
+    pub overrides: Vec<String>,
+
+    /// `--byte-limit`: files larger than this many bytes are diffed as text.
+    pub byte_limit: Option<u64>,
+
+    /// `--graph-limit`: the size of the structural diff graph beyond which files are
+    /// diffed as text.
+    pub graph_limit: Option<u64>,
+
+    /// `--parse-error-limit`: files with more parse errors than this are diffed as text.
+    pub parse_error_limit: Option<u64>,
+}
+
+impl DifftOptions {
+    /// The `DFT_*` environment variables that set these options.
+    #[must_use]
+    pub fn env(&self) -> Vec<(String, String)> {
+        let mut env = Vec::new();
+        let mut set = |name: &str, value: String| env.push((name.to_string(), value));
+
+        if self.ignore_comments {
+            set("DFT_IGNORE_COMMENTS", "yes".to_string());
+        }
+        if let Some(strip_cr) = self.strip_cr {
+            set(
+                "DFT_STRIP_CR",
+                if strip_cr { "on" } else { "off" }.to_string(),
+            );
+        }
+        if let Some(tab_width) = self.tab_width {
+            set("DFT_TAB_WIDTH", tab_width.to_string());
+        }
+        for (i, mapping) in self.overrides.iter().enumerate() {
+            let name = match i {
+                0 => "DFT_OVERRIDE".to_string(),
+                i => format!("DFT_OVERRIDE_{i}"),
+            };
+            set(&name, mapping.clone());
+        }
+        if let Some(limit) = self.byte_limit {
+            set("DFT_BYTE_LIMIT", limit.to_string());
+        }
+        if let Some(limit) = self.graph_limit {
+            set("DFT_GRAPH_LIMIT", limit.to_string());
+        }
+        if let Some(limit) = self.parse_error_limit {
+            set("DFT_PARSE_ERROR_LIMIT", limit.to_string());
+        }
+        env
+    }
+}
+
+impl FromLua for DifftOptions {
+    fn from_lua(value: LuaValue, _: &Lua) -> LuaResult<Self> {
+        let opts = match value {
+            LuaValue::Nil => return Ok(Self::default()),
+            LuaValue::Table(opts) => opts,
+            other => {
+                return Err(LuaError::FromLuaConversionError {
+                    from: other.type_name(),
+                    to: "DifftOptions".to_string(),
+                    message: Some("expected a table of difft options".to_string()),
+                });
+            }
+        };
+
+        let overrides: Vec<String> = opts.get::<Option<_>>("override")?.unwrap_or_default();
+        if overrides.len() > MAX_OVERRIDES {
+            return Err(LuaError::RuntimeError(format!(
+                "At most {MAX_OVERRIDES} difft overrides are supported, got {}",
+                overrides.len()
+            )));
+        }
+        if let Some(bad) = overrides.iter().find(|mapping| !mapping.contains(':')) {
+            return Err(LuaError::RuntimeError(format!(
+                "Invalid difft override `{bad}`; expected \"GLOB:LANGUAGE\""
+            )));
+        }
+
+        Ok(Self {
+            ignore_comments: opts
+                .get::<Option<bool>>("ignore_comments")?
+                .unwrap_or(false),
+            strip_cr: opts.get("strip_cr")?,
+            tab_width: opts.get("tab_width")?,
+            overrides,
+            byte_limit: opts.get("byte_limit")?,
+            graph_limit: opts.get("graph_limit")?,
+            parse_error_limit: opts.get("parse_error_limit")?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum Status {
     Unchanged,
@@ -45,8 +167,21 @@ pub enum Status {
     Changed,
 }
 
+impl Status {
+    /// The status name as reported to Lua.
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Unchanged => "unchanged",
+            Self::Created => "created",
+            Self::Deleted => "deleted",
+            Self::Changed => "changed",
+        }
+    }
+}
+
 /// A file entry from difftastic's JSON output.
-#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct DifftFile {
     pub path: PathBuf,
     pub language: String,
@@ -67,7 +202,7 @@ pub struct DifftFile {
 pub type Chunk = Vec<DiffLine>;
 
 /// A single diff line entry, which may have content on the left side, right side, or both.
-#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct DiffLine {
     /// The left-hand side (old/before) content, if any.
     pub lhs: Option<Side>,
@@ -77,7 +212,7 @@ pub struct DiffLine {
 }
 
 /// One side (left or right) of a diff line.
-#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Side {
     /// The 0-indexed line number in the original file.
     pub line_number: u32,
@@ -99,7 +234,7 @@ pub struct Side {
 ///
 /// The `start` and `end` fields are byte offsets within the line, not
 /// character offsets. This is important for non-ASCII text.
-#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Change {
     /// Start byte offset within the line (0-indexed, inclusive).
     pub start: u32,
@@ -136,7 +271,7 @@ pub struct Change {
 /// - `Comment`: line or block comments
 /// - `Keyword`: language keywords (`fn`, `let`, `if`, etc.)
 /// - `TreeSitterError`: parser error spans reported by tree-sitter
-#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum Highlight {
     Delimiter,
@@ -148,6 +283,22 @@ pub enum Highlight {
     TreeSitterError,
 }
 
+impl Highlight {
+    /// The token class name as reported to Lua.
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Delimiter => "delimiter",
+            Self::Normal => "normal",
+            Self::String => "string",
+            Self::Type => "type",
+            Self::Comment => "comment",
+            Self::Keyword => "keyword",
+            Self::TreeSitterError => "tree_sitter_error",
+        }
+    }
+}
+
 /// Parses difftastic JSON output into a list of file entries.
 ///
 /// Handles two formats:
@@ -170,6 +321,39 @@ pub fn parse(json: &str) -> Result<Vec<DifftFile>, serde_json::Error> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn default_difft_options_set_nothing() {
+        assert!(DifftOptions::default().env().is_empty());
+    }
+
+    #[test]
+    fn difft_options_map_to_environment_variables() {
+        let options = DifftOptions {
+            ignore_comments: true,
+            strip_cr: Some(false),
+            tab_width: Some(4),
+            overrides: vec!["*.h:C++".to_string(), "Jenkinsfile:Groovy".to_string()],
+            byte_limit: Some(1_000_000),
+            graph_limit: None,
+            parse_error_limit: Some(0),
+        };
+        let env = options.env();
+        let get = |name: &str| {
+            env.iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        };
+
+        assert_eq!(get("DFT_IGNORE_COMMENTS"), Some("yes"));
+        assert_eq!(get("DFT_STRIP_CR"), Some("off"));
+        assert_eq!(get("DFT_TAB_WIDTH"), Some("4"));
+        assert_eq!(get("DFT_OVERRIDE"), Some("*.h:C++"));
+        assert_eq!(get("DFT_OVERRIDE_1"), Some("Jenkinsfile:Groovy"));
+        assert_eq!(get("DFT_BYTE_LIMIT"), Some("1000000"));
+        assert_eq!(get("DFT_GRAPH_LIMIT"), None);
+        assert_eq!(get("DFT_PARSE_ERROR_LIMIT"), Some("0"));
+    }
+
     #[test]
     fn parse_empty_array() {
         let json = "[]";
@@ -0,0 +1,207 @@
+//! Running difftastic directly on fetched file contents.
+//!
+//! By default git or jj run difftastic over the whole diff, and the contents shown next to
+//! its output are fetched separately, so every blob is read twice and the two reads can
+//! disagree about a working tree file that changed in between. With
+//! [`ProcessOptions::direct_difft`](crate::processor::ProcessOptions::direct_difft) the VCS
+//! only lists the changed files. Each file is then diffed on its own when it is
+//! materialized:
+//!
+//! 1. The old and new contents are fetched once, through the diff's content source.
+//! 2. They are written to a private temporary directory, under their repo-relative paths so
+//!    difftastic detects the language and applies `--override` globs as usual. A missing
+//!    side is passed as `/dev/null`.
+//! 3. `difft` runs on the two files with JSON output, and the same contents are processed
+//!    into rows.
+//!
+//! Files are materialized in parallel, so difftastic runs in parallel too. A failure is
+//! confined to its file: see [`diff_contents`].
+//!
+//! [`diff_files`] runs difftastic the same way on files that are already on disk, for
+//! diffs of two paths outside any VCS (see [`crate::paths`]).
+
+use crate::difftastic::{DifftFile, Status};
+use crate::error::DiffError;
+use crate::exec::{self, Program, Runner};
+use crate::processor::{self, DisplayFile, ProcessOptions};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::{fs, io};
+
+/// Suffix of the next temporary directory, unique within the process.
+static NEXT_DIR: AtomicU64 = AtomicU64::new(0);
+
+/// A temporary directory removed when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> io::Result<Self> {
+        let path = std::env::temp_dir().join(format!(
+            "difftastic-nvim-{}-{}",
+            std::process::id(),
+            NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&path)?;
+        Ok(Self(path))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Writes `contents` to `side/path` inside the directory and returns the file's path.
+    fn write(&self, side: &str, path: &Path, contents: &[u8]) -> io::Result<PathBuf> {
+        let file = self.path().join(side).join(contained(path));
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&file, contents)?;
+        Ok(file)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Keeps the normal components of `path`, so it can't point outside the directory it is
+/// joined to.
+fn contained(path: &Path) -> PathBuf {
+    let contained: PathBuf = path
+        .components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .collect();
+    if contained.as_os_str().is_empty() {
+        PathBuf::from("file")
+    } else {
+        contained
+    }
+}
+
+/// Runs difftastic on the `old` and `new` contents of a file and returns its entry,
+/// reported under `path`.
+///
+/// `old_path` and `new_path` name the two sides, which differ for renames. `None`
+/// contents are a side where the file doesn't exist. Cancellation and a missing
+/// `difft` fail the whole diff and are returned as is; the caller keeps any other error
+/// with the file it concerns.
+pub fn diff_contents(
+    runner: &Runner,
+    path: &Path,
+    old_path: &Path,
+    new_path: &Path,
+    old: Option<&str>,
+    new: Option<&str>,
+    options: &ProcessOptions,
+) -> Result<DifftFile, DiffError> {
+    let dir = TempDir::new()
+        .map_err(|e| DiffError::Failed(format!("Failed to create a temporary directory: {e}")))?;
+    let write_failed =
+        |e: io::Error| DiffError::Failed(format!("Failed to write {}: {e}", path.display()));
+    let old = old
+        .map(|old| dir.write("old", old_path, old.as_bytes()))
+        .transpose()
+        .map_err(write_failed)?;
+    let new = new
+        .map(|new| dir.write("new", new_path, new.as_bytes()))
+        .transpose()
+        .map_err(write_failed)?;
+
+    diff_files(runner, path, old.as_deref(), new.as_deref(), options)
+}
+
+/// Runs difftastic on two files on disk and returns its entry, reported under `path`.
+///
+/// `None` is a side where the file doesn't exist. Errors are as for [`diff_contents`].
+pub fn diff_files(
+    runner: &Runner,
+    path: &Path,
+    old: Option<&Path>,
+    new: Option<&Path>,
+    options: &ProcessOptions,
+) -> Result<DifftFile, DiffError> {
+    let missing = Path::new("/dev/null");
+    let output = runner.run(
+        exec::command(Program::Difft)
+            .arg(old.unwrap_or(missing))
+            .arg(new.unwrap_or(missing))
+            .envs(crate::difft_env(options)),
+    )?;
+
+    let mut file = crate::parse_difft_output(&output.stdout)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| {
+            DiffError::Parse(format!(
+                "difftastic reported nothing for {}",
+                path.display()
+            ))
+        })?;
+    file.path = path.to_path_buf();
+    Ok(file)
+}
+
+/// Processes a directly diffed file into rows.
+///
+/// Outside a VCS there are no line stats, so changed files count the lines of their hunks.
+pub fn process(
+    file: DifftFile,
+    old: Option<String>,
+    new: Option<String>,
+    options: &ProcessOptions,
+) -> DisplayFile {
+    let mut display = processor::process_file(
+        file,
+        crate::into_lines(old),
+        crate::into_lines(new),
+        None,
+        options,
+    );
+    if matches!(display.status, Status::Changed | Status::Unchanged) {
+        display.additions = display.hunks.iter().map(|hunk| hunk.additions).sum();
+        display.deletions = display.hunks.iter().map(|hunk| hunk.deletions).sum();
+    }
+    display
+}
+
+/// Returns `true` for errors that concern the whole diff rather than a single file.
+pub fn is_fatal(error: &DiffError) -> bool {
+    matches!(error, DiffError::Cancelled | DiffError::NotInstalled { .. })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_stay_inside_the_directory() {
+        assert_eq!(
+            contained(Path::new("src/lib.rs")),
+            PathBuf::from("src/lib.rs")
+        );
+        assert_eq!(
+            contained(Path::new("/etc/../passwd")),
+            PathBuf::from("etc/passwd")
+        );
+        assert_eq!(contained(Path::new("..")), PathBuf::from("file"));
+    }
+
+    #[test]
+    fn temp_dirs_hold_both_sides_and_are_removed() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.0.clone();
+
+        let old = dir
+            .write("old", Path::new("src/a.rs"), b"fn a() {}\n")
+            .unwrap();
+        let new = dir.write("new", Path::new("../a.rs"), b"").unwrap();
+        assert_eq!(old, root.join("old/src/a.rs"));
+        assert_eq!(new, root.join("new/a.rs"));
+        assert_eq!(fs::read(old).unwrap(), b"fn a() {}\n");
+
+        drop(dir);
+        assert!(!root.exists());
+    }
+}
@@ -0,0 +1,201 @@
+//! Errors reported by diff computations.
+//!
+//! Failures are classified so Lua can tell a missing executable from a bad revision from
+//! running outside a repository, and show an actionable message. An error converts into a
+//! table `{ kind = ..., message = ..., command = ..., exit_code = ..., stderr = ... }`;
+//! fields that don't apply to its kind are `nil`.
+
+use mlua::prelude::*;
+use std::fmt;
+
+/// A command that ran but exited unsuccessfully.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandFailure {
+    /// The command line, e.g. `git diff HEAD^..HEAD`.
+    pub command: String,
+    /// `None` if the command was killed by a signal or never started.
+    pub exit_code: Option<i32>,
+    pub stderr: String,
+}
+
+/// Why a diff could not be produced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffError {
+    /// The diff was cancelled before it finished.
+    Cancelled,
+
+    /// `program` is not installed or not on `PATH`. `failure` is the command that needed
+    /// it, which is not `program` itself when difftastic is run through git or jj.
+    NotInstalled {
+        program: String,
+        failure: CommandFailure,
+    },
+
+    /// The command was run outside a git or jj repository.
+    NotARepository(CommandFailure),
+
+    /// A revision, range or revset could not be resolved.
+    BadRevision(CommandFailure),
+
+    /// Any other command that exited unsuccessfully.
+    CommandFailed(CommandFailure),
+
+    /// difftastic's output could not be parsed.
+    Parse(String),
+
+    /// Anything else, such as a command that could not be started or a panic.
+    Failed(String),
+}
+
+impl DiffError {
+    /// Classifies an unsuccessful command by its stderr.
+    pub fn from_failure(failure: CommandFailure) -> Self {
+        let stderr = failure.stderr.to_lowercase();
+        let mentions = |patterns: &[&str]| patterns.iter().any(|p| stderr.contains(p));
+
+        if stderr.contains("difft")
+            && mentions(&["no such file or directory", "cannot run", "not found"])
+        {
+            Self::NotInstalled {
+                program: "difft".to_string(),
+                failure,
+            }
+        } else if mentions(&["not a git repository", "no jj repo"]) {
+            Self::NotARepository(failure)
+        } else if mentions(&[
+            "unknown revision",
+            "bad revision",
+            "ambiguous argument",
+            "invalid object name",
+            "not a valid object name",
+            "doesn't exist",
+            "failed to parse revset",
+            "resolved to more than one",
+        ]) {
+            Self::BadRevision(failure)
+        } else {
+            Self::CommandFailed(failure)
+        }
+    }
+
+    /// The error's kind as exposed to Lua.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Cancelled => "cancelled",
+            Self::NotInstalled { .. } => "not_installed",
+            Self::NotARepository(_) => "not_a_repository",
+            Self::BadRevision(_) => "bad_revision",
+            Self::CommandFailed(_) => "command_failed",
+            Self::Parse(_) => "parse",
+            Self::Failed(_) => "failed",
+        }
+    }
+
+    /// The failed command, for kinds caused by one.
+    pub fn failure(&self) -> Option<&CommandFailure> {
+        match self {
+            Self::NotInstalled { failure, .. }
+            | Self::NotARepository(failure)
+            | Self::BadRevision(failure)
+            | Self::CommandFailed(failure) => Some(failure),
+            Self::Cancelled | Self::Parse(_) | Self::Failed(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for DiffError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Cancelled => f.write_str("cancelled"),
+            Self::NotInstalled { program, .. } => {
+                write!(f, "`{program}` is not installed or not on PATH")
+            }
+            Self::NotARepository(failure) => {
+                write!(f, "Not inside a repository: {}", failure.stderr.trim())
+            }
+            Self::BadRevision(failure) => write!(f, "Bad revision: {}", failure.stderr.trim()),
+            Self::CommandFailed(failure) => {
+                write!(f, "`{}` failed: {}", failure.command, failure.stderr.trim())
+            }
+            Self::Parse(message) | Self::Failed(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for DiffError {}
+
+impl From<String> for DiffError {
+    fn from(message: String) -> Self {
+        Self::Failed(message)
+    }
+}
+
+impl IntoLua for DiffError {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let table = lua.create_table()?;
+        table.set("kind", self.kind())?;
+        table.set("message", self.to_string())?;
+        if let Self::NotInstalled { program, .. } = &self {
+            table.set("program", program.as_str())?;
+        }
+        if let Some(failure) = self.failure() {
+            table.set("command", failure.command.as_str())?;
+            table.set("exit_code", failure.exit_code)?;
+            table.set("stderr", failure.stderr.as_str())?;
+        }
+        Ok(LuaValue::Table(table))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failure(stderr: &str) -> CommandFailure {
+        CommandFailure {
+            command: "git diff HEAD".to_string(),
+            exit_code: Some(128),
+            stderr: stderr.to_string(),
+        }
+    }
+
+    #[test]
+    fn classifies_missing_difft() {
+        let err = DiffError::from_failure(failure(
+            "error: cannot run difft: No such file or directory\nfatal: external diff died",
+        ));
+        assert_eq!(err.kind(), "not_installed");
+        assert_eq!(err.to_string(), "`difft` is not installed or not on PATH");
+    }
+
+    #[test]
+    fn classifies_missing_repositories() {
+        let git = DiffError::from_failure(failure(
+            "fatal: not a git repository (or any of the parent directories): .git",
+        ));
+        let jj = DiffError::from_failure(failure("Error: There is no jj repo in \".\""));
+        assert_eq!(git.kind(), "not_a_repository");
+        assert_eq!(jj.kind(), "not_a_repository");
+    }
+
+    #[test]
+    fn classifies_bad_revisions() {
+        let git = DiffError::from_failure(failure(
+            "fatal: ambiguous argument 'nope': unknown revision or path not in the working tree.",
+        ));
+        let jj = DiffError::from_failure(failure("Error: Revision `nope` doesn't exist"));
+        assert_eq!(git.kind(), "bad_revision");
+        assert_eq!(jj.kind(), "bad_revision");
+    }
+
+    #[test]
+    fn other_failures_keep_the_command() {
+        let err = DiffError::from_failure(failure("fatal: something else\n"));
+        assert_eq!(err.kind(), "command_failed");
+        assert_eq!(
+            err.to_string(),
+            "`git diff HEAD` failed: fatal: something else"
+        );
+        assert_eq!(err.failure().unwrap().exit_code, Some(128));
+    }
+}
@@ -0,0 +1,375 @@
+//! Running external commands with cancellation support.
+//!
+//! Every `git`, `jj` and `difft` invocation goes through a [`Runner`], which carries the
+//! [`CancelToken`] of the diff it belongs to. When the token is cancelled, in-flight children
+//! are killed and new commands fail immediately, so a superseded diff stops spawning work.
+//!
+//! On Unix each child is started in its own process group and the whole group is killed.
+//! That also takes down processes the VCS spawned on our behalf, such as the `difft`
+//! instances run by `git diff` and `jj diff --tool difft`.
+//!
+//! Commands are created with [`command`], which applies the process-wide
+//! [`CommandConfig`]: the executable to run for each [`Program`], extra environment
+//! variables and the working directory. Lua sets it with `configure_commands`.
+
+use crate::error::{CommandFailure, DiffError};
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, RwLock};
+use std::thread;
+use std::time::Duration;
+
+/// Shortest and longest sleep between checks on a running child.
+const MIN_POLL_INTERVAL: Duration = Duration::from_micros(100);
+const MAX_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// An external program the diff runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Program {
+    Git,
+    Jj,
+    Difft,
+}
+
+impl Program {
+    /// The program's name, as looked up on `PATH` by default.
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Git => "git",
+            Self::Jj => "jj",
+            Self::Difft => "difft",
+        }
+    }
+}
+
+/// How external programs are run, as set by `configure_commands`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CommandConfig {
+    /// Executable for `git`; `None` looks it up on `PATH`.
+    pub git: Option<PathBuf>,
+    /// Executable for `jj`; `None` looks it up on `PATH`.
+    pub jj: Option<PathBuf>,
+    /// Executable for `difft`, also used when git or jj run difftastic; `None` looks it
+    /// up on `PATH`.
+    pub difft: Option<PathBuf>,
+    /// Extra environment variables for every command.
+    pub env: Vec<(String, String)>,
+    /// Directory commands run in, and where the repository is looked up; `None` uses
+    /// Neovim's current directory.
+    pub cwd: Option<PathBuf>,
+}
+
+impl CommandConfig {
+    /// The executable configured for `program`, if any.
+    #[must_use]
+    pub fn executable(&self, program: Program) -> Option<&Path> {
+        match program {
+            Program::Git => self.git.as_deref(),
+            Program::Jj => self.jj.as_deref(),
+            Program::Difft => self.difft.as_deref(),
+        }
+    }
+
+    /// Creates a command for `program` with this configuration's executable, environment
+    /// and working directory.
+    #[must_use]
+    pub fn command(&self, program: Program) -> Command {
+        let executable = self
+            .executable(program)
+            .map_or_else(|| OsStr::new(program.name()), Path::as_os_str);
+        let mut cmd = Command::new(executable);
+        cmd.envs(self.env.iter().map(|(key, value)| (key, value)));
+        if let Some(cwd) = &self.cwd {
+            cmd.current_dir(cwd);
+        }
+        cmd
+    }
+}
+
+static CONFIG: LazyLock<RwLock<CommandConfig>> =
+    LazyLock::new(|| RwLock::new(CommandConfig::default()));
+
+/// Replaces the command configuration. Commands already running are unaffected.
+pub fn configure(config: CommandConfig) {
+    *CONFIG.write().unwrap_or_else(|e| e.into_inner()) = config;
+}
+
+/// Returns the current command configuration.
+pub fn config() -> CommandConfig {
+    CONFIG.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// The directory commands run in: the configured `cwd`, or the current directory.
+pub fn working_dir() -> PathBuf {
+    config().cwd.unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// Creates a command for `program` with the configured executable, environment and
+/// working directory. Call sites may still override the directory or variables.
+pub fn command(program: Program) -> Command {
+    config().command(program)
+}
+
+/// A shared flag that requests cancellation of a diff.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// Requests cancellation. Cannot be undone.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns `true` once cancellation has been requested.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Runs external commands on behalf of a single diff.
+#[derive(Debug, Clone, Default)]
+pub struct Runner {
+    cancel: CancelToken,
+}
+
+impl Runner {
+    /// Creates a runner whose commands are cancelled through `cancel`.
+    pub fn new(cancel: CancelToken) -> Self {
+        Self { cancel }
+    }
+
+    /// Returns `true` once the diff this runner belongs to has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    /// Runs `cmd` to completion and collects its output, like [`Command::output`].
+    ///
+    /// Fails with [`io::ErrorKind::Interrupted`] if the diff is cancelled before or
+    /// while the command runs; the child is killed in the latter case.
+    pub fn output(&self, cmd: &mut Command) -> io::Result<Output> {
+        if self.is_cancelled() {
+            return Err(cancelled());
+        }
+
+        cmd.stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            cmd.process_group(0);
+        }
+
+        let mut child = cmd.spawn()?;
+        let mut stdout = child.stdout.take();
+        let mut stderr = child.stderr.take();
+
+        // Both pipes are drained on their own threads so a chatty child can't block
+        // on a full pipe while we wait for it to exit.
+        let (status, stdout, stderr) = thread::scope(|scope| {
+            let stdout = scope.spawn(move || read_to_end(stdout.as_mut()));
+            let stderr = scope.spawn(move || read_to_end(stderr.as_mut()));
+            let status = self.wait(&mut child);
+            (status, stdout.join(), stderr.join())
+        });
+
+        Ok(Output {
+            status: status?,
+            stdout: stdout.unwrap_or_default(),
+            stderr: stderr.unwrap_or_default(),
+        })
+    }
+
+    /// Runs `cmd` like [`Runner::output`], but fails unless it exits successfully.
+    ///
+    /// Failures are classified into [`DiffError`]s that carry the command line, exit code
+    /// and stderr. Commands cut short by cancellation fail with [`DiffError::Cancelled`].
+    pub fn run(&self, cmd: &mut Command) -> Result<Output, DiffError> {
+        let command = command_line(cmd);
+        let output = match self.output(cmd) {
+            Ok(output) => output,
+            Err(_) if self.is_cancelled() => return Err(DiffError::Cancelled),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(DiffError::NotInstalled {
+                    program: cmd.get_program().to_string_lossy().into_owned(),
+                    failure: CommandFailure {
+                        command,
+                        exit_code: None,
+                        stderr: String::new(),
+                    },
+                });
+            }
+            Err(e) => return Err(DiffError::Failed(format!("Failed to run `{command}`: {e}"))),
+        };
+
+        if output.status.success() {
+            return Ok(output);
+        }
+        // Killed children exit unsuccessfully like any other failure.
+        if self.is_cancelled() {
+            return Err(DiffError::Cancelled);
+        }
+        Err(DiffError::from_failure(CommandFailure {
+            command,
+            exit_code: output.status.code(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        }))
+    }
+
+    /// Waits for `child` to exit, killing it if the diff is cancelled in the meantime.
+    fn wait(&self, child: &mut Child) -> io::Result<ExitStatus> {
+        let mut interval = MIN_POLL_INTERVAL;
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Ok(status);
+            }
+            if self.is_cancelled() {
+                kill(child);
+                let _ = child.wait();
+                return Err(cancelled());
+            }
+            thread::sleep(interval);
+            interval = (interval * 2).min(MAX_POLL_INTERVAL);
+        }
+    }
+}
+
+/// Formats a command for error messages, e.g. `git diff HEAD^..HEAD`.
+fn command_line(cmd: &Command) -> String {
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|arg| arg.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Error returned for commands that were cut short by cancellation.
+fn cancelled() -> io::Error {
+    io::Error::new(io::ErrorKind::Interrupted, "cancelled")
+}
+
+fn read_to_end<R: Read>(reader: Option<&mut R>) -> Vec<u8> {
+    let mut buf = Vec::new();
+    if let Some(reader) = reader {
+        let _ = reader.read_to_end(&mut buf);
+    }
+    buf
+}
+
+/// Kills the child's whole process group.
+#[cfg(unix)]
+fn kill(child: &mut Child) {
+    match i32::try_from(child.id()) {
+        // SAFETY: `kill` has no memory-safety preconditions. The child was started as the
+        // leader of its own process group, so the negated pid addresses exactly that group.
+        Ok(pid) => unsafe {
+            libc::kill(-pid, libc::SIGKILL);
+        },
+        Err(_) => {
+            let _ = child.kill();
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn kill(child: &mut Child) {
+    let _ = child.kill();
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn commands_use_the_configured_executable_env_and_cwd() {
+        let config = CommandConfig {
+            jj: Some(PathBuf::from("/nix/store/abc-jj/bin/jj")),
+            env: vec![("DFT_COLOR".to_string(), "never".to_string())],
+            cwd: Some(PathBuf::from("/tmp")),
+            ..CommandConfig::default()
+        };
+        assert_eq!(
+            config.executable(Program::Jj),
+            Some(Path::new("/nix/store/abc-jj/bin/jj"))
+        );
+        assert_eq!(config.executable(Program::Git), None);
+
+        let jj = config.command(Program::Jj);
+        let git = config.command(Program::Git);
+        assert_eq!(jj.get_program(), "/nix/store/abc-jj/bin/jj");
+        assert_eq!(git.get_program(), "git");
+        assert_eq!(jj.get_current_dir(), Some(Path::new("/tmp")));
+        assert!(
+            jj.get_envs()
+                .any(|(key, value)| key == "DFT_COLOR" && value == Some(OsStr::new("never")))
+        );
+    }
+
+    #[test]
+    fn output_collects_stdout_and_status() {
+        let output = Runner::default()
+            .output(Command::new("sh").args(["-c", "echo hello; echo oops >&2"]))
+            .unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"hello\n");
+        assert_eq!(output.stderr, b"oops\n");
+    }
+
+    #[test]
+    fn output_fails_immediately_when_already_cancelled() {
+        let token = CancelToken::default();
+        token.cancel();
+
+        let err = Runner::new(token)
+            .output(&mut Command::new("true"))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+    }
+
+    #[test]
+    fn run_reports_failures_with_their_command() {
+        let err = Runner::default()
+            .run(Command::new("sh").args(["-c", "echo 'fatal: boom' >&2; exit 3"]))
+            .unwrap_err();
+
+        let failure = err.failure().unwrap();
+        assert_eq!(err.kind(), "command_failed");
+        assert_eq!(failure.command, "sh -c echo 'fatal: boom' >&2; exit 3");
+        assert_eq!(failure.exit_code, Some(3));
+        assert_eq!(failure.stderr, "fatal: boom\n");
+    }
+
+    #[test]
+    fn run_reports_missing_programs() {
+        let err = Runner::default()
+            .run(&mut Command::new("difftastic-nvim-no-such-program"))
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            DiffError::NotInstalled { ref program, .. } if program == "difftastic-nvim-no-such-program"
+        ));
+    }
+
+    #[test]
+    fn cancel_kills_running_child() {
+        let token = CancelToken::default();
+        let runner = Runner::new(token.clone());
+
+        let started = Instant::now();
+        let handle = thread::spawn(move || runner.output(Command::new("sleep").arg("30")));
+        thread::sleep(Duration::from_millis(50));
+        token.cancel();
+
+        let err = handle.join().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,531 @@
+//! Background diff jobs.
+//!
+//! Running difftastic over a large range spawns many `git`/`jj`/`difft` processes and
+//! fans the file processing out over rayon. Doing that inside a Lua call blocks Neovim's
+//! UI thread until everything is done, so the `*_async` exports run the work on a
+//! background thread instead and hand a [`DiffJob`] back to Lua immediately.
+//!
+//! ## Completion
+//!
+//! Lua can observe completion in two ways:
+//!
+//! - Polling: `job:poll()` returns `nil` while the job is running, the result table once it
+//!   has finished, or `nil, err` if it failed, where `err` is an error table (see
+//!   [`crate::error`]). For a lazy job the result table carries a
+//!   `session` id and file metadata only; see [`crate::session`].
+//! - Wakeup: on Unix, `job:wakeup_fd()` hands out the read end of a pipe that becomes readable
+//!   when the job finishes (or, in streaming mode, when new events are queued). It can be opened
+//!   with `vim.uv.new_pipe():open(fd)` so the callback runs on the libuv loop without a timer.
+//!
+//! ## Streaming
+//!
+//! A job started with [`DiffJob::spawn_streaming`] queues a [`JobEvent::File`] for every file
+//! as soon as it has been processed, followed by a single [`JobEvent::Done`] with totals.
+//! `job:events()` drains the queued events. Files delivered as events are not part of the
+//! `poll` result, which then only reports completion or failure.
+//!
+//! ## Cancellation
+//!
+//! `job:cancel()` kills the job's in-flight `git`/`jj`/`difft` processes and stops the
+//! remaining file processing. The job then finishes with [`DiffError::Cancelled`], which
+//! `poll` reports as an error of kind `"cancelled"` rather than as a failure.
+//!
+//! Lua tables can only be created on the Lua thread, so the conversion of the processed files
+//! into Lua values happens in `poll`/`events`, not on the worker thread.
+
+use crate::error::DiffError;
+use crate::exec::CancelToken;
+use crate::processor::DisplayFile;
+use crate::session::DiffSession;
+use mlua::prelude::*;
+use std::io::{self, PipeReader, PipeWriter, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+
+/// What a successful diff computation produced.
+pub enum JobOutput {
+    /// Fully processed files, in difftastic's output order.
+    Files(Vec<DisplayFile>),
+    /// File metadata only; rows are materialized per file through the session.
+    Session(Arc<DiffSession>),
+}
+
+/// Outcome of a diff computation.
+pub type JobResult = Result<JobOutput, DiffError>;
+
+/// An event queued by a streaming job.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobEvent {
+    /// A file finished processing.
+    File {
+        /// Position of the file in difftastic's output (0-indexed).
+        index: usize,
+        file: Box<DisplayFile>,
+    },
+    /// All files have been emitted.
+    Done {
+        files: usize,
+        additions: u64,
+        deletions: u64,
+    },
+}
+
+/// Lifecycle of a job's result.
+enum JobState {
+    /// The worker thread is still computing.
+    Running,
+    /// The worker finished; the result has not been handed out yet.
+    Finished(JobResult),
+    /// The result has already been handed out to a caller.
+    Taken,
+}
+
+/// Mutable job state, guarded by a single lock.
+struct Inner {
+    state: JobState,
+    events: Vec<JobEvent>,
+
+    /// Whether a wakeup byte is pending that Lua has not drained events for yet.
+    ///
+    /// Coalesces wakeups so a fast stream of files writes at most one byte between
+    /// drains, which keeps the pipe from filling up if Lua falls behind.
+    signalled: bool,
+
+    /// Running totals for the final [`JobEvent::Done`].
+    totals: (usize, u64, u64),
+}
+
+/// State shared between a [`DiffJob`] handle and its worker thread.
+struct Shared {
+    inner: Mutex<Inner>,
+    finished: Condvar,
+    wakeup: Mutex<Option<PipeWriter>>,
+    cancel: CancelToken,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Writes a wakeup byte, if the pipe is still open.
+    fn signal(&self) {
+        if let Some(writer) = self
+            .wakeup
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_mut()
+        {
+            let _ = writer.write_all(&[1]);
+        }
+    }
+
+    /// Queues an event and signals the wakeup pipe unless a signal is already pending.
+    fn push_event(&self, event: JobEvent) {
+        let needs_signal = {
+            let mut inner = self.lock();
+            inner.events.push(event);
+            !std::mem::replace(&mut inner.signalled, true)
+        };
+        if needs_signal {
+            self.signal();
+        }
+    }
+
+    /// Stores the job result, wakes blocked waiters and closes the wakeup pipe.
+    fn finish(&self, result: JobResult) {
+        self.lock().state = JobState::Finished(result);
+        self.finished.notify_all();
+
+        // A final byte makes the read end readable; dropping the writer then closes it.
+        let writer = self.wakeup.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if let Some(mut writer) = writer {
+            let _ = writer.write_all(&[1]);
+        }
+    }
+}
+
+/// Emits processed files from a streaming job's worker.
+///
+/// Shared across the rayon workers processing the files.
+pub struct JobSink {
+    shared: Arc<Shared>,
+}
+
+impl JobSink {
+    /// The token the job's work should honour.
+    pub fn cancel_token(&self) -> &CancelToken {
+        &self.shared.cancel
+    }
+
+    /// Queues a processed file for Lua.
+    pub fn emit_file(&self, index: usize, file: DisplayFile) {
+        {
+            let mut inner = self.shared.lock();
+            inner.totals.0 += 1;
+            inner.totals.1 += u64::from(file.additions);
+            inner.totals.2 += u64::from(file.deletions);
+        }
+        self.shared.push_event(JobEvent::File {
+            index,
+            file: Box::new(file),
+        });
+    }
+}
+
+/// Handle to a diff computation running on a background thread.
+///
+/// Exposed to Lua as userdata with `is_done`, `poll`, `wait`, `events`, `cancel` and
+/// `wakeup_fd` methods.
+pub struct DiffJob {
+    shared: Arc<Shared>,
+
+    /// Read end of the wakeup pipe, until it is handed out via `wakeup_fd`.
+    wakeup: Option<PipeReader>,
+}
+
+impl DiffJob {
+    /// Spawns `work` on a new thread and returns a handle to it.
+    ///
+    /// `work` receives the job's cancellation token. A panic inside `work` is reported as
+    /// a failed job rather than leaving the job running forever.
+    pub fn spawn<F>(work: F) -> io::Result<Self>
+    where
+        F: FnOnce(&CancelToken) -> JobResult + Send + 'static,
+    {
+        Self::start(move |sink| work(sink.cancel_token()))
+    }
+
+    /// Spawns a streaming `work` on a new thread and returns a handle to it.
+    ///
+    /// `work` delivers files through the [`JobSink`]; once it returns successfully a
+    /// [`JobEvent::Done`] with the totals of all emitted files is queued.
+    pub fn spawn_streaming<F>(work: F) -> io::Result<Self>
+    where
+        F: FnOnce(&JobSink) -> Result<(), DiffError> + Send + 'static,
+    {
+        Self::start(move |sink| {
+            work(sink)?;
+            let (files, additions, deletions) = sink.shared.lock().totals;
+            sink.shared.push_event(JobEvent::Done {
+                files,
+                additions,
+                deletions,
+            });
+            Ok(JobOutput::Files(Vec::new()))
+        })
+    }
+
+    fn start<F>(work: F) -> io::Result<Self>
+    where
+        F: FnOnce(&JobSink) -> JobResult + Send + 'static,
+    {
+        // The wakeup pipe is best-effort: without it Lua can still poll.
+        let (reader, writer) = match io::pipe() {
+            Ok((reader, writer)) => (Some(reader), Some(writer)),
+            Err(_) => (None, None),
+        };
+
+        let shared = Arc::new(Shared {
+            inner: Mutex::new(Inner {
+                state: JobState::Running,
+                events: Vec::new(),
+                signalled: false,
+                totals: (0, 0, 0),
+            }),
+            finished: Condvar::new(),
+            wakeup: Mutex::new(writer),
+            cancel: CancelToken::default(),
+        });
+
+        let sink = JobSink {
+            shared: Arc::clone(&shared),
+        };
+        thread::Builder::new()
+            .name("difftastic-diff".to_string())
+            .spawn(move || {
+                let result = panic::catch_unwind(AssertUnwindSafe(|| work(&sink)))
+                    .unwrap_or_else(|_| Err(DiffError::Failed("diff job panicked".to_string())));
+                sink.shared.finish(result);
+            })?;
+
+        Ok(Self {
+            shared,
+            wakeup: reader,
+        })
+    }
+
+    /// Requests cancellation of the job. Has no effect once the job has finished.
+    pub fn cancel(&self) {
+        self.shared.cancel.cancel();
+    }
+
+    /// Returns `true` once the worker has finished, whether or not the result was taken.
+    pub fn is_done(&self) -> bool {
+        !matches!(self.shared.lock().state, JobState::Running)
+    }
+
+    /// Takes the result if the job has finished, or returns `None` while it is running.
+    ///
+    /// The result is moved out of the job, so it can only be taken once.
+    pub fn try_take(&self) -> Option<Result<JobResult, &'static str>> {
+        let mut inner = self.shared.lock();
+        match std::mem::replace(&mut inner.state, JobState::Taken) {
+            JobState::Running => {
+                inner.state = JobState::Running;
+                None
+            }
+            JobState::Finished(result) => Some(Ok(result)),
+            JobState::Taken => Some(Err("diff job result has already been taken")),
+        }
+    }
+
+    /// Blocks until the job has finished, then takes its result.
+    pub fn wait(&self) -> Result<JobResult, &'static str> {
+        let mut inner = self.shared.lock();
+        while matches!(inner.state, JobState::Running) {
+            inner = self
+                .shared
+                .finished
+                .wait(inner)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+        match std::mem::replace(&mut inner.state, JobState::Taken) {
+            JobState::Finished(result) => Ok(result),
+            JobState::Running | JobState::Taken => Err("diff job result has already been taken"),
+        }
+    }
+
+    /// Drains the events queued since the last call.
+    pub fn drain_events(&self) -> Vec<JobEvent> {
+        let mut inner = self.shared.lock();
+        inner.signalled = false;
+        std::mem::take(&mut inner.events)
+    }
+
+    /// Hands out the raw read end of the wakeup pipe, transferring ownership to the caller.
+    ///
+    /// Returns `None` if the pipe could not be created or was already handed out.
+    #[cfg(unix)]
+    pub fn take_wakeup_fd(&mut self) -> Option<i32> {
+        use std::os::fd::IntoRawFd;
+        self.wakeup.take().map(IntoRawFd::into_raw_fd)
+    }
+
+    /// Wakeup pipes are only handed out on Unix; other platforms poll instead.
+    #[cfg(not(unix))]
+    pub fn take_wakeup_fd(&mut self) -> Option<i32> {
+        None
+    }
+}
+
+/// Converts a taken job result into Lua's `result` / `nil, err` convention.
+fn into_lua_result(
+    lua: &Lua,
+    result: JobResult,
+) -> LuaResult<(Option<LuaTable>, Option<DiffError>)> {
+    match result {
+        Ok(JobOutput::Files(files)) => Ok((Some(crate::files_to_lua(lua, files)?), None)),
+        Ok(JobOutput::Session(session)) => {
+            Ok((Some(crate::session::session_to_lua(lua, session)?), None))
+        }
+        Err(err) => Ok((None, Some(err))),
+    }
+}
+
+impl IntoLua for JobEvent {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let table = lua.create_table()?;
+        match self {
+            JobEvent::File { index, file } => {
+                table.set("type", "file")?;
+                table.set("index", index + 1)?;
+                table.set("file", (*file).into_lua(lua)?)?;
+            }
+            JobEvent::Done {
+                files,
+                additions,
+                deletions,
+            } => {
+                table.set("type", "done")?;
+                table.set("files", files)?;
+                table.set("additions", additions)?;
+                table.set("deletions", deletions)?;
+            }
+        }
+        Ok(LuaValue::Table(table))
+    }
+}
+
+impl LuaUserData for DiffJob {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("is_done", |_, this, ()| Ok(this.is_done()));
+
+        methods.add_method("poll", |lua, this, ()| match this.try_take() {
+            None => Ok((None, None)),
+            Some(Ok(result)) => into_lua_result(lua, result),
+            Some(Err(msg)) => Err(LuaError::RuntimeError(msg.to_string())),
+        });
+
+        methods.add_method("wait", |lua, this, ()| match this.wait() {
+            Ok(result) => into_lua_result(lua, result),
+            Err(msg) => Err(LuaError::RuntimeError(msg.to_string())),
+        });
+
+        methods.add_method("events", |lua, this, ()| {
+            let events = this.drain_events();
+            let table = lua.create_table_with_capacity(events.len(), 0)?;
+            for (i, event) in events.into_iter().enumerate() {
+                table.set(i + 1, event.into_lua(lua)?)?;
+            }
+            Ok(table)
+        });
+
+        methods.add_method("cancel", |_, this, ()| {
+            this.cancel();
+            Ok(())
+        });
+
+        methods.add_method_mut("wakeup_fd", |_, this, ()| Ok(this.take_wakeup_fd()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::difftastic::Status;
+    use std::io::Read;
+    use std::sync::mpsc;
+
+    fn display_file(path: &str, additions: u32, deletions: u32) -> DisplayFile {
+        DisplayFile {
+            path: path.into(),
+            moved_from: None,
+            language: "Rust".into(),
+            status: Status::Changed,
+            additions,
+            deletions,
+            rows: Vec::new(),
+            hunk_starts: Vec::new(),
+            aligned_lines: Vec::new(),
+            unified: None,
+            folds: Vec::new(),
+            hunks: Vec::new(),
+            moves: Vec::new(),
+            whitespace_only: false,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn wait_returns_result() {
+        let job = DiffJob::spawn(|_| Ok(JobOutput::Files(Vec::new()))).unwrap();
+        let result = job.wait().unwrap();
+        assert!(matches!(result, Ok(JobOutput::Files(files)) if files.is_empty()));
+        assert!(job.is_done());
+    }
+
+    #[test]
+    fn try_take_is_none_while_running() {
+        let (tx, rx) = mpsc::channel::<()>();
+        let job = DiffJob::spawn(move |_| {
+            rx.recv().unwrap();
+            Err(DiffError::Failed("boom".to_string()))
+        })
+        .unwrap();
+
+        assert!(job.try_take().is_none());
+        assert!(!job.is_done());
+
+        tx.send(()).unwrap();
+        assert_eq!(
+            job.wait().unwrap().err(),
+            Some(DiffError::Failed("boom".to_string()))
+        );
+    }
+
+    #[test]
+    fn result_can_only_be_taken_once() {
+        let job = DiffJob::spawn(|_| Ok(JobOutput::Files(Vec::new()))).unwrap();
+        assert!(job.wait().is_ok());
+        assert!(job.wait().is_err());
+        assert!(matches!(job.try_take(), Some(Err(_))));
+    }
+
+    #[test]
+    fn panic_is_reported_as_error() {
+        let job = DiffJob::spawn(|_| panic!("worker exploded")).unwrap();
+        assert_eq!(
+            job.wait().unwrap().err(),
+            Some(DiffError::Failed("diff job panicked".to_string()))
+        );
+    }
+
+    #[test]
+    fn wakeup_pipe_becomes_readable_on_finish() {
+        let mut job = DiffJob::spawn(|_| Ok(JobOutput::Files(Vec::new()))).unwrap();
+        let mut reader = job.wakeup.take().unwrap();
+        let mut buf = [0u8; 1];
+        reader.read_exact(&mut buf).unwrap();
+        assert!(job.is_done());
+    }
+
+    #[test]
+    fn streaming_emits_files_then_done_with_totals() {
+        let job = DiffJob::spawn_streaming(|sink| {
+            sink.emit_file(1, display_file("b.rs", 1, 2));
+            sink.emit_file(0, display_file("a.rs", 3, 0));
+            Ok(())
+        })
+        .unwrap();
+
+        assert!(matches!(job.wait().unwrap(), Ok(JobOutput::Files(files)) if files.is_empty()));
+
+        let events = job.drain_events();
+        assert_eq!(events.len(), 3);
+        assert!(
+            matches!(&events[0], JobEvent::File { index: 1, file } if file.path.ends_with("b.rs"))
+        );
+        assert!(matches!(&events[1], JobEvent::File { index: 0, .. }));
+        assert_eq!(
+            events[2],
+            JobEvent::Done {
+                files: 2,
+                additions: 4,
+                deletions: 2,
+            }
+        );
+        assert!(job.drain_events().is_empty());
+    }
+
+    #[test]
+    fn streaming_failure_skips_done_event() {
+        let job =
+            DiffJob::spawn_streaming(|_| Err(DiffError::Failed("jj failed".to_string()))).unwrap();
+
+        assert_eq!(
+            job.wait().unwrap().err(),
+            Some(DiffError::Failed("jj failed".to_string()))
+        );
+        assert!(job.drain_events().is_empty());
+    }
+
+    #[test]
+    fn cancel_is_visible_to_work() {
+        let (tx, rx) = mpsc::channel::<()>();
+        let job = DiffJob::spawn(move |cancel| {
+            rx.recv().unwrap();
+            if cancel.is_cancelled() {
+                Err(DiffError::Cancelled)
+            } else {
+                Ok(JobOutput::Files(Vec::new()))
+            }
+        })
+        .unwrap();
+
+        job.cancel();
+        tx.send(()).unwrap();
+        assert_eq!(job.wait().unwrap().err(), Some(DiffError::Cancelled));
+    }
+}
@@ -8,10 +8,23 @@
 //!
 //! ## Architecture
 //!
-//! The crate is organized into three modules:
+//! The crate is organized into the following modules:
 //!
 //! - `difftastic` - Types and parsing for difftastic's JSON output format
+//! - `cache` - Caching of diffs between immutable commits
+//! - `cat_file` - Batched git blob reads through `git cat-file --batch`
 //! - `processor` - Transforms parsed data into aligned side-by-side display rows
+//! - `scope` - Enclosing definition names for hunk headers
+//! - `job` - Background diff jobs for the non-blocking `*_async` exports
+//! - `moves` - Detection of code moved within or across files
+//! - `direct` - Running difftastic directly on fetched file contents
+//! - `paths` - Diffs of two files or directories outside any VCS
+//! - `texts` - Diffs of in-memory texts, such as unsaved buffers
+//! - `exec` - Running `git`/`jj`/`difft` subprocesses with cancellation
+//! - `error` - Errors reported by diff computations
+//! - `native_git` - In-process git access through libgit2 (`native-git` feature)
+//! - `session` - Diffs whose rows are materialized per file on demand
+//! - `watch` - Watching the working copy for changes to the unstaged diff
 //! - `lib` (this module) - Lua bindings and VCS integration
 //!
 //! ## Usage from Lua
@@ -29,6 +42,8 @@
 //! local result = difft.run_diff("main..feature", "git")
 //! ```
 //!
+//! The full Lua API is documented in `:help difftastic-nvim-api`.
+//!
 //! ## Environment Variables
 //!
 //! This crate sets the following environment variables when invoking difftastic:
@@ -40,10 +55,29 @@ use mlua::prelude::*;
 use rayon::prelude::*;
 use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::sync::Mutex;
 
+mod cache;
+mod cat_file;
 mod difftastic;
+mod direct;
+mod error;
+mod exec;
+mod job;
+mod moves;
+#[cfg(feature = "native-git")]
+mod native_git;
+mod paths;
 mod processor;
+mod scope;
+mod session;
+mod texts;
+mod watch;
+
+use cat_file::CatFile;
+use error::DiffError;
+use exec::{Program, Runner};
+use processor::ProcessOptions;
 
 /// Splits file content into individual lines, or empty vector if `None`.
 #[inline]
@@ -58,56 +92,63 @@ fn into_lines(content: Option<String>) -> Vec<String> {
 ///
 /// Paths from difftastic are relative to the repo root, so the command
 /// must run from the repo root for `jj file show` to resolve them correctly.
-fn jj_file_content(root: &Path, revset: &str, path: &Path) -> Option<String> {
-    Command::new("jj")
-        .args(["file", "show", "-r", revset])
-        .arg(path)
-        .current_dir(root)
-        .output()
+fn jj_file_content(runner: &Runner, root: &Path, revset: &str, path: &Path) -> Option<String> {
+    runner
+        .output(
+            exec::command(Program::Jj)
+                .args(["file", "show", "-r", revset])
+                .arg(path)
+                .current_dir(root),
+        )
         .ok()
         .filter(|output| output.status.success())
         .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
 }
 
-/// Fetches file content from git at a specific commit via `git show`.
-/// Returns `None` if the command fails or the file doesn't exist.
-fn git_file_content(commit: &str, path: &Path) -> Option<String> {
-    Command::new("git")
-        .arg("show")
-        .arg(format!("{commit}:{}", path.display()))
-        .output()
-        .ok()
-        .filter(|output| output.status.success())
-        .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+/// Fetches a git blob through the shared `git cat-file --batch` process, falling back to
+/// `git show` if the batch process is unavailable.
+/// Returns `None` if the object doesn't exist.
+fn git_blob_content(runner: &Runner, blobs: &CatFile, name: &str) -> Option<String> {
+    match blobs.read(runner, name) {
+        Ok(contents) => contents.map(|bytes| String::from_utf8_lossy(&bytes).into_owned()),
+        Err(_) => runner
+            .output(exec::command(Program::Git).arg("show").arg(name))
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).into_owned()),
+    }
+}
+
+/// Fetches file content from git at a specific commit.
+/// Returns `None` if the file doesn't exist at that commit.
+fn git_file_content(runner: &Runner, blobs: &CatFile, commit: &str, path: &Path) -> Option<String> {
+    git_blob_content(runner, blobs, &format!("{commit}:{}", path.display()))
 }
 
 /// Fetches file content from git index (staged version).
-/// Returns `None` if the command fails or the file doesn't exist in the index.
-fn git_index_content(path: &Path) -> Option<String> {
-    Command::new("git")
-        .arg("show")
-        .arg(format!(":{}", path.display()))
-        .output()
-        .ok()
-        .filter(|output| output.status.success())
-        .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+/// Returns `None` if the file doesn't exist in the index.
+fn git_index_content(runner: &Runner, blobs: &CatFile, path: &Path) -> Option<String> {
+    git_blob_content(runner, blobs, &format!(":{}", path.display()))
 }
 
 /// Gets the git repository root directory.
-fn git_root() -> Option<PathBuf> {
-    Command::new("git")
-        .args(["rev-parse", "--show-toplevel"])
-        .output()
+fn git_root(runner: &Runner) -> Option<PathBuf> {
+    #[cfg(feature = "native-git")]
+    if let Ok(root) = native_git::open().and_then(|repo| native_git::root(&repo)) {
+        return Some(root);
+    }
+
+    runner
+        .output(exec::command(Program::Git).args(["rev-parse", "--show-toplevel"]))
         .ok()
         .filter(|o| o.status.success())
         .map(|o| PathBuf::from(String::from_utf8_lossy(&o.stdout).trim()))
 }
 
 /// Gets the jj repository root directory.
-fn jj_root() -> Option<PathBuf> {
-    Command::new("jj")
-        .args(["root"])
-        .output()
+fn jj_root(runner: &Runner) -> Option<PathBuf> {
+    runner
+        .output(exec::command(Program::Jj).args(["root"]))
         .ok()
         .filter(|o| o.status.success())
         .map(|o| PathBuf::from(String::from_utf8_lossy(&o.stdout).trim()))
@@ -123,11 +164,24 @@ type FileStats = HashMap<PathBuf, (u32, u32)>;
 /// - `&["HEAD^..HEAD"]` for a commit range
 /// - `&[]` for unstaged changes (working tree vs index)
 /// - `&["--cached"]` for staged changes (index vs HEAD)
-fn git_diff_stats(extra_args: &[&str]) -> FileStats {
+///
+/// With `ignore_whitespace`, lines that changed only in whitespace are not counted.
+fn git_diff_stats(runner: &Runner, extra_args: &[&str], ignore_whitespace: bool) -> FileStats {
+    #[cfg(feature = "native-git")]
+    if !ignore_whitespace
+        && let Some(spec) = native_git::DiffSpec::from_args(extra_args)
+        && let Ok(stats) = native_git::open().and_then(|repo| native_git::diff_stats(&repo, &spec))
+    {
+        return stats;
+    }
+
     let mut args = vec!["diff", "--numstat"];
+    if ignore_whitespace {
+        args.push("--ignore-all-space");
+    }
     args.extend(extra_args);
 
-    let output = Command::new("git").args(&args).output().ok();
+    let output = runner.output(exec::command(Program::Git).args(&args)).ok();
 
     let Some(output) = output.filter(|o| o.status.success()) else {
         return HashMap::new();
@@ -160,17 +214,16 @@ fn parse_jj_range(revset: &str) -> Option<(String, String)> {
     Some((old.to_string(), new.to_string()))
 }
 
-fn jj_git_commits(revset: &str) -> Option<Vec<String>> {
-    let output = Command::new("jj")
-        .args([
+fn jj_git_commits(runner: &Runner, revset: &str) -> Option<Vec<String>> {
+    let output = runner
+        .output(exec::command(Program::Jj).args([
             "log",
             "-r",
             revset,
             "--no-graph",
             "-T",
             "commit_id ++ \"\n\"",
-        ])
-        .output()
+        ]))
         .ok()?;
 
     if !output.status.success() {
@@ -205,58 +258,157 @@ fn git_range_from_jj_commits(old_revs: &[String], new_revs: &[String]) -> Option
     Some(format!("{}..{}", old_revs[0], new_revs[0]))
 }
 
-fn jj_diff_git_range(mode: &DiffMode) -> Option<String> {
+fn jj_diff_git_range(runner: &Runner, mode: &DiffMode) -> Option<String> {
     let revset = jj_diff_revset(mode);
-    let old_revs = jj_git_commits(&format!("roots({revset})-"))?;
-    let new_revs = jj_git_commits(&format!("heads({revset})"))?;
+    let old_revs = jj_git_commits(runner, &format!("roots({revset})-"))?;
+    let new_revs = jj_git_commits(runner, &format!("heads({revset})"))?;
 
     git_range_from_jj_commits(&old_revs, &new_revs)
 }
 
-fn jj_diff_stats(mode: &DiffMode) -> FileStats {
-    let Some(git_range) = jj_diff_git_range(mode) else {
+fn jj_diff_stats(
+    runner: &Runner,
+    mode: &DiffMode,
+    pathspecs: &[String],
+    ignore_whitespace: bool,
+) -> FileStats {
+    let Some(git_range) = jj_diff_git_range(runner, mode) else {
         return HashMap::new();
     };
 
-    git_diff_stats(&[git_range.as_str()])
+    git_diff_stats(
+        runner,
+        &with_git_paths(vec![git_range.as_str()], pathspecs),
+        ignore_whitespace,
+    )
 }
 
-/// Runs difftastic via jj and parses the JSON output.
-/// Executes `jj diff -r <revset> --tool difft` with JSON output mode enabled.
-fn run_jj_diff(revset: &str) -> Result<Vec<difftastic::DifftFile>, String> {
-    let output = Command::new("jj")
-        .args(["diff", "-r", revset, "--tool", "difft"])
-        .env("DFT_DISPLAY", "json")
-        .env("DFT_UNSTABLE", "yes")
-        .output()
-        .map_err(|e| format!("Failed to run jj: {e}"))?;
+/// Converts repo-root-relative paths into git pathspecs that match exactly those files,
+/// regardless of the CWD or glob characters in their names.
+fn git_pathspecs(paths: &[PathBuf]) -> Vec<String> {
+    paths
+        .iter()
+        .map(|path| format!(":(top,literal){}", path.display()))
+        .collect()
+}
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(format!("jj command failed: {stderr}"));
+/// Appends `-- <pathspecs>` to git diff arguments. An empty list leaves them unchanged.
+fn with_git_paths<'a>(mut args: Vec<&'a str>, pathspecs: &'a [String]) -> Vec<&'a str> {
+    if !pathspecs.is_empty() {
+        args.push("--");
+        args.extend(pathspecs.iter().map(String::as_str));
     }
+    args
+}
 
-    difftastic::parse(&String::from_utf8_lossy(&output.stdout))
-        .map_err(|e| format!("Failed to parse difftastic JSON: {e}"))
+/// Converts repo-root-relative paths into jj filesets that match exactly those files.
+fn jj_filesets(paths: &[PathBuf]) -> Vec<String> {
+    paths
+        .iter()
+        .map(|path| {
+            let escaped = path
+                .to_string_lossy()
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"");
+            format!("root-file:\"{escaped}\"")
+        })
+        .collect()
 }
 
-/// Runs difftastic via jj for uncommitted changes (working copy).
-/// Executes `jj diff` with no revision argument.
-fn run_jj_diff_uncommitted() -> Result<Vec<difftastic::DifftFile>, String> {
-    let output = Command::new("jj")
-        .args(["diff", "--tool", "difft"])
-        .env("DFT_DISPLAY", "json")
-        .env("DFT_UNSTABLE", "yes")
-        .output()
-        .map_err(|e| format!("Failed to run jj: {e}"))?;
+/// Environment variables configuring difftastic when it is run through git or jj.
+///
+/// JSON output is always requested, on top of the pass-through [`ProcessOptions::difft`]
+/// settings. With [`ProcessOptions::ignore_whitespace`], carriage returns are stripped
+/// unless `strip_cr` says otherwise, so line ending changes don't show up; difftastic has
+/// no switch for other whitespace, which the processor hides instead.
+fn difft_env(options: &ProcessOptions) -> Vec<(String, String)> {
+    let mut difft = options.difft.clone();
+    if options.ignore_whitespace {
+        difft.strip_cr.get_or_insert(true);
+    }
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(format!("jj command failed: {stderr}"));
+    let mut env = vec![
+        ("DFT_DISPLAY".to_string(), "json".to_string()),
+        ("DFT_UNSTABLE".to_string(), "yes".to_string()),
+    ];
+    env.extend(difft.env());
+    env
+}
+
+/// Parses the JSON printed by difftastic.
+fn parse_difft_output(stdout: &[u8]) -> Result<Vec<difftastic::DifftFile>, DiffError> {
+    difftastic::parse(&String::from_utf8_lossy(stdout))
+        .map_err(|e| DiffError::Parse(format!("Failed to parse difftastic JSON: {e}")))
+}
+
+/// Arguments selecting difftastic as the tool of `jj diff`.
+///
+/// jj runs the `difft` merge tool from `PATH`; a configured `difft` executable replaces
+/// its program for this invocation only.
+fn jj_difft_args(difft: Option<&Path>) -> Vec<String> {
+    let mut args = vec!["--tool".to_string(), "difft".to_string()];
+    if let Some(difft) = difft {
+        let path = difft
+            .to_string_lossy()
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"");
+        args.push("--config".to_string());
+        args.push(format!("merge-tools.difft.program=\"{path}\""));
+    }
+    args
+}
+
+/// The `diff.external` setting that makes `git diff` run difftastic.
+///
+/// git runs external diff commands through the shell, so a configured `difft` executable
+/// is single-quoted.
+fn git_external_diff(difft: Option<&Path>) -> String {
+    match difft {
+        Some(difft) => {
+            let path = difft.to_string_lossy().replace('\'', r"'\''");
+            format!("diff.external='{path}'")
+        }
+        None => "diff.external=difft".to_string(),
     }
+}
+
+/// Runs difftastic via jj and parses the JSON output.
+/// Executes `jj diff -r <revset> --tool difft` with JSON output mode enabled.
+///
+/// `filesets` restricts the diff to matching files; pass `&[]` for the whole revision.
+fn run_jj_diff(
+    runner: &Runner,
+    revset: &str,
+    filesets: &[String],
+    options: &ProcessOptions,
+) -> Result<Vec<difftastic::DifftFile>, DiffError> {
+    let output = runner.run(
+        exec::command(Program::Jj)
+            .args(["diff", "-r", revset])
+            .args(jj_difft_args(exec::config().difft.as_deref()))
+            .args(filesets)
+            .envs(difft_env(options)),
+    )?;
 
-    difftastic::parse(&String::from_utf8_lossy(&output.stdout))
-        .map_err(|e| format!("Failed to parse difftastic JSON: {e}"))
+    parse_difft_output(&output.stdout)
+}
+
+/// Runs difftastic via jj for uncommitted changes (working copy).
+/// Executes `jj diff` with no revision argument.
+fn run_jj_diff_uncommitted(
+    runner: &Runner,
+    filesets: &[String],
+    options: &ProcessOptions,
+) -> Result<Vec<difftastic::DifftFile>, DiffError> {
+    let output = runner.run(
+        exec::command(Program::Jj)
+            .arg("diff")
+            .args(jj_difft_args(exec::config().difft.as_deref()))
+            .args(filesets)
+            .envs(difft_env(options)),
+    )?;
+
+    parse_difft_output(&output.stdout)
 }
 
 /// Runs difftastic via git and parses the JSON output.
@@ -266,31 +418,179 @@ fn run_jj_diff_uncommitted() -> Result<Vec<difftastic::DifftFile>, String> {
 /// - `&["HEAD^..HEAD"]` for a commit range
 /// - `&[]` for unstaged changes (working tree vs index)
 /// - `&["--cached"]` for staged changes (index vs HEAD)
-fn run_git_diff(extra_args: &[&str]) -> Result<Vec<difftastic::DifftFile>, String> {
-    let mut args = vec!["-c", "diff.external=difft", "diff"];
+fn run_git_diff(
+    runner: &Runner,
+    extra_args: &[&str],
+    options: &ProcessOptions,
+) -> Result<Vec<difftastic::DifftFile>, DiffError> {
+    let external = git_external_diff(exec::config().difft.as_deref());
+    let mut args = vec!["-c", external.as_str(), "diff"];
     args.extend(extra_args);
 
-    let output = Command::new("git")
-        .args(&args)
-        .env("DFT_DISPLAY", "json")
-        .env("DFT_UNSTABLE", "yes")
-        .output()
-        .map_err(|e| format!("Failed to run git: {e}"))?;
+    let output = runner.run(
+        exec::command(Program::Git)
+            .args(&args)
+            .envs(difft_env(options)),
+    )?;
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(format!("git command failed: {stderr}"));
+    parse_difft_output(&output.stdout)
+}
+
+/// Parses `git diff --name-status` output into placeholder difftastic entries.
+///
+/// Renames are reported under an `old => new` display path, as difftastic run by git
+/// reports them. Entries carry no language or changes; those come from running difftastic
+/// on the file's contents (see [`direct`]).
+fn parse_git_name_status_files(output: &str) -> Vec<difftastic::DifftFile> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.trim().split('\t');
+            let status = parts.next()?;
+            let path = parts.next()?.trim();
+            let (path, status) = match (status.chars().next()?, parts.next()) {
+                ('R', Some(new_path)) => (
+                    format!("{path} => {}", new_path.trim()),
+                    difftastic::Status::Changed,
+                ),
+                ('C', Some(new_path)) => (new_path.trim().to_string(), difftastic::Status::Created),
+                ('A', _) => (path.to_string(), difftastic::Status::Created),
+                ('D', _) => (path.to_string(), difftastic::Status::Deleted),
+                _ => (path.to_string(), difftastic::Status::Changed),
+            };
+            Some(placeholder_file(path, status))
+        })
+        .collect()
+}
+
+/// Parses `jj diff --summary` output into placeholder difftastic entries, like
+/// [`parse_git_name_status_files`].
+fn parse_jj_summary_files(output: &str) -> Vec<difftastic::DifftFile> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (status, path) = line.trim().split_once(' ')?;
+            let status = match status {
+                "A" | "C" => difftastic::Status::Created,
+                "D" => difftastic::Status::Deleted,
+                _ => difftastic::Status::Changed,
+            };
+            let path = if status == difftastic::Status::Created {
+                // Copies are shown at their destination only.
+                split_display_path(Path::new(path)).1
+            } else {
+                PathBuf::from(path)
+            };
+            Some(placeholder_file(path, status))
+        })
+        .collect()
+}
+
+fn placeholder_file(path: impl Into<PathBuf>, status: difftastic::Status) -> difftastic::DifftFile {
+    difftastic::DifftFile {
+        path: path.into(),
+        language: String::new(),
+        status,
+        aligned_lines: Vec::new(),
+        chunks: Vec::new(),
     }
+}
 
-    difftastic::parse(&String::from_utf8_lossy(&output.stdout))
-        .map_err(|e| format!("Failed to parse difftastic JSON: {e}"))
+/// Lists the files `git diff` would pass to difftastic, without running it.
+fn git_changed_files(
+    runner: &Runner,
+    extra_args: &[&str],
+) -> Result<Vec<difftastic::DifftFile>, DiffError> {
+    let output = runner.run(
+        exec::command(Program::Git)
+            .args(["diff", "--name-status", "-M"])
+            .args(extra_args),
+    )?;
+    Ok(parse_git_name_status_files(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+/// Lists the files `jj diff` would pass to difftastic, without running it.
+///
+/// `revset` is `None` for the working copy.
+fn jj_changed_files(
+    runner: &Runner,
+    revset: Option<&str>,
+    filesets: &[String],
+) -> Result<Vec<difftastic::DifftFile>, DiffError> {
+    let mut cmd = exec::command(Program::Jj);
+    cmd.arg("diff");
+    if let Some(revset) = revset {
+        cmd.args(["-r", revset]);
+    }
+    let output = runner.run(cmd.arg("--summary").args(filesets))?;
+    Ok(parse_jj_summary_files(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+/// Runs difftastic through `git diff`, or with [`ProcessOptions::direct_difft`] only lists
+/// the changed files for it to be run on later.
+fn git_difft_files(
+    runner: &Runner,
+    extra_args: &[&str],
+    options: &ProcessOptions,
+) -> Result<Vec<difftastic::DifftFile>, DiffError> {
+    if options.direct_difft {
+        git_changed_files(runner, extra_args)
+    } else {
+        run_git_diff(runner, extra_args, options)
+    }
+}
+
+/// Runs difftastic through `jj diff`, or with [`ProcessOptions::direct_difft`] only lists
+/// the changed files for it to be run on later.
+///
+/// `revset` is `None` for the working copy.
+fn jj_difft_files(
+    runner: &Runner,
+    revset: Option<&str>,
+    filesets: &[String],
+    options: &ProcessOptions,
+) -> Result<Vec<difftastic::DifftFile>, DiffError> {
+    if options.direct_difft {
+        return jj_changed_files(runner, revset, filesets);
+    }
+    match revset {
+        Some(revset) => run_jj_diff(runner, revset, filesets, options),
+        None => run_jj_diff_uncommitted(runner, filesets, options),
+    }
 }
 
 /// Gets the merge-base of two git refs.
-fn git_merge_base(a: &str, b: &str) -> Option<String> {
-    Command::new("git")
-        .args(["merge-base", a, b])
-        .output()
+fn git_merge_base(runner: &Runner, a: &str, b: &str) -> Option<String> {
+    #[cfg(feature = "native-git")]
+    if let Ok(base) = native_git::open().and_then(|repo| native_git::merge_base(&repo, a, b)) {
+        return Some(base);
+    }
+
+    runner
+        .output(exec::command(Program::Git).args(["merge-base", a, b]))
+        .ok()
+        .filter(|o| o.status.success())
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+}
+
+/// Resolves a git revision to a full commit id.
+fn git_commit_id(runner: &Runner, rev: &str) -> Option<String> {
+    #[cfg(feature = "native-git")]
+    if let Ok(id) = native_git::open().and_then(|repo| native_git::commit_id(&repo, rev)) {
+        return Some(id.to_string());
+    }
+
+    runner
+        .output(exec::command(Program::Git).args([
+            "rev-parse",
+            "--verify",
+            "-q",
+            &format!("{rev}^{{commit}}"),
+        ]))
         .ok()
         .filter(|o| o.status.success())
         .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
@@ -358,18 +658,6 @@ fn prepare_file_for_display(
     (file_stats, old_path, new_path, moved_from)
 }
 
-fn process_prepared_file(
-    file: difftastic::DifftFile,
-    old_lines: Vec<String>,
-    new_lines: Vec<String>,
-    file_stats: Option<(u32, u32)>,
-    moved_from: Option<PathBuf>,
-) -> processor::DisplayFile {
-    let mut display = processor::process_file(file, old_lines, new_lines, file_stats);
-    display.moved_from = moved_from;
-    display
-}
-
 fn parse_jj_summary_rename(line: &str) -> Option<(PathBuf, PathBuf)> {
     let renamed = line.trim().strip_prefix("R ")?;
     let (old_path, new_path) = split_display_path(Path::new(renamed));
@@ -408,21 +696,25 @@ fn parse_git_name_status_renames(output: &str) -> HashMap<PathBuf, PathBuf> {
         .collect()
 }
 
-fn git_rename_map(mode: &DiffMode) -> HashMap<PathBuf, PathBuf> {
-    let mut cmd = Command::new("git");
-    cmd.args(["diff", "--name-status", "-M"]);
+fn git_rename_map(runner: &Runner, mode: &DiffMode) -> HashMap<PathBuf, PathBuf> {
+    let extra_args: &[&str] = match mode {
+        DiffMode::Range(range) => &[range],
+        DiffMode::Unstaged => &[],
+        DiffMode::Staged => &["--cached"],
+    };
 
-    match mode {
-        DiffMode::Range(range) => {
-            cmd.arg(range);
-        }
-        DiffMode::Unstaged => {}
-        DiffMode::Staged => {
-            cmd.arg("--cached");
-        }
+    #[cfg(feature = "native-git")]
+    if let Some(spec) = native_git::DiffSpec::from_args(extra_args)
+        && let Ok(renames) =
+            native_git::open().and_then(|repo| native_git::rename_map(&repo, &spec))
+    {
+        return renames;
     }
 
-    let output = cmd.output().ok();
+    let mut cmd = exec::command(Program::Git);
+    cmd.args(["diff", "--name-status", "-M"]).args(extra_args);
+
+    let output = runner.output(&mut cmd).ok();
     let Some(output) = output.filter(|o| o.status.success()) else {
         return HashMap::new();
     };
@@ -430,8 +722,8 @@ fn git_rename_map(mode: &DiffMode) -> HashMap<PathBuf, PathBuf> {
     parse_git_name_status_renames(&String::from_utf8_lossy(&output.stdout))
 }
 
-fn jj_rename_map(mode: &DiffMode) -> HashMap<PathBuf, PathBuf> {
-    let mut cmd = Command::new("jj");
+fn jj_rename_map(runner: &Runner, mode: &DiffMode) -> HashMap<PathBuf, PathBuf> {
+    let mut cmd = exec::command(Program::Jj);
     cmd.arg("diff");
 
     match mode {
@@ -444,7 +736,7 @@ fn jj_rename_map(mode: &DiffMode) -> HashMap<PathBuf, PathBuf> {
         }
     }
 
-    let output = cmd.arg("--summary").output().ok();
+    let output = runner.output(cmd.arg("--summary")).ok();
     let Some(output) = output.filter(|o| o.status.success()) else {
         return HashMap::new();
     };
@@ -456,9 +748,9 @@ fn jj_rename_map(mode: &DiffMode) -> HashMap<PathBuf, PathBuf> {
 ///
 /// Handles single commits, `A..B` ranges, and `A...B` (merge-base) ranges.
 #[inline]
-fn parse_git_range(range: &str) -> (String, String) {
+fn parse_git_range(runner: &Runner, range: &str) -> (String, String) {
     if let Some((a, b)) = range.split_once("...") {
-        let base = git_merge_base(a, b).unwrap_or_else(|| format!("{a}^"));
+        let base = git_merge_base(runner, a, b).unwrap_or_else(|| format!("{a}^"));
         (base, b.to_string())
     } else if let Some((old, new)) = range.split_once("..") {
         (old.to_string(), new.to_string())
@@ -468,6 +760,7 @@ fn parse_git_range(range: &str) -> (String, String) {
 }
 
 /// The type of diff to perform.
+#[derive(Clone)]
 enum DiffMode {
     /// A commit range (e.g., "HEAD^..HEAD" for git, "@" for jj).
     Range(String),
@@ -477,160 +770,372 @@ enum DiffMode {
     Staged,
 }
 
-/// Fetches file content from the working tree, using the appropriate VCS root.
-fn working_tree_content_for_vcs(path: &Path, vcs: &str) -> Option<String> {
-    let root = if vcs == "git" { git_root() } else { jj_root() }?;
+/// Reads a file from the working tree, resolving repo-root-relative paths against `root`.
+fn working_tree_content(root: &Path, path: &Path) -> Option<String> {
     std::fs::read_to_string(root.join(path)).ok()
 }
 
-/// Unified implementation for running difftastic with any diff mode.
-/// Handles git and jj VCS, fetches file contents, and processes files in parallel.
-fn run_diff_impl(lua: &Lua, mode: DiffMode, vcs: &str) -> LuaResult<LuaTable> {
-    // Get files and stats based on mode and VCS
-    let (files, stats) = match (&mode, vcs) {
+/// Where the old and new versions of each file are read from for a diff mode.
+enum ContentSource {
+    /// Both sides from git commits.
+    GitCommits {
+        old: String,
+        new: String,
+        blobs: CatFile,
+    },
+    /// Old side from the git index, new side from the working tree.
+    GitUnstaged { root: PathBuf, blobs: CatFile },
+    /// Old side from `HEAD`, new side from the git index.
+    GitStaged { blobs: CatFile },
+    /// Both sides from jj revisions.
+    JjRevisions {
+        root: PathBuf,
+        old: String,
+        new: String,
+    },
+    /// Old side from `@-`, new side from the jj working copy.
+    JjWorkingCopy { root: PathBuf },
+}
+
+impl ContentSource {
+    /// Picks the content source for a diff mode.
+    ///
+    /// `root` is the VCS root: paths from difftastic are repo-root-relative, but
+    /// `jj file show` and working tree reads resolve relative to the CWD. git blobs
+    /// are read through a single `git cat-file --batch` process started in `root`.
+    fn new(runner: &Runner, mode: &DiffMode, vcs: &str, root: PathBuf) -> Self {
+        match (mode, vcs) {
+            (DiffMode::Range(range), "git") => {
+                let (old, new) = parse_git_range(runner, range);
+                Self::GitCommits {
+                    old,
+                    new,
+                    blobs: CatFile::new(root),
+                }
+            }
+            (DiffMode::Range(range), _) => {
+                let (old, new) = parse_jj_range(range)
+                    .unwrap_or_else(|| (format!("roots({range})-"), format!("heads({range})")));
+                Self::JjRevisions { root, old, new }
+            }
+            (DiffMode::Unstaged, "git") => Self::GitUnstaged {
+                blobs: CatFile::new(root.clone()),
+                root,
+            },
+            (DiffMode::Unstaged, _) => Self::JjWorkingCopy { root },
+            (DiffMode::Staged, "git") => Self::GitStaged {
+                blobs: CatFile::new(root),
+            },
+            (DiffMode::Staged, _) => Self::JjRevisions {
+                root,
+                old: "@-".to_string(),
+                new: "@".to_string(),
+            },
+        }
+    }
+
+    /// Fetches the old and new contents of a file; `None` where a side doesn't exist.
+    fn fetch(
+        &self,
+        runner: &Runner,
+        old_path: &Path,
+        new_path: &Path,
+    ) -> (Option<String>, Option<String>) {
+        match self {
+            Self::GitCommits { old, new, blobs } => (
+                git_file_content(runner, blobs, old, old_path),
+                git_file_content(runner, blobs, new, new_path),
+            ),
+            Self::GitUnstaged { root, blobs } => (
+                git_index_content(runner, blobs, old_path),
+                working_tree_content(root, new_path),
+            ),
+            Self::GitStaged { blobs } => (
+                git_file_content(runner, blobs, "HEAD", old_path),
+                git_index_content(runner, blobs, new_path),
+            ),
+            Self::JjRevisions { root, old, new } => (
+                jj_file_content(runner, root, old, old_path),
+                jj_file_content(runner, root, new, new_path),
+            ),
+            Self::JjWorkingCopy { root } => (
+                jj_file_content(runner, root, "@-", old_path),
+                working_tree_content(root, new_path),
+            ),
+        }
+    }
+
+    /// Stops any blob reader process until the next fetch.
+    fn release_readers(&self) {
+        match self {
+            Self::GitCommits { blobs, .. }
+            | Self::GitUnstaged { blobs, .. }
+            | Self::GitStaged { blobs } => blobs.release(),
+            Self::JjRevisions { .. } | Self::JjWorkingCopy { .. } => {}
+        }
+    }
+}
+
+/// Applies VCS-detected renames to a processed file.
+///
+/// Rename destinations get `moved_from` set, and the deleted side of a rename is
+/// dropped (returns `None`) since it is already represented by the destination.
+fn apply_renames(
+    mut file: processor::DisplayFile,
+    renames: &HashMap<PathBuf, PathBuf>,
+    old_paths: &HashSet<PathBuf>,
+) -> Option<processor::DisplayFile> {
+    if let Some(old_path) = renames.get(&file.path) {
+        file.moved_from = Some(old_path.clone());
+        file.status = difftastic::Status::Created;
+    }
+
+    if file.status == difftastic::Status::Deleted && old_paths.contains(&file.path) {
+        return None;
+    }
+
+    Some(file)
+}
+
+/// Runs difftastic for `mode` and fetches the VCS line stats for the same range.
+///
+/// A non-empty `paths` list (repo-root-relative) restricts both to those files.
+fn difft_files_and_stats(
+    runner: &Runner,
+    mode: &DiffMode,
+    vcs: &str,
+    paths: &[PathBuf],
+    options: &ProcessOptions,
+) -> Result<(Vec<difftastic::DifftFile>, FileStats), DiffError> {
+    let pathspecs = git_pathspecs(paths);
+    let filesets = jj_filesets(paths);
+    let ignore_whitespace = options.ignore_whitespace;
+
+    Ok(match (mode, vcs) {
         (DiffMode::Range(range), "git") => {
-            let (old_ref, new_ref) = parse_git_range(range);
+            let (old_ref, new_ref) = parse_git_range(runner, range);
             let git_range = format!("{old_ref}..{new_ref}");
-            let files = run_git_diff(&[&git_range]).map_err(LuaError::RuntimeError)?;
-            let stats = git_diff_stats(&[&git_range]);
+            let args = with_git_paths(vec![&git_range], &pathspecs);
+            let files = git_difft_files(runner, &args, options)?;
+            let stats = git_diff_stats(runner, &args, ignore_whitespace);
             (files, stats)
         }
         (DiffMode::Range(range), _) => {
-            let files = run_jj_diff(range).map_err(LuaError::RuntimeError)?;
-            let stats = jj_diff_stats(&mode);
+            let files = jj_difft_files(runner, Some(range), &filesets, options)?;
+            let stats = jj_diff_stats(runner, mode, &pathspecs, ignore_whitespace);
             (files, stats)
         }
         (DiffMode::Unstaged, "git") => {
-            let files = run_git_diff(&[]).map_err(LuaError::RuntimeError)?;
-            let stats = git_diff_stats(&[]);
+            let args = with_git_paths(vec![], &pathspecs);
+            let files = git_difft_files(runner, &args, options)?;
+            let stats = git_diff_stats(runner, &args, ignore_whitespace);
             (files, stats)
         }
         (DiffMode::Unstaged, _) => {
-            let files = run_jj_diff_uncommitted().map_err(LuaError::RuntimeError)?;
-            let stats = jj_diff_stats(&mode);
+            let files = jj_difft_files(runner, None, &filesets, options)?;
+            let stats = jj_diff_stats(runner, mode, &pathspecs, ignore_whitespace);
             (files, stats)
         }
         (DiffMode::Staged, "git") => {
-            let files = run_git_diff(&["--cached"]).map_err(LuaError::RuntimeError)?;
-            let stats = git_diff_stats(&["--cached"]);
+            let args = with_git_paths(vec!["--cached"], &pathspecs);
+            let files = git_difft_files(runner, &args, options)?;
+            let stats = git_diff_stats(runner, &args, ignore_whitespace);
             (files, stats)
         }
         (DiffMode::Staged, _) => {
             // jj doesn't have a staging area concept, so show current revision
-            let files = run_jj_diff("@").map_err(LuaError::RuntimeError)?;
-            let stats = jj_diff_stats(&mode);
+            let files = jj_difft_files(runner, Some("@"), &filesets, options)?;
+            let stats = jj_diff_stats(runner, mode, &pathspecs, ignore_whitespace);
             (files, stats)
         }
-    };
+    })
+}
 
-    // Compute VCS root once for jj file content lookups (paths from difftastic
-    // are repo-root-relative, but jj file show resolves relative to CWD).
-    let vcs_root = if vcs != "git" { jj_root() } else { git_root() };
+/// The parts of a diff computed once up front, before any file is processed.
+struct PreparedDiff {
+    files: Vec<difftastic::DifftFile>,
+    stats: FileStats,
+    source: ContentSource,
+    renames: HashMap<PathBuf, PathBuf>,
+    old_paths: HashSet<PathBuf>,
+}
 
-    // Process files based on mode and VCS
-    let mut display_files: Vec<_> = match (&mode, vcs) {
-        (DiffMode::Range(range), "git") => {
-            let (old_ref, new_ref) = parse_git_range(range);
-            files
-                .into_par_iter()
-                .map(|mut file| {
-                    let (file_stats, old_path, new_path, moved_from) =
-                        prepare_file_for_display(&mut file, &stats);
-                    let old_lines = into_lines(git_file_content(&old_ref, &old_path));
-                    let new_lines = into_lines(git_file_content(&new_ref, &new_path));
-                    process_prepared_file(file, old_lines, new_lines, file_stats, moved_from)
-                })
-                .collect()
-        }
-        (DiffMode::Range(range), _) => {
-            let root = vcs_root.as_deref().unwrap_or(Path::new("."));
-            let (old_ref, new_ref) = parse_jj_range(range)
-                .unwrap_or_else(|| (format!("roots({range})-"), format!("heads({range})")));
-            files
-                .into_par_iter()
-                .map(|mut file| {
-                    let (file_stats, old_path, new_path, moved_from) =
-                        prepare_file_for_display(&mut file, &stats);
-                    let old_lines = into_lines(jj_file_content(root, &old_ref, &old_path));
-                    let new_lines = into_lines(jj_file_content(root, &new_ref, &new_path));
-                    process_prepared_file(file, old_lines, new_lines, file_stats, moved_from)
-                })
-                .collect()
-        }
-        (DiffMode::Unstaged, "git") => files
-            .into_par_iter()
-            .map(|mut file| {
-                let (file_stats, old_path, new_path, moved_from) =
-                    prepare_file_for_display(&mut file, &stats);
-                let old_lines = into_lines(git_index_content(&old_path));
-                let new_lines = into_lines(working_tree_content_for_vcs(&new_path, "git"));
-                process_prepared_file(file, old_lines, new_lines, file_stats, moved_from)
-            })
-            .collect(),
-        (DiffMode::Unstaged, _) => {
-            let root = vcs_root.as_deref().unwrap_or(Path::new("."));
-            files
-                .into_par_iter()
-                .map(|mut file| {
-                    let (file_stats, old_path, new_path, moved_from) =
-                        prepare_file_for_display(&mut file, &stats);
-                    let old_lines = into_lines(jj_file_content(root, "@-", &old_path));
-                    let new_lines = into_lines(working_tree_content_for_vcs(&new_path, "jj"));
-                    process_prepared_file(file, old_lines, new_lines, file_stats, moved_from)
-                })
-                .collect()
-        }
-        (DiffMode::Staged, "git") => files
-            .into_par_iter()
-            .map(|mut file| {
-                let (file_stats, old_path, new_path, moved_from) =
-                    prepare_file_for_display(&mut file, &stats);
-                let old_lines = into_lines(git_file_content("HEAD", &old_path));
-                let new_lines = into_lines(git_index_content(&new_path));
-                process_prepared_file(file, old_lines, new_lines, file_stats, moved_from)
-            })
-            .collect(),
-        (DiffMode::Staged, _) => {
-            let root = vcs_root.as_deref().unwrap_or(Path::new("."));
-            files
-                .into_par_iter()
-                .map(|mut file| {
-                    let (file_stats, old_path, new_path, moved_from) =
-                        prepare_file_for_display(&mut file, &stats);
-                    let old_lines = into_lines(jj_file_content(root, "@-", &old_path));
-                    let new_lines = into_lines(jj_file_content(root, "@", &new_path));
-                    process_prepared_file(file, old_lines, new_lines, file_stats, moved_from)
-                })
-                .collect()
-        }
+/// Gets the VCS root used for file content lookups, falling back to the CWD.
+fn vcs_root(runner: &Runner, vcs: &str) -> PathBuf {
+    let root = if vcs != "git" {
+        jj_root(runner)
+    } else {
+        git_root(runner)
     };
+    root.unwrap_or_else(exec::working_dir)
+}
 
+/// Runs difftastic for `mode` and resolves everything needed to process its files:
+/// line stats, the content source and VCS-detected renames.
+///
+/// A non-empty `paths` list restricts the diff to those repo-root-relative files. Renames
+/// are still detected across the whole diff, so a restricted file is reported exactly as
+/// it would be in the full diff.
+fn prepare_diff(
+    runner: &Runner,
+    mode: &DiffMode,
+    vcs: &str,
+    root: PathBuf,
+    paths: &[PathBuf],
+    options: &ProcessOptions,
+) -> Result<PreparedDiff, DiffError> {
+    let (files, stats) = difft_files_and_stats(runner, mode, vcs, paths, options)?;
+
+    let source = ContentSource::new(runner, mode, vcs, root);
+
+    // Renames are resolved up front so each file can be emitted as soon as it is processed.
     let renames = if vcs == "git" {
-        git_rename_map(&mode)
+        git_rename_map(runner, mode)
     } else {
-        jj_rename_map(&mode)
+        jj_rename_map(runner, mode)
     };
-    if !renames.is_empty() {
-        let old_paths: HashSet<PathBuf> = renames.values().cloned().collect();
-
-        display_files = display_files
-            .into_iter()
-            .filter_map(|mut file| {
-                if let Some(old_path) = renames.get(&file.path) {
-                    file.moved_from = Some(old_path.clone());
-                    file.status = difftastic::Status::Created;
-                }
+    let old_paths: HashSet<PathBuf> = renames.values().cloned().collect();
 
-                if file.status == difftastic::Status::Deleted && old_paths.contains(&file.path) {
-                    return None;
-                }
+    if runner.is_cancelled() {
+        return Err(DiffError::Cancelled);
+    }
 
-                Some(file)
-            })
-            .collect();
+    Ok(PreparedDiff {
+        files,
+        stats,
+        source,
+        renames,
+        old_paths,
+    })
+}
+
+/// Unified implementation for running difftastic with any diff mode.
+/// Handles git and jj VCS, fetches file contents, and processes files in parallel.
+///
+/// Each processed file is handed to `emit` as soon as it is ready, together with its
+/// position in the diff's file list. Files are processed in parallel, so `emit` is called
+/// from multiple threads and in no particular order.
+///
+/// Commit ranges are served from the [`cache`] when possible; files materialized by an
+/// earlier diff of the same range are emitted without reading any content.
+///
+/// Once the runner is cancelled, no further files are emitted and the remaining work is
+/// abandoned with [`DiffError::Cancelled`].
+fn diff_files<F>(
+    runner: &Runner,
+    mode: &DiffMode,
+    vcs: &str,
+    options: &ProcessOptions,
+    emit: F,
+) -> Result<(), DiffError>
+where
+    F: Fn(usize, processor::DisplayFile) + Sync,
+{
+    let session = session::DiffSession::open(runner, mode, vcs, options)?;
+    let was_materialized = session.is_materialized();
+
+    (0..session.len()).into_par_iter().try_for_each(|index| {
+        if runner.is_cancelled() {
+            return Err(DiffError::Cancelled);
+        }
+        if let Some(file) = session.file(runner, index)? {
+            emit(index, file.clone());
+        }
+        Ok(())
+    })?;
+
+    // Every file is materialized now, so a cached entry can be served without any reads.
+    if !was_materialized {
+        session.persist();
+    }
+    session.release_readers();
+    Ok(())
+}
+
+/// Runs a diff and collects the processed files in difftastic's output order.
+fn collect_diff(
+    runner: &Runner,
+    mode: &DiffMode,
+    vcs: &str,
+    options: &ProcessOptions,
+) -> Result<Vec<processor::DisplayFile>, DiffError> {
+    // Moves are detected across all files below, so files skip the per-file pass.
+    let per_file = options.without_moves();
+    let collected = Mutex::new(Vec::new());
+    diff_files(runner, mode, vcs, &per_file, |index, file| {
+        collected
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push((index, file));
+    })?;
+
+    let mut files = collected.into_inner().unwrap_or_else(|e| e.into_inner());
+    files.sort_unstable_by_key(|(index, _)| *index);
+    let mut files: Vec<_> = files.into_iter().map(|(_, file)| file).collect();
+
+    // With every file at hand, find moves within and across files.
+    if options.detect_moves {
+        moves::detect_moves(&mut files);
+    }
+    Ok(files)
+}
+
+/// Resolves paths passed from Lua to repo-root-relative paths.
+///
+/// Absolute paths, such as buffer names, are made relative to `root` and dropped if they
+/// lie outside it. Relative paths are taken to be repo-root-relative already, like the
+/// `path` of a diffed file.
+fn repo_relative_paths(root: &Path, paths: &[PathBuf]) -> Vec<PathBuf> {
+    let canonical_root = root.canonicalize().ok();
+    paths
+        .iter()
+        .filter_map(|path| {
+            if path.is_relative() {
+                return Some(path.clone());
+            }
+            if let Ok(relative) = path.strip_prefix(root) {
+                return Some(relative.to_path_buf());
+            }
+            // Buffer names may go through symlinks the VCS root doesn't. The file itself
+            // may have been deleted, so resolve its directory instead.
+            let canonical = path.parent()?.canonicalize().ok()?.join(path.file_name()?);
+            let relative = canonical.strip_prefix(canonical_root.as_ref()?).ok()?;
+            Some(relative.to_path_buf())
+        })
+        .collect()
+}
+
+/// Re-diffs `paths` against the index (git) or `@-` (jj), as in the unstaged diff.
+///
+/// Returns the repo-root-relative paths that were refreshed together with the processed
+/// files of those that still have changes. A refreshed path without a file no longer
+/// differs and should be dropped from the view.
+fn refresh_files(
+    runner: &Runner,
+    vcs: &str,
+    paths: &[PathBuf],
+    options: &ProcessOptions,
+) -> Result<(Vec<PathBuf>, Vec<processor::DisplayFile>), DiffError> {
+    let root = vcs_root(runner, vcs);
+    let paths = repo_relative_paths(&root, paths);
+    // An empty filter would diff the whole working tree.
+    if paths.is_empty() {
+        return Ok((paths, Vec::new()));
     }
 
-    let files_table = lua.create_table()?;
+    let prepared = prepare_diff(runner, &DiffMode::Unstaged, vcs, root, &paths, options)?;
+    let session = session::DiffSession::from_prepared(prepared, options.clone());
+    let files = (0..session.len())
+        .into_par_iter()
+        .map(|index| session.file(runner, index).map(|file| file.cloned()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok((paths, files.into_iter().flatten().collect()))
+}
+
+/// Converts processed files into the `{ files = { ... } }` result table returned to Lua.
+fn files_to_lua(lua: &Lua, display_files: Vec<processor::DisplayFile>) -> LuaResult<LuaTable> {
+    let files_table = lua.create_table_with_capacity(display_files.len(), 0)?;
     for (i, file) in display_files.into_iter().enumerate() {
         files_table.set(i + 1, file.into_lua(lua)?)?;
     }
@@ -640,19 +1145,285 @@ fn run_diff_impl(lua: &Lua, mode: DiffMode, vcs: &str) -> LuaResult<LuaTable> {
     Ok(result)
 }
 
+/// Result of a fallible export: the value, or `nil` and an error table.
+type LuaDiffResult<T> = LuaResult<(Option<T>, Option<DiffError>)>;
+
+/// Converts a diff outcome into Lua's `result` / `nil, err` convention.
+fn lua_result<T, U>(
+    result: Result<T, DiffError>,
+    convert: impl FnOnce(T) -> LuaResult<U>,
+) -> LuaDiffResult<U> {
+    match result {
+        Ok(value) => Ok((Some(convert(value)?), None)),
+        Err(err) => Ok((None, Some(err))),
+    }
+}
+
+/// Runs a diff synchronously and converts the result for Lua.
+fn run_diff_impl(
+    lua: &Lua,
+    mode: DiffMode,
+    vcs: &str,
+    options: ProcessOptions,
+) -> LuaDiffResult<LuaTable> {
+    lua_result(
+        collect_diff(&Runner::default(), &mode, vcs, &options),
+        |files| files_to_lua(lua, files),
+    )
+}
+
+/// Options accepted by the `*_async` exports.
+#[derive(Default)]
+struct JobOptions {
+    /// Deliver each file as a job event as soon as it is processed.
+    stream: bool,
+    /// Return file metadata only and materialize rows per file through a session.
+    lazy: bool,
+    /// How files are processed; read from the same table.
+    process: ProcessOptions,
+}
+
+impl FromLua for JobOptions {
+    fn from_lua(value: LuaValue, lua: &Lua) -> LuaResult<Self> {
+        let opts = match value {
+            LuaValue::Nil => return Ok(Self::default()),
+            LuaValue::Table(opts) => opts,
+            other => {
+                return Err(LuaError::FromLuaConversionError {
+                    from: other.type_name(),
+                    to: "JobOptions".to_string(),
+                    message: Some("expected an options table".to_string()),
+                });
+            }
+        };
+
+        let options = Self {
+            stream: opts.get::<Option<bool>>("stream")?.unwrap_or(false),
+            lazy: opts.get::<Option<bool>>("lazy")?.unwrap_or(false),
+            process: ProcessOptions::from_lua(LuaValue::Table(opts), lua)?,
+        };
+        if options.stream && options.lazy {
+            return Err(LuaError::RuntimeError(
+                "`stream` and `lazy` cannot be combined".to_string(),
+            ));
+        }
+        Ok(options)
+    }
+}
+
+/// Starts a diff on a background thread and returns a job handle.
+///
+/// In streaming mode each file is delivered as a job event as soon as it is processed. In
+/// lazy mode only file metadata is collected and `poll` returns a session id for fetching
+/// rows with `file_rows`. Otherwise the files are collected and returned by `poll` once the
+/// job finishes. `job:cancel()` kills the job's child processes and stops the remaining work.
+fn spawn_diff_job(mode: DiffMode, vcs: String, opts: JobOptions) -> LuaResult<job::DiffJob> {
+    let process = opts.process;
+    let spawned = if opts.stream {
+        job::DiffJob::spawn_streaming(move |sink| {
+            let runner = Runner::new(sink.cancel_token().clone());
+            diff_files(&runner, &mode, &vcs, &process, |index, file| {
+                sink.emit_file(index, file);
+            })
+        })
+    } else if opts.lazy {
+        job::DiffJob::spawn(move |cancel| {
+            session::DiffSession::open(&Runner::new(cancel.clone()), &mode, &vcs, &process)
+                .map(job::JobOutput::Session)
+        })
+    } else {
+        job::DiffJob::spawn(move |cancel| {
+            collect_diff(&Runner::new(cancel.clone()), &mode, &vcs, &process)
+                .map(job::JobOutput::Files)
+        })
+    };
+    spawned.map_err(|e| LuaError::RuntimeError(format!("Failed to spawn diff job: {e}")))
+}
+
 /// Runs difftastic for a commit range.
-fn run_diff(lua: &Lua, (range, vcs): (String, String)) -> LuaResult<LuaTable> {
-    run_diff_impl(lua, DiffMode::Range(range), &vcs)
+fn run_diff(
+    lua: &Lua,
+    (range, vcs, opts): (String, String, ProcessOptions),
+) -> LuaDiffResult<LuaTable> {
+    run_diff_impl(lua, DiffMode::Range(range), &vcs, opts)
 }
 
 /// Runs difftastic for unstaged changes.
-fn run_diff_unstaged(lua: &Lua, vcs: String) -> LuaResult<LuaTable> {
-    run_diff_impl(lua, DiffMode::Unstaged, &vcs)
+fn run_diff_unstaged(lua: &Lua, (vcs, opts): (String, ProcessOptions)) -> LuaDiffResult<LuaTable> {
+    run_diff_impl(lua, DiffMode::Unstaged, &vcs, opts)
 }
 
 /// Runs difftastic for staged changes.
-fn run_diff_staged(lua: &Lua, vcs: String) -> LuaResult<LuaTable> {
-    run_diff_impl(lua, DiffMode::Staged, &vcs)
+fn run_diff_staged(lua: &Lua, (vcs, opts): (String, ProcessOptions)) -> LuaDiffResult<LuaTable> {
+    run_diff_impl(lua, DiffMode::Staged, &vcs, opts)
+}
+
+/// Starts a background diff for a commit range.
+fn run_diff_async(
+    _: &Lua,
+    (range, vcs, opts): (String, String, JobOptions),
+) -> LuaResult<job::DiffJob> {
+    spawn_diff_job(DiffMode::Range(range), vcs, opts)
+}
+
+/// Starts a background diff for unstaged changes.
+fn run_diff_unstaged_async(_: &Lua, (vcs, opts): (String, JobOptions)) -> LuaResult<job::DiffJob> {
+    spawn_diff_job(DiffMode::Unstaged, vcs, opts)
+}
+
+/// Starts a background diff for staged changes.
+fn run_diff_staged_async(_: &Lua, (vcs, opts): (String, JobOptions)) -> LuaResult<job::DiffJob> {
+    spawn_diff_job(DiffMode::Staged, vcs, opts)
+}
+
+/// Runs difftastic on two files or directories, outside any VCS.
+///
+/// Relative paths are resolved against the configured working directory.
+fn run_diff_paths(
+    lua: &Lua,
+    (lhs, rhs, opts): (String, String, ProcessOptions),
+) -> LuaDiffResult<LuaTable> {
+    let cwd = exec::working_dir();
+    lua_result(
+        paths::diff_paths(&Runner::default(), &cwd.join(lhs), &cwd.join(rhs), &opts),
+        |files| files_to_lua(lua, files),
+    )
+}
+
+/// Starts a background diff of two files or directories, outside any VCS.
+///
+/// `poll` returns the files once the job finishes, as `run_diff_paths` does.
+/// `job:cancel()` kills the running `difft` processes and skips the remaining pairs.
+fn run_diff_paths_async(
+    _: &Lua,
+    (lhs, rhs, opts): (String, String, ProcessOptions),
+) -> LuaResult<job::DiffJob> {
+    let cwd = exec::working_dir();
+    let (lhs, rhs) = (cwd.join(lhs), cwd.join(rhs));
+    job::DiffJob::spawn(move |cancel| {
+        paths::diff_paths(&Runner::new(cancel.clone()), &lhs, &rhs, &opts)
+            .map(job::JobOutput::Files)
+    })
+    .map_err(|e| LuaError::RuntimeError(format!("Failed to spawn diff job: {e}")))
+}
+
+/// Runs difftastic on two in-memory texts and returns the processed file.
+fn diff_texts(
+    lua: &Lua,
+    (old, new, opts): (texts::Text, texts::Text, ProcessOptions),
+) -> LuaDiffResult<LuaValue> {
+    lua_result(
+        texts::diff_texts(&Runner::default(), &old, &new, &opts),
+        |file| file.into_lua(lua),
+    )
+}
+
+/// Re-diffs individual files of the unstaged diff, e.g. after a buffer is written.
+///
+/// `paths` may be absolute or repo-root-relative. Returns
+/// `{ files = { ... }, paths = { ... } }`: the updated files and the repo-root-relative
+/// paths that were refreshed. Refreshed paths missing from `files` no longer have changes.
+fn refresh_paths(
+    lua: &Lua,
+    (vcs, paths, opts): (String, Vec<String>, ProcessOptions),
+) -> LuaDiffResult<LuaTable> {
+    let paths: Vec<PathBuf> = paths.into_iter().map(PathBuf::from).collect();
+    lua_result(
+        refresh_files(&Runner::default(), &vcs, &paths, &opts),
+        |(paths, files)| {
+            let result = files_to_lua(lua, files)?;
+            let paths_table = lua.create_table_with_capacity(paths.len(), 0)?;
+            for (i, path) in paths.iter().enumerate() {
+                paths_table.set(i + 1, path.to_string_lossy().as_ref())?;
+            }
+            result.set("paths", paths_table)?;
+            Ok(result)
+        },
+    )
+}
+
+/// Starts watching the working copy of the repository for changes to the unstaged diff.
+fn watch(_: &Lua, vcs: String) -> LuaDiffResult<watch::RepoWatcher> {
+    let root = vcs_root(&Runner::default(), &vcs);
+    let started = watch::RepoWatcher::start(&vcs, &root)
+        .map_err(|e| DiffError::Failed(format!("Failed to watch {}: {e}", root.display())));
+    lua_result(started, Ok)
+}
+
+/// Materializes the rows of one file of a lazy diff session.
+///
+/// `index` is 1-based, matching the session's `files` list. Returns the full file table,
+/// including `rows`, `hunk_starts` and `aligned_lines`, `nil` if the index is out of range, or
+/// `nil, err` if the file's content could not be read.
+fn file_rows(lua: &Lua, (id, index): (u64, usize)) -> LuaDiffResult<LuaValue> {
+    let session = session::get(id)
+        .ok_or_else(|| LuaError::RuntimeError(format!("No open diff session {id}")))?;
+    let Some(index) = index.checked_sub(1) else {
+        return Ok((None, None));
+    };
+    match session.file(&Runner::default(), index) {
+        Ok(Some(file)) => Ok((Some(file.clone().into_lua(lua)?), None)),
+        Ok(None) => Ok((None, None)),
+        Err(err) => Ok((None, Some(err))),
+    }
+}
+
+/// Closes a lazy diff session, releasing its cached rows.
+///
+/// Rows of a cached commit range stay available to later diffs of the same range.
+fn close_session(_: &Lua, id: u64) -> LuaResult<bool> {
+    Ok(session::close(id))
+}
+
+/// Configures the result cache.
+///
+/// Accepts `{ enabled = true, dir = nil, max_entries = 8 }`; omitted fields keep their
+/// defaults. Setting `dir` persists cached diffs to that directory.
+fn configure_cache(_: &Lua, opts: Option<LuaTable>) -> LuaResult<()> {
+    let defaults = cache::CacheOptions::default();
+    let options = match opts {
+        Some(opts) => cache::CacheOptions {
+            enabled: opts
+                .get::<Option<bool>>("enabled")?
+                .unwrap_or(defaults.enabled),
+            dir: opts.get::<Option<String>>("dir")?.map(PathBuf::from),
+            max_entries: opts
+                .get::<Option<usize>>("max_entries")?
+                .unwrap_or(defaults.max_entries),
+        },
+        None => defaults,
+    };
+    cache::configure(options);
+    Ok(())
+}
+
+/// Sets how git, jj and difftastic are run.
+///
+/// Accepts `{ git = nil, jj = nil, difft = nil, env = {}, cwd = nil }`: the executables
+/// to run instead of looking each up on `PATH`, extra environment variables for every
+/// command, and the directory to run them in. Omitted fields keep their defaults.
+fn configure_commands(_: &Lua, opts: Option<LuaTable>) -> LuaResult<()> {
+    let config = match opts {
+        Some(opts) => exec::CommandConfig {
+            git: opts.get::<Option<String>>("git")?.map(PathBuf::from),
+            jj: opts.get::<Option<String>>("jj")?.map(PathBuf::from),
+            difft: opts.get::<Option<String>>("difft")?.map(PathBuf::from),
+            env: opts
+                .get::<Option<HashMap<String, String>>>("env")?
+                .map(|env| env.into_iter().collect())
+                .unwrap_or_default(),
+            cwd: opts.get::<Option<String>>("cwd")?.map(PathBuf::from),
+        },
+        None => exec::CommandConfig::default(),
+    };
+    exec::configure(config);
+    Ok(())
+}
+
+/// Drops all cached diffs, including persisted ones.
+fn clear_cache(_: &Lua, (): ()) -> LuaResult<()> {
+    cache::clear();
+    Ok(())
 }
 
 /// Creates the Lua module exports. Called by mlua when loaded via `require("difftastic_nvim")`.
@@ -661,15 +1432,75 @@ fn difftastic_nvim(lua: &Lua) -> LuaResult<LuaTable> {
     let exports = lua.create_table()?;
     exports.set(
         "run_diff",
-        lua.create_function(|lua, args: (String, String)| run_diff(lua, args))?,
+        lua.create_function(|lua, args: (String, String, ProcessOptions)| run_diff(lua, args))?,
     )?;
     exports.set(
         "run_diff_unstaged",
-        lua.create_function(|lua, vcs: String| run_diff_unstaged(lua, vcs))?,
+        lua.create_function(|lua, args: (String, ProcessOptions)| run_diff_unstaged(lua, args))?,
     )?;
     exports.set(
         "run_diff_staged",
-        lua.create_function(|lua, vcs: String| run_diff_staged(lua, vcs))?,
+        lua.create_function(|lua, args: (String, ProcessOptions)| run_diff_staged(lua, args))?,
+    )?;
+    exports.set(
+        "diff_texts",
+        lua.create_function(|lua, args: (texts::Text, texts::Text, ProcessOptions)| {
+            diff_texts(lua, args)
+        })?,
+    )?;
+    exports.set(
+        "run_diff_paths",
+        lua.create_function(|lua, args: (String, String, ProcessOptions)| {
+            run_diff_paths(lua, args)
+        })?,
+    )?;
+    exports.set(
+        "run_diff_paths_async",
+        lua.create_function(|lua, args: (String, String, ProcessOptions)| {
+            run_diff_paths_async(lua, args)
+        })?,
+    )?;
+    exports.set(
+        "run_diff_async",
+        lua.create_function(|lua, args: (String, String, JobOptions)| run_diff_async(lua, args))?,
+    )?;
+    exports.set(
+        "run_diff_unstaged_async",
+        lua.create_function(|lua, args: (String, JobOptions)| run_diff_unstaged_async(lua, args))?,
+    )?;
+    exports.set(
+        "run_diff_staged_async",
+        lua.create_function(|lua, args: (String, JobOptions)| run_diff_staged_async(lua, args))?,
+    )?;
+    exports.set(
+        "refresh_paths",
+        lua.create_function(|lua, args: (String, Vec<String>, ProcessOptions)| {
+            refresh_paths(lua, args)
+        })?,
+    )?;
+    exports.set(
+        "watch",
+        lua.create_function(|lua, vcs: String| watch(lua, vcs))?,
+    )?;
+    exports.set(
+        "file_rows",
+        lua.create_function(|lua, args: (u64, usize)| file_rows(lua, args))?,
+    )?;
+    exports.set(
+        "close_session",
+        lua.create_function(|lua, id: u64| close_session(lua, id))?,
+    )?;
+    exports.set(
+        "configure_cache",
+        lua.create_function(|lua, opts: Option<LuaTable>| configure_cache(lua, opts))?,
+    )?;
+    exports.set(
+        "configure_commands",
+        lua.create_function(|lua, opts: Option<LuaTable>| configure_commands(lua, opts))?,
+    )?;
+    exports.set(
+        "clear_cache",
+        lua.create_function(|lua, args: ()| clear_cache(lua, args))?,
     )?;
     Ok(exports)
 }
@@ -678,6 +1509,30 @@ fn difftastic_nvim(lua: &Lua) -> LuaResult<LuaTable> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn difft_runs_from_path_unless_configured() {
+        assert_eq!(git_external_diff(None), "diff.external=difft");
+        assert_eq!(jj_difft_args(None), vec!["--tool", "difft"]);
+    }
+
+    #[test]
+    fn configured_difft_is_quoted_for_git_and_jj() {
+        let difft = Path::new("/nix/store/it's-difft/bin/difft");
+        assert_eq!(
+            git_external_diff(Some(difft)),
+            r"diff.external='/nix/store/it'\''s-difft/bin/difft'"
+        );
+        assert_eq!(
+            jj_difft_args(Some(Path::new(r#"C:\tools\"difft".exe"#))),
+            vec![
+                "--tool",
+                "difft",
+                "--config",
+                r#"merge-tools.difft.program="C:\\tools\\\"difft\".exe""#,
+            ]
+        );
+    }
+
     #[test]
     fn test_into_lines_with_content() {
         let lines = into_lines(Some("line1\nline2\nline3".to_string()));
@@ -698,21 +1553,21 @@ mod tests {
 
     #[test]
     fn test_parse_git_range_single_commit() {
-        let (old, new) = parse_git_range("abc123");
+        let (old, new) = parse_git_range(&Runner::default(), "abc123");
         assert_eq!(old, "abc123^");
         assert_eq!(new, "abc123");
     }
 
     #[test]
     fn test_parse_git_range_double_dot() {
-        let (old, new) = parse_git_range("main..feature");
+        let (old, new) = parse_git_range(&Runner::default(), "main..feature");
         assert_eq!(old, "main");
         assert_eq!(new, "feature");
     }
 
     #[test]
     fn test_parse_git_range_empty_left() {
-        let (old, new) = parse_git_range("..HEAD");
+        let (old, new) = parse_git_range(&Runner::default(), "..HEAD");
         assert_eq!(old, "");
         assert_eq!(new, "HEAD");
     }
@@ -878,4 +1733,100 @@ mod tests {
         );
         assert!(!renames.contains_key(Path::new("c.txt")));
     }
+
+    #[test]
+    fn test_parse_git_name_status_files() {
+        let files =
+            parse_git_name_status_files("M\tsrc/a.rs\nA\tb.rs\nD\tc.rs\nR090\told.rs\tnew.rs\n");
+        let entries: Vec<_> = files.iter().map(|f| (f.path.clone(), f.status)).collect();
+        assert_eq!(
+            entries,
+            vec![
+                (PathBuf::from("src/a.rs"), difftastic::Status::Changed),
+                (PathBuf::from("b.rs"), difftastic::Status::Created),
+                (PathBuf::from("c.rs"), difftastic::Status::Deleted),
+                (
+                    PathBuf::from("old.rs => new.rs"),
+                    difftastic::Status::Changed
+                ),
+            ]
+        );
+        assert!(files.iter().all(|f| f.chunks.is_empty()));
+
+        let mut renamed = files[3].clone();
+        let (_, old_path, new_path, moved_from) =
+            prepare_file_for_display(&mut renamed, &HashMap::new());
+        assert_eq!(
+            (old_path.as_path(), new_path.as_path()),
+            (Path::new("old.rs"), Path::new("new.rs"))
+        );
+        assert_eq!(moved_from, Some(PathBuf::from("old.rs")));
+    }
+
+    #[test]
+    fn test_parse_jj_summary_files() {
+        let files = parse_jj_summary_files(
+            "M src/a.rs\nA b.rs\nD c.rs\nR src/{old => new}.rs\nC {x => y}.rs\n",
+        );
+        let entries: Vec<_> = files.iter().map(|f| (f.path.clone(), f.status)).collect();
+        assert_eq!(
+            entries,
+            vec![
+                (PathBuf::from("src/a.rs"), difftastic::Status::Changed),
+                (PathBuf::from("b.rs"), difftastic::Status::Created),
+                (PathBuf::from("c.rs"), difftastic::Status::Deleted),
+                (
+                    PathBuf::from("src/{old => new}.rs"),
+                    difftastic::Status::Changed
+                ),
+                (PathBuf::from("y.rs"), difftastic::Status::Created),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_git_pathspecs_are_literal_and_root_relative() {
+        let pathspecs = git_pathspecs(&[PathBuf::from("src/*.rs")]);
+        assert_eq!(pathspecs, vec![":(top,literal)src/*.rs"]);
+        assert_eq!(
+            with_git_paths(vec!["--cached"], &pathspecs),
+            vec!["--cached", "--", ":(top,literal)src/*.rs"]
+        );
+        assert_eq!(with_git_paths(vec!["--cached"], &[]), vec!["--cached"]);
+    }
+
+    #[test]
+    fn test_jj_filesets_escape_quotes() {
+        assert_eq!(
+            jj_filesets(&[PathBuf::from("a \"b\".txt")]),
+            vec![r#"root-file:"a \"b\".txt""#]
+        );
+    }
+
+    #[test]
+    fn test_repo_relative_paths() {
+        let root = std::env::current_dir().unwrap();
+        let paths = repo_relative_paths(
+            &root,
+            &[
+                PathBuf::from("src/lib.rs"),
+                root.join("Cargo.toml"),
+                PathBuf::from("/definitely/not/in/the/repo.rs"),
+            ],
+        );
+        assert_eq!(
+            paths,
+            vec![PathBuf::from("src/lib.rs"), PathBuf::from("Cargo.toml")]
+        );
+    }
+
+    #[test]
+    fn test_repo_relative_paths_resolves_symlinked_roots() {
+        // `.` is not a prefix of any absolute path, so this goes through canonicalization.
+        let absolute = std::env::current_dir().unwrap().join("src/deleted.rs");
+        assert_eq!(
+            repo_relative_paths(Path::new("."), &[absolute]),
+            vec![PathBuf::from("src/deleted.rs")]
+        );
+    }
 }
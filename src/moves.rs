@@ -0,0 +1,336 @@
+//! Detection of code moved within or across files.
+//!
+//! difftastic reports moved code as a deletion in one place and an addition in another.
+//! [`detect_moves`] runs over the processed files of a diff and pairs blocks of removed
+//! lines with identical blocks of added lines, in the same file or another one. Both ends
+//! are tagged with a [`MovedBlock`] pointing at the other.
+//!
+//! ## Matching
+//!
+//! - Removed lines are the left sides of changed rows; added lines are the right sides.
+//! - Lines are compared with surrounding whitespace trimmed, so re-indented code still
+//!   counts as moved.
+//! - A block needs at least [`MIN_MOVED_LINES`] lines with some word characters, so
+//!   closing braces and blank lines alone never make a move.
+//! - Each line belongs to at most one move; longer matches are found first from the top
+//!   of each removed run.
+//! - Lines added more than [`MAX_OCCURRENCES`] times, as in generated code or lockfiles,
+//!   never start a move, and a block is at most [`MAX_MOVED_LINES`] lines, so matching
+//!   stays cheap on repetitive input.
+
+use crate::processor::{DisplayFile, LineKind, LineRange, MovedBlock};
+use std::collections::HashMap;
+
+/// Minimum number of substantive lines in a moved block.
+pub const MIN_MOVED_LINES: usize = 3;
+
+/// Most added occurrences of a line's text for it to start a move.
+pub const MAX_OCCURRENCES: usize = 16;
+
+/// Most lines in a single moved block; longer moves are reported as several blocks.
+pub const MAX_MOVED_LINES: usize = 1000;
+
+/// A changed line on one side of a file.
+struct Line<'a> {
+    file: usize,
+    row: u32,
+    number: u32,
+    text: &'a str,
+}
+
+/// Consecutive changed lines on one side: a removed or added run.
+type Run<'a> = Vec<Line<'a>>;
+
+/// A detected move: `len` lines of removed run `from.0` starting at `from.1`, found in
+/// added run `to.0` starting at `to.1`.
+struct Match {
+    from: (usize, usize),
+    to: (usize, usize),
+    len: usize,
+}
+
+/// Finds moved blocks across `files` and records them in each file's `moves`.
+///
+/// Moves recorded by an earlier pass are replaced.
+pub fn detect_moves(files: &mut [DisplayFile]) {
+    let matches = {
+        let removed = runs(files, LineKind::Removed);
+        let added = runs(files, LineKind::Added);
+        find_matches(&removed, &added)
+            .into_iter()
+            .map(|m| {
+                let from = &removed[m.from.0][m.from.1..m.from.1 + m.len];
+                let to = &added[m.to.0][m.to.1..m.to.1 + m.len];
+                (block_span(from), block_span(to))
+            })
+            .collect::<Vec<_>>()
+    };
+
+    for file in files.iter_mut() {
+        file.moves.clear();
+    }
+    for (from, to) in matches {
+        let from_path = files[from.file].path.clone();
+        let to_path = files[to.file].path.clone();
+        files[from.file].moves.push(MovedBlock {
+            kind: LineKind::Removed,
+            start: from.rows.0,
+            end: from.rows.1,
+            lines: from.lines,
+            counterpart_path: to_path,
+            counterpart_lines: to.lines,
+        });
+        files[to.file].moves.push(MovedBlock {
+            kind: LineKind::Added,
+            start: to.rows.0,
+            end: to.rows.1,
+            lines: to.lines,
+            counterpart_path: from_path,
+            counterpart_lines: from.lines,
+        });
+    }
+    for file in files.iter_mut() {
+        file.moves.sort_by_key(|block| block.start);
+    }
+}
+
+/// Where a matched block lies in its file.
+struct Span {
+    file: usize,
+    /// Rows, end exclusive.
+    rows: (u32, u32),
+    lines: LineRange,
+}
+
+fn block_span(lines: &[Line<'_>]) -> Span {
+    let (first, last) = (&lines[0], &lines[lines.len() - 1]);
+    Span {
+        file: first.file,
+        rows: (first.row, last.row + 1),
+        lines: LineRange {
+            start: first.number,
+            end: last.number + 1,
+        },
+    }
+}
+
+/// Collects the removed or added runs of every file.
+///
+/// A run ends at an unchanged row; filler rows on this side are skipped.
+fn runs(files: &[DisplayFile], kind: LineKind) -> Vec<Run<'_>> {
+    let mut runs: Vec<Run<'_>> = Vec::new();
+    for (file_index, file) in files.iter().enumerate() {
+        let mut current: Run<'_> = Vec::new();
+        for (row_index, row) in file.rows.iter().enumerate() {
+            if !row.is_changed() {
+                if !current.is_empty() {
+                    runs.push(std::mem::take(&mut current));
+                }
+                continue;
+            }
+            let (side, number) = match kind {
+                LineKind::Removed => (
+                    &row.left,
+                    file.aligned_lines.get(row_index).and_then(|l| l.0),
+                ),
+                _ => (
+                    &row.right,
+                    file.aligned_lines.get(row_index).and_then(|l| l.1),
+                ),
+            };
+            if side.is_filler {
+                continue;
+            }
+            current.push(Line {
+                file: file_index,
+                row: row_index as u32,
+                number: number.unwrap_or(row_index as u32),
+                text: side.content.trim(),
+            });
+        }
+        if !current.is_empty() {
+            runs.push(current);
+        }
+    }
+    runs
+}
+
+/// Returns `true` if a line can anchor a move on its own.
+fn is_substantive(text: &str) -> bool {
+    text.chars().filter(|c| c.is_alphanumeric()).count() >= 2
+}
+
+fn find_matches(removed: &[Run<'_>], added: &[Run<'_>]) -> Vec<Match> {
+    // Where each added line's text occurs.
+    let mut index: HashMap<&str, Vec<(usize, usize)>> = HashMap::new();
+    for (run, lines) in added.iter().enumerate() {
+        for (pos, line) in lines.iter().enumerate() {
+            if is_substantive(line.text) {
+                index.entry(line.text).or_default().push((run, pos));
+            }
+        }
+    }
+    index.retain(|_, occurrences| occurrences.len() <= MAX_OCCURRENCES);
+
+    let mut used_added: Vec<Vec<bool>> = added.iter().map(|run| vec![false; run.len()]).collect();
+    let mut matches = Vec::new();
+
+    for (run_index, run) in removed.iter().enumerate() {
+        let mut pos = 0;
+        while pos < run.len() {
+            // The longest move from `pos`, and the longest block rejected as changed in place.
+            let mut best: Option<(usize, usize, usize)> = None;
+            let mut rejected = 0;
+            for &(to_run, to_pos) in index.get(run[pos].text).into_iter().flatten() {
+                let target = &added[to_run];
+                let len = run[pos..]
+                    .iter()
+                    .zip(&target[to_pos..])
+                    .zip(&used_added[to_run][to_pos..])
+                    .take(MAX_MOVED_LINES)
+                    .take_while(|((from, to), used)| from.text == to.text && !**used)
+                    .count();
+                // The same row on both sides is a line changed in place, not moved.
+                let in_place = run[pos].file == target[to_pos].file
+                    && run[pos..pos + len]
+                        .iter()
+                        .zip(&target[to_pos..to_pos + len])
+                        .any(|(from, to)| from.row == to.row);
+                let substantive = run[pos..pos + len]
+                    .iter()
+                    .filter(|line| is_substantive(line.text))
+                    .count();
+                if in_place {
+                    rejected = rejected.max(len);
+                } else if substantive >= MIN_MOVED_LINES
+                    && best.is_none_or(|(_, _, best_len)| len >= best_len)
+                {
+                    best = Some((to_run, to_pos, len));
+                }
+            }
+
+            match best {
+                Some((to_run, to_pos, len)) => {
+                    used_added[to_run][to_pos..to_pos + len].fill(true);
+                    matches.push(Match {
+                        from: (run_index, pos),
+                        to: (to_run, to_pos),
+                        len,
+                    });
+                    pos += len;
+                }
+                None => pos += rejected.max(1),
+            }
+        }
+    }
+
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::difftastic::{DifftFile, Status};
+    use crate::processor::{ProcessOptions, process_file};
+    use std::path::PathBuf;
+
+    const BLOCK: [&str; 4] = ["fn helper() {", "    let a = 1;", "    a + 1", "}"];
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|l| l.to_string()).collect()
+    }
+
+    fn file(path: &str, status: Status, old: &[&str], new: &[&str]) -> DisplayFile {
+        let file = DifftFile {
+            path: path.into(),
+            language: "Rust".into(),
+            status,
+            aligned_lines: vec![],
+            chunks: vec![],
+        };
+        process_file(
+            file,
+            lines(old),
+            lines(new),
+            None,
+            &ProcessOptions::default(),
+        )
+    }
+
+    #[test]
+    fn moves_across_files_are_linked_both_ways() {
+        let mut files = vec![
+            file("a.rs", Status::Deleted, &BLOCK, &[]),
+            file(
+                "b.rs",
+                Status::Created,
+                &[],
+                &[&["// moved"][..], &BLOCK].concat(),
+            ),
+        ];
+        detect_moves(&mut files);
+
+        assert_eq!(files[0].moves.len(), 1);
+        let from = &files[0].moves[0];
+        assert_eq!(from.kind, LineKind::Removed);
+        assert_eq!((from.start, from.end), (0, 4));
+        assert_eq!(from.counterpart_path, PathBuf::from("b.rs"));
+        assert_eq!(from.counterpart_lines, LineRange { start: 1, end: 5 });
+
+        let to = &files[1].moves[0];
+        assert_eq!(to.kind, LineKind::Added);
+        assert_eq!(to.lines, LineRange { start: 1, end: 5 });
+        assert_eq!(to.counterpart_path, PathBuf::from("a.rs"));
+        assert_eq!(to.counterpart_lines, LineRange { start: 0, end: 4 });
+    }
+
+    #[test]
+    fn reindented_blocks_still_match() {
+        let indented: Vec<String> = BLOCK.iter().map(|l| format!("    {l}")).collect();
+        let indented: Vec<&str> = indented.iter().map(String::as_str).collect();
+        let mut files = vec![
+            file("a.rs", Status::Deleted, &BLOCK, &[]),
+            file("b.rs", Status::Created, &[], &indented),
+        ];
+        detect_moves(&mut files);
+        assert_eq!(files[1].moves.len(), 1);
+    }
+
+    #[test]
+    fn short_or_trivial_blocks_are_not_moves() {
+        let mut files = vec![
+            file("a.rs", Status::Deleted, &["let a = 1;", "}", "}"], &[]),
+            file("b.rs", Status::Created, &[], &["let a = 1;", "}", "}"]),
+        ];
+        detect_moves(&mut files);
+        assert!(files.iter().all(|f| f.moves.is_empty()));
+    }
+
+    #[test]
+    fn lines_repeated_too_often_do_not_start_moves() {
+        let repeated = vec!["value: 1,"; MAX_OCCURRENCES + 1];
+        let mut files = vec![
+            file("a.rs", Status::Deleted, &repeated, &[]),
+            file("b.rs", Status::Created, &[], &repeated),
+        ];
+        detect_moves(&mut files);
+        assert!(files.iter().all(|f| f.moves.is_empty()));
+    }
+
+    #[test]
+    fn long_moves_are_split_into_capped_blocks() {
+        let old: Vec<String> = (0..MAX_MOVED_LINES + 10)
+            .map(|i| format!("let x{i} = {i};"))
+            .collect();
+        let old: Vec<&str> = old.iter().map(String::as_str).collect();
+        let mut files = vec![
+            file("a.rs", Status::Deleted, &old, &[]),
+            file("b.rs", Status::Created, &[], &old),
+        ];
+        detect_moves(&mut files);
+
+        let blocks: Vec<_> = files[0].moves.iter().map(|m| (m.start, m.end)).collect();
+        let split = MAX_MOVED_LINES as u32;
+        assert_eq!(blocks, [(0, split), (split, split + 10)]);
+    }
+}
@@ -0,0 +1,342 @@
+//! In-process git access through libgit2.
+//!
+//! Enabled by the `native-git` cargo feature. Instead of spawning `git` for every
+//! query, refs, trees, blobs, the index and merge bases are read directly from the
+//! repository. Errors come back as [`git2::Error`] values carrying libgit2's error class
+//! and code rather than as scraped stderr.
+//!
+//! Running difftastic itself still goes through `git diff`, since difftastic is an
+//! external diff tool. Callers fall back to the `git` CLI whenever an operation here
+//! fails, so repositories libgit2 can't handle keep working.
+//!
+//! Diffs are described with the same arguments the CLI code passes to `git diff`
+//! (see [`DiffSpec::from_args`]), so both backends compare exactly the same trees.
+
+use crate::FileStats;
+use git2::{Diff, DiffFindOptions, Oid, Patch, Repository, Tree};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Opens the repository containing the directory commands run in (see
+/// [`crate::exec::working_dir`]).
+pub fn open() -> Result<Repository, git2::Error> {
+    open_at(&crate::exec::working_dir())
+}
+
+/// Opens the repository containing `path`.
+pub fn open_at(path: &Path) -> Result<Repository, git2::Error> {
+    Repository::discover(path)
+}
+
+/// Gets the repository's working directory.
+pub fn root(repo: &Repository) -> Result<PathBuf, git2::Error> {
+    repo.workdir()
+        .map(Path::to_path_buf)
+        .ok_or_else(|| git2::Error::from_str("repository has no working directory"))
+}
+
+/// Gets the merge-base of two revisions as a hex commit id.
+pub fn merge_base(repo: &Repository, a: &str, b: &str) -> Result<String, git2::Error> {
+    let a = commit_id(repo, a)?;
+    let b = commit_id(repo, b)?;
+    Ok(repo.merge_base(a, b)?.to_string())
+}
+
+/// Reads the blob named `name`: `rev:path` for a revision or `:path` for the index.
+///
+/// Returns `Ok(None)` if the path does not exist or does not name a blob.
+pub fn blob(repo: &Repository, name: &str) -> Result<Option<Vec<u8>>, git2::Error> {
+    let Some((rev, path)) = name.split_once(':') else {
+        return Err(git2::Error::from_str("expected `rev:path` or `:path`"));
+    };
+
+    let id = if rev.is_empty() {
+        let index = repo.index()?;
+        match index.get_path(Path::new(path), 0) {
+            Some(entry) => entry.id,
+            None => return Ok(None),
+        }
+    } else {
+        let tree = repo.revparse_single(rev)?.peel_to_tree()?;
+        match tree.get_path(Path::new(path)) {
+            Ok(entry) => entry.id(),
+            Err(e) if e.code() == git2::ErrorCode::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        }
+    };
+
+    match repo.find_blob(id) {
+        Ok(blob) => Ok(Some(blob.content().to_vec())),
+        Err(e) if e.code() == git2::ErrorCode::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// What a `git diff` invocation compares.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffSpec {
+    /// Two revisions (`old..new`).
+    Revisions { old: String, new: String },
+    /// The merge-base of two revisions against the second one (`a...b`).
+    MergeBase { a: String, b: String },
+    /// A revision against the working tree (`git diff <rev>`).
+    RevisionToWorkdir(String),
+    /// The index against the working tree (`git diff`).
+    IndexToWorkdir,
+    /// `HEAD` against the index (`git diff --cached`).
+    HeadToIndex,
+}
+
+impl DiffSpec {
+    /// Interprets the arguments the CLI code passes to `git diff`: nothing, `--cached`,
+    /// or a single revision or range.
+    pub fn from_args(args: &[&str]) -> Option<Self> {
+        match args {
+            [] => Some(Self::IndexToWorkdir),
+            ["--cached"] => Some(Self::HeadToIndex),
+            [range] if range.contains("...") => {
+                let (a, b) = range.split_once("...")?;
+                Some(Self::MergeBase {
+                    a: or_head(a).to_string(),
+                    b: or_head(b).to_string(),
+                })
+            }
+            [range] if range.contains("..") => {
+                let (old, new) = range.split_once("..")?;
+                Some(Self::Revisions {
+                    old: or_head(old).to_string(),
+                    new: or_head(new).to_string(),
+                })
+            }
+            [rev] if !rev.starts_with('-') => Some(Self::RevisionToWorkdir(rev.to_string())),
+            _ => None,
+        }
+    }
+
+    /// Computes the diff with rename detection, matching `git diff -M`.
+    fn diff<'r>(&self, repo: &'r Repository) -> Result<Diff<'r>, git2::Error> {
+        let mut diff = match self {
+            Self::Revisions { old, new } => {
+                repo.diff_tree_to_tree(Some(&tree(repo, old)?), Some(&tree(repo, new)?), None)?
+            }
+            Self::MergeBase { a, b } => {
+                let base = repo.merge_base(commit_id(repo, a)?, commit_id(repo, b)?)?;
+                let base = repo.find_commit(base)?.tree()?;
+                repo.diff_tree_to_tree(Some(&base), Some(&tree(repo, b)?), None)?
+            }
+            Self::RevisionToWorkdir(rev) => {
+                repo.diff_tree_to_workdir_with_index(Some(&tree(repo, rev)?), None)?
+            }
+            Self::IndexToWorkdir => repo.diff_index_to_workdir(None, None)?,
+            Self::HeadToIndex => {
+                let head = repo.head()?.peel_to_tree()?;
+                repo.diff_tree_to_index(Some(&head), None, None)?
+            }
+        };
+        diff.find_similar(Some(DiffFindOptions::new().renames(true)))?;
+        Ok(diff)
+    }
+}
+
+/// `git` treats an omitted side of a range as `HEAD`.
+fn or_head(rev: &str) -> &str {
+    if rev.is_empty() { "HEAD" } else { rev }
+}
+
+/// Resolves a revision to a commit id.
+pub fn commit_id(repo: &Repository, rev: &str) -> Result<Oid, git2::Error> {
+    Ok(repo.revparse_single(rev)?.peel_to_commit()?.id())
+}
+
+fn tree<'r>(repo: &'r Repository, rev: &str) -> Result<Tree<'r>, git2::Error> {
+    repo.revparse_single(rev)?.peel_to_tree()
+}
+
+/// Gets per-file line stats, like `git diff --numstat`. Binary files are skipped.
+pub fn diff_stats(repo: &Repository, spec: &DiffSpec) -> Result<FileStats, git2::Error> {
+    let diff = spec.diff(repo)?;
+    let mut stats = HashMap::new();
+    for idx in 0..diff.deltas().len() {
+        let Some(patch) = Patch::from_diff(&diff, idx)? else {
+            continue;
+        };
+        let delta = patch.delta();
+        if delta.flags().is_binary() {
+            continue;
+        }
+        let Some(path) = delta.new_file().path().or_else(|| delta.old_file().path()) else {
+            continue;
+        };
+        let (_, additions, deletions) = patch.line_stats()?;
+        stats.insert(
+            path.to_path_buf(),
+            (
+                u32::try_from(additions).unwrap_or(u32::MAX),
+                u32::try_from(deletions).unwrap_or(u32::MAX),
+            ),
+        );
+    }
+    Ok(stats)
+}
+
+/// Gets renamed files as a `new path -> old path` map, like `git diff --name-status -M`.
+pub fn rename_map(
+    repo: &Repository,
+    spec: &DiffSpec,
+) -> Result<HashMap<PathBuf, PathBuf>, git2::Error> {
+    let diff = spec.diff(repo)?;
+    Ok(diff
+        .deltas()
+        .filter(|delta| delta.status() == git2::Delta::Renamed)
+        .filter_map(|delta| {
+            let old_path = delta.old_file().path()?;
+            let new_path = delta.new_file().path()?;
+            Some((new_path.to_path_buf(), old_path.to_path_buf()))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    fn git(args: &[&str]) -> String {
+        let output = Command::new("git").args(args).output().unwrap();
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    }
+
+    #[test]
+    fn spec_from_args() {
+        assert_eq!(DiffSpec::from_args(&[]), Some(DiffSpec::IndexToWorkdir));
+        assert_eq!(
+            DiffSpec::from_args(&["--cached"]),
+            Some(DiffSpec::HeadToIndex)
+        );
+        assert_eq!(
+            DiffSpec::from_args(&["main..feature"]),
+            Some(DiffSpec::Revisions {
+                old: "main".to_string(),
+                new: "feature".to_string(),
+            })
+        );
+        assert_eq!(
+            DiffSpec::from_args(&["main..."]),
+            Some(DiffSpec::MergeBase {
+                a: "main".to_string(),
+                b: "HEAD".to_string(),
+            })
+        );
+        assert_eq!(
+            DiffSpec::from_args(&["HEAD"]),
+            Some(DiffSpec::RevisionToWorkdir("HEAD".to_string()))
+        );
+        assert_eq!(DiffSpec::from_args(&["--stat"]), None);
+    }
+
+    // Tests below run from the crate root, which is a git checkout.
+
+    #[test]
+    fn root_matches_git_cli() {
+        let repo = open().unwrap();
+        let root = root(&repo).unwrap();
+        assert_eq!(
+            root.canonicalize().unwrap(),
+            PathBuf::from(git(&["rev-parse", "--show-toplevel"]))
+                .canonicalize()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn blob_matches_git_show() {
+        let repo = open().unwrap();
+        let contents = blob(&repo, "HEAD:Cargo.toml").unwrap().unwrap();
+        assert_eq!(
+            String::from_utf8(contents).unwrap().trim(),
+            git(&["show", "HEAD:Cargo.toml"])
+        );
+        assert_eq!(blob(&repo, "HEAD:no/such/file.rs").unwrap(), None);
+        assert_eq!(blob(&repo, ":no/such/file.rs").unwrap(), None);
+    }
+
+    #[test]
+    fn merge_base_of_a_commit_with_itself() {
+        let repo = open().unwrap();
+        assert_eq!(
+            merge_base(&repo, "HEAD", "HEAD").unwrap(),
+            git(&["rev-parse", "HEAD"])
+        );
+    }
+
+    /// Creates a throwaway repository with two commits: `a.txt` is edited and `b.txt`
+    /// is renamed to `c.txt`.
+    fn fixture() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "difftastic-nvim-native-git-{}-{:?}",
+            std::process::id(),
+            std::thread::current().id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let run = |args: &[&str]| {
+            let status = Command::new("git")
+                .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+                .args(args)
+                .current_dir(&dir)
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "git {args:?} failed");
+        };
+        run(&["init", "-q"]);
+        std::fs::write(dir.join("a.txt"), "one\ntwo\nthree\n").unwrap();
+        std::fs::write(dir.join("b.txt"), "some\nfile\ncontents\n").unwrap();
+        run(&["add", "."]);
+        run(&["commit", "-q", "-m", "first"]);
+        std::fs::write(dir.join("a.txt"), "one\n2\nthree\nfour\n").unwrap();
+        run(&["mv", "b.txt", "c.txt"]);
+        run(&["commit", "-q", "-am", "second"]);
+        dir
+    }
+
+    #[test]
+    fn stats_and_renames_between_revisions() {
+        let dir = fixture();
+        let repo = open_at(&dir).unwrap();
+        let spec = DiffSpec::from_args(&["HEAD~1..HEAD"]).unwrap();
+
+        let stats = diff_stats(&repo, &spec).unwrap();
+        assert_eq!(stats.get(Path::new("a.txt")), Some(&(2, 1)));
+        assert_eq!(stats.get(Path::new("c.txt")), Some(&(0, 0)));
+
+        let renames = rename_map(&repo, &spec).unwrap();
+        assert_eq!(
+            renames,
+            HashMap::from([(PathBuf::from("c.txt"), PathBuf::from("b.txt"))])
+        );
+
+        assert_eq!(
+            blob(&repo, "HEAD~1:b.txt").unwrap().as_deref(),
+            Some(&b"some\nfile\ncontents\n"[..])
+        );
+        assert_eq!(
+            blob(&repo, ":a.txt").unwrap().as_deref(),
+            Some(&b"one\n2\nthree\nfour\n"[..])
+        );
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn identical_revisions_have_no_stats() {
+        let repo = open().unwrap();
+        let spec = DiffSpec::Revisions {
+            old: "HEAD".to_string(),
+            new: "HEAD".to_string(),
+        };
+        assert!(diff_stats(&repo, &spec).unwrap().is_empty());
+        assert!(rename_map(&repo, &spec).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,528 @@
+//! Diffs of two files or directories outside any VCS.
+//!
+//! [`diff_paths`] compares two paths like `difft lhs rhs` does, but produces the same
+//! [`DisplayFile`] list as a git or jj diff:
+//!
+//! - Two files are a single entry, reported under the right-hand path. If the two file
+//!   names differ, the left-hand path is its `moved_from`.
+//! - Two directories are walked recursively and their files paired by relative path. Files
+//!   with identical contents are left out, files only on the left are deleted and files
+//!   only on the right are created. `.git` and `.jj` directories and symlinked directories
+//!   are not walked.
+//! - A deleted and a created file with identical contents, or with the same extension and
+//!   at least [`RENAME_SIMILARITY`] of their lines in common, are paired as a rename: they
+//!   are diffed against each other and reported under the new path, with the old one as
+//!   `moved_from`.
+//!
+//! difftastic runs on each pair directly (see [`crate::direct`]), in parallel. As with
+//! [`ProcessOptions::direct_difft`], a file it fails on keeps the error in
+//! [`DisplayFile::error`] instead of failing the whole diff.
+
+use crate::difftastic::Status;
+use crate::direct;
+use crate::error::DiffError;
+use crate::exec::Runner;
+use crate::moves;
+use crate::processor::{DisplayFile, ProcessOptions};
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Share of lines a deleted and a created file must have in common to be a rename.
+pub const RENAME_SIMILARITY: f64 = 0.5;
+
+/// Most deleted or created files compared line by line for renames. Beyond it, only files
+/// with identical contents are paired.
+pub const MAX_RENAME_CANDIDATES: usize = 1000;
+
+/// Directories that are never walked.
+const SKIPPED_DIRS: &[&str] = &[".git", ".jj"];
+
+/// A file to diff: where it is reported and its two sides on disk.
+#[derive(Debug, PartialEq, Eq)]
+struct Pair {
+    path: PathBuf,
+    moved_from: Option<PathBuf>,
+    /// `None` for a created file.
+    old: Option<PathBuf>,
+    /// `None` for a deleted file.
+    new: Option<PathBuf>,
+}
+
+/// Diffs `lhs` against `rhs`, two files or two directories, and processes the result.
+///
+/// Files are ordered by path. With [`ProcessOptions::detect_moves`], moves are detected
+/// across files too. Once the runner is cancelled, no further pairs are diffed and the
+/// diff fails with [`DiffError::Cancelled`].
+pub fn diff_paths(
+    runner: &Runner,
+    lhs: &Path,
+    rhs: &Path,
+    options: &ProcessOptions,
+) -> Result<Vec<DisplayFile>, DiffError> {
+    let pairs = pair_paths(lhs, rhs)?;
+    let per_file = options.without_moves();
+    let mut files = pairs
+        .par_iter()
+        .map(|pair| {
+            if runner.is_cancelled() {
+                return Err(DiffError::Cancelled);
+            }
+            diff_pair(runner, pair, &per_file)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if options.detect_moves {
+        moves::detect_moves(&mut files);
+    }
+    Ok(files)
+}
+
+fn read_failed(path: &Path, error: std::io::Error) -> DiffError {
+    DiffError::Failed(format!("Failed to read {}: {error}", path.display()))
+}
+
+/// Reads a file, replacing invalid UTF-8 like the VCS content readers do.
+fn read_text(path: &Path) -> Result<String, DiffError> {
+    fs::read(path)
+        .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+        .map_err(|e| read_failed(path, e))
+}
+
+/// Pairs up the files to diff.
+fn pair_paths(lhs: &Path, rhs: &Path) -> Result<Vec<Pair>, DiffError> {
+    let lhs_dir = fs::metadata(lhs).map_err(|e| read_failed(lhs, e))?.is_dir();
+    let rhs_dir = fs::metadata(rhs).map_err(|e| read_failed(rhs, e))?.is_dir();
+
+    match (lhs_dir, rhs_dir) {
+        (false, false) => Ok(vec![Pair {
+            path: rhs.to_path_buf(),
+            moved_from: (lhs.file_name() != rhs.file_name()).then(|| lhs.to_path_buf()),
+            old: Some(lhs.to_path_buf()),
+            new: Some(rhs.to_path_buf()),
+        }]),
+        (true, true) => pair_dirs(lhs, rhs),
+        _ => Err(DiffError::Failed(format!(
+            "Cannot compare a file with a directory: {} and {}",
+            lhs.display(),
+            rhs.display()
+        ))),
+    }
+}
+
+/// Pairs the files of two directories by relative path, then pairs up renames among the
+/// rest.
+fn pair_dirs(lhs: &Path, rhs: &Path) -> Result<Vec<Pair>, DiffError> {
+    let old_files = walk(lhs)?;
+    let new_files = walk(rhs)?;
+    let old_set: HashSet<&PathBuf> = old_files.iter().collect();
+    let new_set: HashSet<&PathBuf> = new_files.iter().collect();
+
+    let mut pairs = Vec::new();
+    let mut deleted = Vec::new();
+    for path in &old_files {
+        if !new_set.contains(path) {
+            deleted.push(path.clone());
+        } else if !same_contents(&lhs.join(path), &rhs.join(path)) {
+            pairs.push(Pair {
+                path: path.clone(),
+                moved_from: None,
+                old: Some(lhs.join(path)),
+                new: Some(rhs.join(path)),
+            });
+        }
+    }
+    let created: Vec<PathBuf> = new_files
+        .iter()
+        .filter(|path| !old_set.contains(path))
+        .cloned()
+        .collect();
+
+    let renames = find_renames(lhs, rhs, &deleted, &created);
+    let renamed_old: HashSet<usize> = renames.iter().map(|&(old, _)| old).collect();
+    let renamed_new: HashSet<usize> = renames.iter().map(|&(_, new)| new).collect();
+
+    pairs.extend(renames.into_iter().map(|(old, new)| Pair {
+        path: created[new].clone(),
+        moved_from: Some(deleted[old].clone()),
+        old: Some(lhs.join(&deleted[old])),
+        new: Some(rhs.join(&created[new])),
+    }));
+    pairs.extend(
+        deleted
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !renamed_old.contains(index))
+            .map(|(_, path)| Pair {
+                path: path.clone(),
+                moved_from: None,
+                old: Some(lhs.join(path)),
+                new: None,
+            }),
+    );
+    pairs.extend(
+        created
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !renamed_new.contains(index))
+            .map(|(_, path)| Pair {
+                path: path.clone(),
+                moved_from: None,
+                old: None,
+                new: Some(rhs.join(path)),
+            }),
+    );
+
+    pairs.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(pairs)
+}
+
+/// Lists the files below `root`, relative to it and sorted.
+fn walk(root: &Path) -> Result<Vec<PathBuf>, DiffError> {
+    let mut files = Vec::new();
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(dir) = dirs.pop() {
+        let absolute = root.join(&dir);
+        let entries = fs::read_dir(&absolute).map_err(|e| read_failed(&absolute, e))?;
+        for entry in entries {
+            let entry = entry.map_err(|e| read_failed(&absolute, e))?;
+            let file_type = entry
+                .file_type()
+                .map_err(|e| read_failed(&entry.path(), e))?;
+            let relative = dir.join(entry.file_name());
+            if file_type.is_dir() {
+                if !SKIPPED_DIRS
+                    .iter()
+                    .any(|skipped| entry.file_name() == *skipped)
+                {
+                    dirs.push(relative);
+                }
+            } else if file_type.is_file() || (file_type.is_symlink() && entry.path().is_file()) {
+                files.push(relative);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Returns `true` if both files can be read and have the same contents.
+fn same_contents(a: &Path, b: &Path) -> bool {
+    let same_len = match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.len() == b.len(),
+        _ => false,
+    };
+    same_len && matches!((fs::read(a), fs::read(b)), (Ok(a), Ok(b)) if a == b)
+}
+
+/// Pairs deleted files with the created files they were most likely renamed to.
+///
+/// Returns `(deleted index, created index)` pairs. Files with identical contents pair up
+/// first, in order. Then each remaining deleted file, in order, takes the most similar
+/// created file not taken yet. Only files with the same extension and line counts close
+/// enough to reach [`RENAME_SIMILARITY`] are compared. Empty files are never renames.
+fn find_renames(
+    lhs: &Path,
+    rhs: &Path,
+    deleted: &[PathBuf],
+    created: &[PathBuf],
+) -> Vec<(usize, usize)> {
+    if deleted.is_empty() || created.is_empty() {
+        return Vec::new();
+    }
+    let read_all = |root: &Path, paths: &[PathBuf]| -> Vec<String> {
+        paths
+            .par_iter()
+            .map(|path| read_text(&root.join(path)).unwrap_or_default())
+            .collect()
+    };
+    let old = read_all(lhs, deleted);
+    let new = read_all(rhs, created);
+
+    let mut taken = vec![false; new.len()];
+    let mut renames = Vec::new();
+
+    // Created files by contents, each list in reverse so popping takes the first.
+    let mut identical: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (new_index, new_text) in new.iter().enumerate().rev() {
+        if !new_text.is_empty() {
+            identical.entry(new_text).or_default().push(new_index);
+        }
+    }
+    let mut unmatched = Vec::new();
+    for (old_index, old_text) in old.iter().enumerate() {
+        if old_text.is_empty() {
+            continue;
+        }
+        match identical.get_mut(old_text.as_str()).and_then(Vec::pop) {
+            Some(new_index) => {
+                taken[new_index] = true;
+                renames.push((old_index, new_index));
+            }
+            None => unmatched.push(old_index),
+        }
+    }
+
+    if deleted.len() > MAX_RENAME_CANDIDATES || created.len() > MAX_RENAME_CANDIDATES {
+        return renames;
+    }
+    let new_lines: Vec<LineCounts<'_>> = new.iter().map(|text| LineCounts::new(text)).collect();
+    for old_index in unmatched {
+        let old_lines = LineCounts::new(&old[old_index]);
+        let extension = deleted[old_index].extension();
+        let best = new_lines
+            .iter()
+            .enumerate()
+            .filter(|&(new_index, new_lines)| {
+                !taken[new_index]
+                    && new_lines.total > 0
+                    && created[new_index].extension() == extension
+                    && could_be_similar(&old_lines, new_lines)
+            })
+            .filter_map(|(new_index, new_lines)| {
+                let score = similarity(&old_lines, new_lines);
+                (score >= RENAME_SIMILARITY).then_some((new_index, score))
+            })
+            // The first of equally similar files wins.
+            .max_by(|a, b| a.1.total_cmp(&b.1).then(b.0.cmp(&a.0)));
+        if let Some((new_index, _)) = best {
+            taken[new_index] = true;
+            renames.push((old_index, new_index));
+        }
+    }
+    renames
+}
+
+/// The lines of a text and how often each occurs.
+struct LineCounts<'a> {
+    counts: HashMap<&'a str, usize>,
+    total: usize,
+}
+
+impl<'a> LineCounts<'a> {
+    fn new(text: &'a str) -> Self {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        let mut total = 0;
+        for line in text.lines() {
+            *counts.entry(line).or_default() += 1;
+            total += 1;
+        }
+        Self { counts, total }
+    }
+}
+
+/// Returns `false` if the line counts alone rule out reaching [`RENAME_SIMILARITY`]: at
+/// best every line of the shorter text is common.
+fn could_be_similar(a: &LineCounts<'_>, b: &LineCounts<'_>) -> bool {
+    let (shorter, longer) = (a.total.min(b.total), a.total.max(b.total));
+    (2 * shorter) as f64 >= RENAME_SIMILARITY * (shorter + longer) as f64
+}
+
+/// Share of lines two texts have in common: twice the common lines over all lines.
+fn similarity(a: &LineCounts<'_>, b: &LineCounts<'_>) -> f64 {
+    let total = a.total + b.total;
+    if total == 0 {
+        return 0.0;
+    }
+    let (fewer, more) = if a.counts.len() <= b.counts.len() {
+        (a, b)
+    } else {
+        (b, a)
+    };
+    let common: usize = fewer
+        .counts
+        .iter()
+        .map(|(line, &count)| count.min(more.counts.get(line).copied().unwrap_or(0)))
+        .sum();
+    (2 * common) as f64 / total as f64
+}
+
+/// Runs difftastic on a pair and processes it.
+fn diff_pair(
+    runner: &Runner,
+    pair: &Pair,
+    options: &ProcessOptions,
+) -> Result<DisplayFile, DiffError> {
+    let status = match (&pair.old, &pair.new) {
+        (None, _) => Status::Created,
+        (_, None) => Status::Deleted,
+        _ => Status::Changed,
+    };
+    let read = |path: &Option<PathBuf>| path.as_deref().map(read_text).transpose();
+
+    let diffed = read(&pair.old)
+        .and_then(|old| Ok((old, read(&pair.new)?)))
+        .and_then(|(old, new)| {
+            let file = direct::diff_files(
+                runner,
+                &pair.path,
+                pair.old.as_deref(),
+                pair.new.as_deref(),
+                options,
+            )?;
+            Ok((file, old, new))
+        });
+    let (file, old, new, error) = match diffed {
+        Ok((file, old, new)) => (file, old, new, None),
+        Err(e) if direct::is_fatal(&e) => return Err(e),
+        Err(e) => (
+            crate::placeholder_file(pair.path.clone(), status),
+            None,
+            None,
+            Some(e.to_string()),
+        ),
+    };
+
+    let mut display = direct::process(file, old, new, options);
+    display.moved_from = pair.moved_from.clone();
+    display.error = error;
+    Ok(display)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::direct::TempDir;
+
+    /// Creates a temporary directory holding `files`, removed when dropped.
+    fn tree(files: &[(&str, &str)]) -> TempDir {
+        let root = TempDir::new().unwrap();
+        for (path, contents) in files {
+            let path = root.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        root
+    }
+
+    fn summary(pairs: &[Pair]) -> Vec<(&str, Option<&str>, bool, bool)> {
+        pairs
+            .iter()
+            .map(|pair| {
+                (
+                    pair.path.to_str().unwrap(),
+                    pair.moved_from.as_deref().and_then(Path::to_str),
+                    pair.old.is_some(),
+                    pair.new.is_some(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn directories_pair_by_relative_path() {
+        let lhs = tree(&[
+            ("same.txt", "same\n"),
+            ("src/changed.rs", "fn a() {}\n"),
+            ("gone.txt", "gone\n"),
+            (".git/HEAD", "ref: refs/heads/main\n"),
+        ]);
+        let rhs = tree(&[
+            ("same.txt", "same\n"),
+            ("src/changed.rs", "fn b() {}\n"),
+            ("src/new.rs", "fn new() {}\n"),
+        ]);
+
+        let pairs = pair_paths(lhs.path(), rhs.path()).unwrap();
+        assert_eq!(
+            summary(&pairs),
+            vec![
+                ("gone.txt", None, true, false),
+                ("src/changed.rs", None, true, true),
+                ("src/new.rs", None, false, true),
+            ]
+        );
+        assert_eq!(pairs[1].old, Some(lhs.path().join("src/changed.rs")));
+    }
+
+    #[test]
+    fn similar_files_pair_as_renames() {
+        let lhs = tree(&[
+            ("old.rs", "a\nb\nc\nd\n"),
+            ("unrelated.rs", "w\nx\ny\nz\n"),
+            ("empty.rs", ""),
+        ]);
+        let rhs = tree(&[
+            ("new.rs", "a\nb\nc\nchanged\n"),
+            ("other.rs", "1\n2\n3\n4\n"),
+            ("also_empty.rs", ""),
+        ]);
+
+        let pairs = pair_paths(lhs.path(), rhs.path()).unwrap();
+        assert_eq!(
+            summary(&pairs),
+            vec![
+                ("also_empty.rs", None, false, true),
+                ("empty.rs", None, true, false),
+                ("new.rs", Some("old.rs"), true, true),
+                ("other.rs", None, false, true),
+                ("unrelated.rs", None, true, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn two_files_are_one_entry() {
+        let root = tree(&[("a.toml", "x = 1\n"), ("b.toml", "x = 2\n")]);
+        let pairs = pair_paths(&root.path().join("a.toml"), &root.path().join("b.toml")).unwrap();
+        assert_eq!(pairs.len(), 1);
+        assert_eq!(pairs[0].path, root.path().join("b.toml"));
+        assert_eq!(pairs[0].moved_from, Some(root.path().join("a.toml")));
+
+        let other = tree(&[("b.toml", "x = 3\n")]);
+        let pairs = pair_paths(&root.path().join("b.toml"), &other.path().join("b.toml")).unwrap();
+        assert_eq!(pairs[0].moved_from, None);
+
+        assert!(pair_paths(&root.path().join("a.toml"), root.path()).is_err());
+        assert!(pair_paths(&root.path().join("missing"), root.path()).is_err());
+    }
+
+    #[test]
+    fn cancelled_diffs_stop_before_running_difft() {
+        let lhs = tree(&[("a.txt", "one\n")]);
+        let rhs = tree(&[("a.txt", "two\n")]);
+        let cancel = crate::exec::CancelToken::default();
+        cancel.cancel();
+
+        let result = diff_paths(
+            &Runner::new(cancel),
+            lhs.path(),
+            rhs.path(),
+            &ProcessOptions::default(),
+        );
+        assert_eq!(result, Err(DiffError::Cancelled));
+    }
+
+    #[test]
+    fn similarity_counts_common_lines() {
+        let similarity = |a, b| similarity(&LineCounts::new(a), &LineCounts::new(b));
+        assert_eq!(similarity("a\nb\n", "a\nb\n"), 1.0);
+        assert_eq!(similarity("a\nb\n", "a\nc\n"), 0.5);
+        assert_eq!(similarity("a\na\n", "a\n"), 2.0 / 3.0);
+        assert_eq!(similarity("", ""), 0.0);
+    }
+
+    #[test]
+    fn line_counts_rule_out_dissimilar_files() {
+        let could_be_similar = |a, b| could_be_similar(&LineCounts::new(a), &LineCounts::new(b));
+        assert!(could_be_similar("a\n", "a\nb\nc\n"));
+        assert!(!could_be_similar("a\n", "a\nb\nc\nd\n"));
+    }
+
+    #[test]
+    fn renames_need_identical_contents_or_the_same_extension() {
+        let lhs = tree(&[("copy.txt", "same\n"), ("lib.rs", "a\nb\nc\nd\n")]);
+        let rhs = tree(&[("copy.md", "same\n"), ("lib.py", "a\nb\nc\nchanged\n")]);
+
+        let pairs = pair_paths(lhs.path(), rhs.path()).unwrap();
+        assert_eq!(
+            summary(&pairs),
+            vec![
+                ("copy.md", Some("copy.txt"), true, true),
+                ("lib.py", None, false, true),
+                ("lib.rs", None, true, false),
+            ]
+        );
+    }
+}
@@ -10,6 +10,17 @@
 //! 2. For created/deleted files, all lines are treated as additions/deletions
 //! 3. For changed files, the pre-computed `aligned_lines` from difftastic guides row alignment
 //! 4. Highlights are computed by analyzing the change regions and merging adjacent regions
+//! 5. For files difftastic diffed line by line, fully changed line pairs are refined to
+//!    the words that actually differ (see [`refine_changed_line`])
+//! 6. With [`Layout::Unified`], the side-by-side rows are also flattened into a single
+//!    column of context, removed and added lines (see [`unified_layout`])
+//! 7. With [`ProcessOptions::context_lines`], unchanged runs further than that from any
+//!    change are described as [`Fold`]s that the viewer collapses
+//! 8. With [`ProcessOptions::detect_moves`], blocks of removed lines that reappear as added
+//!    lines are tagged as [`MovedBlock`]s (see [`crate::moves`])
+//! 9. With [`ProcessOptions::ignore_whitespace`], rows that differ only in whitespace are
+//!    shown as unchanged, and files with nothing else changed are marked
+//!    [`DisplayFile::whitespace_only`]
 //!
 //! ## Highlight Strategy
 //!
@@ -19,25 +30,45 @@
 //!   cover all non-whitespace content
 //! - Partial highlight: Used when only specific regions of a line changed, showing
 //!   exactly which characters differ
-//! - Merged regions: Adjacent change regions separated only by whitespace are merged
-//!   for cleaner visual presentation
-
-use crate::difftastic::{Change, Chunk, DifftFile, Status};
+//! - Merged regions: Overlapping and touching change regions are merged, keeping the first
+//!   region's token class
+//! - Token regions: With [`ProcessOptions::token_regions`], nothing is merged and each
+//!   region is exactly one change reported by difftastic
+//! - Token classes: Partial regions carry difftastic's token class (keyword, string,
+//!   comment, ...) and the changed text, so the renderer can style them without a
+//!   treesitter parser for the language
+//!
+//! ## Columns
+//!
+//! Region columns are byte offsets into the line, which is what Neovim's extmarks take.
+//! Offsets are clamped to the line and widened to whole characters, so a region never
+//! splits a multibyte character. With [`DifftOptions::tab_width`], token regions also
+//! carry display columns (see [`display_column`]) for consumers that position by screen
+//! cell.
+
+use crate::difftastic::{Change, Chunk, DifftFile, DifftOptions, Highlight, Status};
+use crate::{moves, scope};
 use mlua::prelude::*;
+use serde::{Deserialize, Serialize};
 use smallvec::SmallVec;
 use std::collections::HashMap;
 use std::path::PathBuf;
+use unicode_width::UnicodeWidthChar;
 
 /// Most lines have 0-2 highlight regions; inline storage avoids heap allocation.
 type Highlights = SmallVec<[HighlightRegion; 2]>;
 
+/// Upper bound on the token comparison table when refining a line pair. Longer lines keep
+/// difftastic's whole-line changes.
+const MAX_REFINE_CELLS: usize = 250_000;
+
 /// A highlight region within a line, specified by column range.
 ///
 /// Represents a contiguous span of characters that should be highlighted
 /// in the diff viewer to indicate changes.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct HighlightRegion {
-    /// Start column (0-indexed, inclusive).
+    /// Start byte column (0-indexed, inclusive), on a character boundary.
     pub start: u32,
 
     /// End column (exclusive), or -1 to indicate full-line highlight.
@@ -46,6 +77,17 @@ pub struct HighlightRegion {
     /// when the entire line should be highlighted without needing to know
     /// the actual line length.
     pub end: i32,
+
+    /// difftastic's token class for the changed text; `None` for full-line regions.
+    pub kind: Option<Highlight>,
+
+    /// The changed text; `None` for full-line regions.
+    pub text: Option<String>,
+
+    /// Display columns `(start, end)` of a token region, when
+    /// [`DifftOptions::tab_width`] is set.
+    #[serde(default)]
+    pub display: Option<(u32, u32)>,
 }
 
 impl HighlightRegion {
@@ -53,16 +95,25 @@ impl HighlightRegion {
     #[inline]
     #[must_use]
     fn full_line() -> Self {
-        Self { start: 0, end: -1 }
+        Self {
+            start: 0,
+            end: -1,
+            kind: None,
+            text: None,
+            display: None,
+        }
     }
 
-    /// Creates a highlight region for a specific column range.
+    /// Creates a highlight region for a changed token spanning a column range.
     #[inline]
     #[must_use]
-    fn columns(start: u32, end: u32) -> Self {
+    fn token(start: u32, end: u32, kind: Highlight, text: String) -> Self {
         Self {
             start,
             end: i32::try_from(end).unwrap_or(i32::MAX),
+            kind: Some(kind),
+            text: Some(text),
+            display: None,
         }
     }
 }
@@ -71,7 +122,7 @@ impl HighlightRegion {
 ///
 /// Contains the line content, whether it's a filler (placeholder) line,
 /// and the regions to highlight within the line.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Side {
     /// The text content of this line.
     ///
@@ -134,20 +185,38 @@ impl Side {
 /// - Left with content, right filler: A deleted line
 /// - Left filler, right with content: An added line
 /// - Both unchanged: Context line (no highlights)
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Row {
     /// The left side (old/before version) of this row.
     pub left: Side,
 
     /// The right side (new/after version) of this row.
     pub right: Side,
+
+    /// The row's only change is whitespace, which [`ProcessOptions::ignore_whitespace`]
+    /// hides: its highlights are cleared and it counts as unchanged.
+    #[serde(default)]
+    pub whitespace_only: bool,
+}
+
+impl Row {
+    /// Returns `true` if the row is part of a hunk: a line on either side is missing or
+    /// has changes, and the change is not [`whitespace_only`](Self::whitespace_only).
+    #[must_use]
+    pub fn is_changed(&self) -> bool {
+        !self.whitespace_only
+            && (self.left.is_filler
+                || self.right.is_filler
+                || !self.left.highlights.is_empty()
+                || !self.right.highlights.is_empty())
+    }
 }
 
 /// A processed file ready for display in the diff viewer.
 ///
 /// Contains all the information needed to render a file's diff in Neovim:
 /// file metadata, the aligned rows for display, and navigation aids.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct DisplayFile {
     pub path: PathBuf,
 
@@ -176,11 +245,257 @@ pub struct DisplayFile {
     /// Used for navigation commands like "jump to next hunk".
     pub hunk_starts: Vec<u32>,
 
+    /// Each hunk of `rows`, in order; `hunks[i].start == hunk_starts[i]`.
+    #[serde(default)]
+    pub hunks: Vec<Hunk>,
+
     /// Original line number mapping: `(left_line, right_line)` for each display row.
     ///
     /// `None` means filler line. Line numbers are 0-indexed into the source file.
     /// Used for "goto file" navigation to jump from diff view to actual file location.
     pub aligned_lines: Vec<(Option<u32>, Option<u32>)>,
+
+    /// The single-column layout of `rows`, when [`Layout::Unified`] was requested.
+    #[serde(default)]
+    pub unified: Option<UnifiedLayout>,
+
+    /// Unchanged runs of `rows` to collapse, when [`ProcessOptions::context_lines`] is set.
+    #[serde(default)]
+    pub folds: Vec<Fold>,
+
+    /// Blocks of `rows` moved from or to elsewhere, when [`ProcessOptions::detect_moves`]
+    /// is set; ordered by row.
+    #[serde(default)]
+    pub moves: Vec<MovedBlock>,
+
+    /// With [`ProcessOptions::ignore_whitespace`], the file's content changed only in
+    /// whitespace.
+    #[serde(default)]
+    pub whitespace_only: bool,
+
+    /// Why difftastic failed on this file, with [`ProcessOptions::direct_difft`]. Changed
+    /// files then have no rows; other files of the diff are unaffected.
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// One end of a block of lines that moved, within the file or from another one.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MovedBlock {
+    /// [`LineKind::Removed`] for the block's old location (left side), or
+    /// [`LineKind::Added`] for its new location (right side).
+    pub kind: LineKind,
+
+    /// First row of the block (0-indexed, inclusive).
+    pub start: u32,
+
+    /// Row after the last row of the block (exclusive).
+    pub end: u32,
+
+    /// The block's lines on its side.
+    pub lines: LineRange,
+
+    /// File at the other end of the move; the same file for moves within it.
+    pub counterpart_path: PathBuf,
+
+    /// The block's lines at the other end: new-version lines for a removed block,
+    /// old-version lines for an added one.
+    pub counterpart_lines: LineRange,
+}
+
+/// A range of source lines (0-indexed, end exclusive).
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LineRange {
+    pub start: u32,
+    pub end: u32,
+}
+
+/// A run of consecutive changed rows, with what the UI needs to describe it.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Hunk {
+    /// First row of the hunk (0-indexed, inclusive).
+    pub start: u32,
+
+    /// Row after the last row of the hunk (exclusive).
+    pub end: u32,
+
+    /// Old-version lines shown in the hunk; `None` if it only adds lines.
+    pub left: Option<LineRange>,
+
+    /// New-version lines shown in the hunk; `None` if it only removes lines.
+    pub right: Option<LineRange>,
+
+    /// Number of new-version lines in the hunk.
+    pub additions: u32,
+
+    /// Number of old-version lines in the hunk.
+    pub deletions: u32,
+
+    /// Token classes of the hunk's changed regions, most frequent first.
+    pub kinds: Vec<Highlight>,
+
+    /// Header of the definition enclosing the hunk in the old version, if found.
+    pub left_scope: Option<String>,
+
+    /// Header of the definition enclosing the hunk in the new version, if found.
+    pub right_scope: Option<String>,
+}
+
+/// A run of unchanged rows that can be collapsed, by row index (0-indexed).
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Fold {
+    /// First folded row (inclusive).
+    pub start: u32,
+
+    /// Row after the last folded row (exclusive).
+    pub end: u32,
+}
+
+/// How a unified row relates to the old and new versions of a file.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LineKind {
+    /// Unchanged; present in both versions.
+    Context,
+    /// Only in the new version.
+    Added,
+    /// Only in the old version.
+    Removed,
+}
+
+impl LineKind {
+    /// The kind name as reported to Lua.
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Context => "context",
+            Self::Added => "added",
+            Self::Removed => "removed",
+        }
+    }
+}
+
+/// A single row of the unified (single-column) layout.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct UnifiedRow {
+    pub kind: LineKind,
+
+    /// Line number in the old version (0-indexed); `None` for added lines.
+    pub old_line: Option<u32>,
+
+    /// Line number in the new version (0-indexed); `None` for removed lines.
+    pub new_line: Option<u32>,
+
+    pub content: String,
+
+    /// Regions within the line to highlight as changed, as in [`Side::highlights`].
+    pub highlights: Highlights,
+}
+
+/// A file's rows flattened into a single column.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct UnifiedLayout {
+    pub rows: Vec<UnifiedRow>,
+
+    /// Row indices (0-indexed) into `rows` where hunks start.
+    pub hunk_starts: Vec<u32>,
+
+    /// Unchanged runs of `rows` to collapse, as in [`DisplayFile::folds`].
+    #[serde(default)]
+    pub folds: Vec<Fold>,
+}
+
+/// Which layouts a processed file carries.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    /// Side-by-side `rows` only.
+    #[default]
+    Split,
+    /// Side-by-side `rows` plus the single-column [`DisplayFile::unified`] layout.
+    Unified,
+}
+
+/// Options controlling how files are processed into rows.
+///
+/// Processed files depend on these, so they are part of a diff's cache key.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ProcessOptions {
+    /// Keep one highlight region per difftastic change, with its original boundaries,
+    /// instead of merging overlapping and touching changes.
+    pub token_regions: bool,
+
+    pub layout: Layout,
+
+    /// Number of unchanged rows to keep around each hunk; the rest is described as
+    /// [`Fold`]s. `None` folds nothing.
+    pub context_lines: Option<u32>,
+
+    /// Tag blocks of code moved within a file. Diffs collected as a whole also detect
+    /// blocks moved across files.
+    pub detect_moves: bool,
+
+    /// Treat changes to whitespace as unchanged: difftastic strips carriage returns, line
+    /// stats ignore whitespace and rows differing only in whitespace are not highlighted.
+    pub ignore_whitespace: bool,
+
+    /// Options passed through to difftastic itself, from the `difft` table.
+    #[serde(default)]
+    pub difft: DifftOptions,
+
+    /// Run difftastic on each file's fetched contents instead of through git or jj, which
+    /// then only list the changed files (see [`crate::direct`]).
+    #[serde(default)]
+    pub direct_difft: bool,
+}
+
+impl FromLua for ProcessOptions {
+    fn from_lua(value: LuaValue, _: &Lua) -> LuaResult<Self> {
+        let opts = match value {
+            LuaValue::Nil => return Ok(Self::default()),
+            LuaValue::Table(opts) => opts,
+            other => {
+                return Err(LuaError::FromLuaConversionError {
+                    from: other.type_name(),
+                    to: "ProcessOptions".to_string(),
+                    message: Some("expected an options table".to_string()),
+                });
+            }
+        };
+
+        let layout = match opts.get::<Option<String>>("layout")?.as_deref() {
+            None | Some("split") => Layout::Split,
+            Some("unified") => Layout::Unified,
+            Some(other) => {
+                return Err(LuaError::RuntimeError(format!(
+                    "Unknown layout `{other}`; expected \"split\" or \"unified\""
+                )));
+            }
+        };
+
+        Ok(Self {
+            token_regions: opts.get::<Option<bool>>("token_regions")?.unwrap_or(false),
+            layout,
+            context_lines: opts.get("context_lines")?,
+            detect_moves: opts.get::<Option<bool>>("detect_moves")?.unwrap_or(false),
+            ignore_whitespace: opts
+                .get::<Option<bool>>("ignore_whitespace")?
+                .unwrap_or(false),
+            difft: opts.get("difft")?,
+            direct_difft: opts.get::<Option<bool>>("direct_difft")?.unwrap_or(false),
+        })
+    }
+}
+
+impl ProcessOptions {
+    /// These options for a diff collected as a whole, whose moves are detected across all
+    /// of its files afterwards: files are processed without the per-file pass.
+    pub fn without_moves(&self) -> Self {
+        Self {
+            detect_moves: false,
+            ..self.clone()
+        }
+    }
 }
 
 /// Processes a difftastic file into display-ready format.
@@ -198,11 +513,304 @@ pub fn process_file(
     old_lines: Vec<String>,
     new_lines: Vec<String>,
     stats: Option<(u32, u32)>,
+    options: &ProcessOptions,
 ) -> DisplayFile {
-    match file.status {
+    let mut display = match file.status {
         Status::Created => process_created(file, new_lines, stats),
         Status::Deleted => process_deleted(file, old_lines, stats),
-        Status::Changed | Status::Unchanged => process_changed(file, &old_lines, &new_lines, stats),
+        Status::Changed | Status::Unchanged => {
+            process_changed(file, &old_lines, &new_lines, stats, options)
+        }
+    };
+    if let Some(tab_width) = options.difft.tab_width {
+        add_display_columns(&mut display.rows, tab_width);
+    }
+    display.whitespace_only = options.ignore_whitespace
+        && matches!(display.status, Status::Changed | Status::Unchanged)
+        && !display.rows.is_empty()
+        && !display.rows.iter().any(Row::is_changed);
+    display.hunks = describe_hunks(&display.rows, &display.aligned_lines, &display.language);
+    if options.detect_moves {
+        moves::detect_moves(std::slice::from_mut(&mut display));
+    }
+    if let Some(context) = options.context_lines {
+        display.folds = fold_unchanged(display.rows.iter().map(Row::is_changed), context);
+    }
+    if options.layout == Layout::Unified {
+        let mut unified = unified_layout(&display);
+        if let Some(context) = options.context_lines {
+            unified.folds = fold_unchanged(
+                unified.rows.iter().map(|row| row.kind != LineKind::Context),
+                context,
+            );
+        }
+        display.unified = Some(unified);
+    }
+    display
+}
+
+/// Describes each run of changed rows as a [`Hunk`].
+///
+/// Runs are found with [`Row::is_changed`], the same test that fills `hunk_starts`. Line
+/// ranges come from `aligned_lines`, and enclosing scopes from the rows above each hunk
+/// (see [`crate::scope`]).
+#[must_use]
+pub fn describe_hunks(
+    rows: &[Row],
+    aligned_lines: &[(Option<u32>, Option<u32>)],
+    language: &str,
+) -> Vec<Hunk> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    let mut in_hunk = false;
+
+    for (index, row) in rows.iter().enumerate() {
+        if !row.is_changed() {
+            in_hunk = false;
+            continue;
+        }
+        let index = index as u32;
+        if !in_hunk {
+            hunks.push(Hunk {
+                start: index,
+                end: index,
+                left: None,
+                right: None,
+                additions: 0,
+                deletions: 0,
+                kinds: Vec::new(),
+                left_scope: None,
+                right_scope: None,
+            });
+            in_hunk = true;
+        }
+        let Some(hunk) = hunks.last_mut() else {
+            continue;
+        };
+        hunk.end = index + 1;
+
+        let (left_line, right_line) = aligned_lines
+            .get(index as usize)
+            .copied()
+            .unwrap_or((None, None));
+        if let Some(line) = left_line.filter(|_| !row.left.is_filler) {
+            extend_range(&mut hunk.left, line);
+            hunk.deletions += 1;
+        }
+        if let Some(line) = right_line.filter(|_| !row.right.is_filler) {
+            extend_range(&mut hunk.right, line);
+            hunk.additions += 1;
+        }
+    }
+
+    // Rank each hunk's token classes by how many changed regions they cover.
+    for hunk in &mut hunks {
+        let mut counts: Vec<(Highlight, usize)> = Vec::new();
+        let regions = rows[hunk.start as usize..hunk.end as usize]
+            .iter()
+            .flat_map(|row| row.left.highlights.iter().chain(&row.right.highlights));
+        for kind in regions.filter_map(|region| region.kind) {
+            match counts.iter_mut().find(|(k, _)| *k == kind) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((kind, 1)),
+            }
+        }
+        // Stable, so ties keep the order in which the classes first appear.
+        counts.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+        hunk.kinds = counts.into_iter().map(|(kind, _)| kind).collect();
+    }
+
+    let left_scopes = hunk_scopes(rows, &hunks, |row| &row.left, language);
+    let right_scopes = hunk_scopes(rows, &hunks, |row| &row.right, language);
+    for ((hunk, left), right) in hunks.iter_mut().zip(left_scopes).zip(right_scopes) {
+        if hunk.left.is_some() {
+            hunk.left_scope = left;
+        }
+        if hunk.right.is_some() {
+            hunk.right_scope = right;
+        }
+    }
+
+    hunks
+}
+
+/// Finds the enclosing scope of each hunk on one side, in a single pass over its lines.
+fn hunk_scopes(
+    rows: &[Row],
+    hunks: &[Hunk],
+    side: fn(&Row) -> &Side,
+    language: &str,
+) -> Vec<Option<String>> {
+    let mut tracker = scope::ScopeTracker::new(language);
+    let mut read = 0;
+    hunks
+        .iter()
+        .map(|hunk| {
+            let (start, end) = (hunk.start as usize, hunk.end as usize);
+            for line in side_lines(&rows[read..start], side) {
+                tracker.push(line);
+            }
+            read = start;
+            tracker.scope(side_lines(&rows[start..end], side))
+        })
+        .collect()
+}
+
+/// The lines of one side of `rows`, skipping fillers.
+fn side_lines(rows: &[Row], side: fn(&Row) -> &Side) -> impl Iterator<Item = &str> {
+    rows.iter()
+        .map(side)
+        .filter(|side| !side.is_filler)
+        .map(|side| side.content.as_str())
+}
+
+/// Grows a line range to include `line`.
+fn extend_range(range: &mut Option<LineRange>, line: u32) {
+    let range = range.get_or_insert(LineRange {
+        start: line,
+        end: line + 1,
+    });
+    range.start = range.start.min(line);
+    range.end = range.end.max(line + 1);
+}
+
+/// Describes the unchanged runs of rows further than `context` rows from any change.
+///
+/// `changed` yields whether each row is part of a hunk. Runs of a single row are left
+/// alone, since a fold would take up just as much space.
+#[must_use]
+pub fn fold_unchanged(changed: impl Iterator<Item = bool>, context: u32) -> Vec<Fold> {
+    let mut folds = Vec::new();
+    let mut run_start = 0u32;
+    let mut seen_change = false;
+    let mut len = 0u32;
+
+    let mut close_run = |start: u32, end: u32, before_change: bool, after_change: bool| {
+        let start = if before_change {
+            start + context
+        } else {
+            start
+        };
+        let end = if after_change {
+            end.saturating_sub(context)
+        } else {
+            end
+        };
+        if end > start + 1 {
+            folds.push(Fold { start, end });
+        }
+    };
+
+    for (index, is_changed) in changed.enumerate() {
+        let index = index as u32;
+        len = index + 1;
+        if is_changed {
+            if run_start < index {
+                close_run(run_start, index, seen_change, true);
+            }
+            run_start = index + 1;
+            seen_change = true;
+        }
+    }
+    // A file without changes is shown whole.
+    if seen_change && run_start < len {
+        close_run(run_start, len, true, false);
+    }
+
+    folds
+}
+
+/// Flattens a file's side-by-side rows into a single column.
+///
+/// Rows without changes become context lines. Within each run of changed rows, the
+/// removed lines are listed before the added ones, as in a unified diff. Line numbers
+/// come from the file's `aligned_lines`.
+#[must_use]
+pub fn unified_layout(file: &DisplayFile) -> UnifiedLayout {
+    let mut layout = UnifiedLayout::default();
+    let mut removed: Vec<UnifiedRow> = Vec::new();
+    let mut added: Vec<UnifiedRow> = Vec::new();
+
+    let flush =
+        |layout: &mut UnifiedLayout, removed: &mut Vec<UnifiedRow>, added: &mut Vec<UnifiedRow>| {
+            if removed.is_empty() && added.is_empty() {
+                return;
+            }
+            layout.hunk_starts.push(layout.rows.len() as u32);
+            layout.rows.append(removed);
+            layout.rows.append(added);
+        };
+
+    for (row, &(old_line, new_line)) in file.rows.iter().zip(&file.aligned_lines) {
+        if !row.is_changed() {
+            flush(&mut layout, &mut removed, &mut added);
+            layout.rows.push(UnifiedRow {
+                kind: LineKind::Context,
+                old_line,
+                new_line,
+                content: row.right.content.clone(),
+                highlights: Highlights::new(),
+            });
+            continue;
+        }
+
+        if !row.left.is_filler {
+            removed.push(UnifiedRow {
+                kind: LineKind::Removed,
+                old_line,
+                new_line: None,
+                content: row.left.content.clone(),
+                highlights: row.left.highlights.clone(),
+            });
+        }
+        if !row.right.is_filler {
+            added.push(UnifiedRow {
+                kind: LineKind::Added,
+                old_line: None,
+                new_line,
+                content: row.right.content.clone(),
+                highlights: row.right.highlights.clone(),
+            });
+        }
+    }
+    flush(&mut layout, &mut removed, &mut added);
+
+    layout
+}
+
+/// Builds the metadata of a file without materializing its rows.
+///
+/// The returned file has empty `rows`, `hunk_starts` and `aligned_lines`; they are filled
+/// in later by [`process_file`] once the file's content has been fetched. Without VCS
+/// `stats`, both counts are 0 since they can't be derived without the content.
+///
+/// With [`ProcessOptions::ignore_whitespace`] the stats ignore whitespace too, so a
+/// changed file without any counted lines is marked whitespace-only up front.
+#[must_use]
+pub fn summarize_file(
+    file: &DifftFile,
+    stats: Option<(u32, u32)>,
+    options: &ProcessOptions,
+) -> DisplayFile {
+    let (additions, deletions) = stats.unwrap_or((0, 0));
+    let whitespace_only = options.ignore_whitespace
+        && matches!(file.status, Status::Changed | Status::Unchanged)
+        && stats == Some((0, 0));
+    DisplayFile {
+        path: file.path.clone(),
+        moved_from: None,
+        language: file.language.clone(),
+        status: file.status,
+        additions,
+        deletions,
+        rows: Vec::new(),
+        hunk_starts: Vec::new(),
+        aligned_lines: Vec::new(),
+        unified: None,
+        folds: Vec::new(),
+        hunks: Vec::new(),
+        moves: Vec::new(),
+        whitespace_only,
+        error: None,
     }
 }
 
@@ -221,6 +829,7 @@ fn process_created(
         .map(|line| Row {
             left: Side::filler(),
             right: Side::with_full_highlight(line),
+            whitespace_only: false,
         })
         .collect();
 
@@ -241,6 +850,12 @@ fn process_created(
         rows,
         hunk_starts,
         aligned_lines,
+        unified: None,
+        folds: Vec::new(),
+        hunks: Vec::new(),
+        moves: Vec::new(),
+        whitespace_only: false,
+        error: None,
     }
 }
 
@@ -259,6 +874,7 @@ fn process_deleted(
         .map(|line| Row {
             left: Side::with_full_highlight(line),
             right: Side::filler(),
+            whitespace_only: false,
         })
         .collect();
 
@@ -279,6 +895,12 @@ fn process_deleted(
         rows,
         hunk_starts,
         aligned_lines,
+        unified: None,
+        folds: Vec::new(),
+        hunks: Vec::new(),
+        moves: Vec::new(),
+        whitespace_only: false,
+        error: None,
     }
 }
 
@@ -322,8 +944,15 @@ fn process_changed(
     old_lines: &[String],
     new_lines: &[String],
     stats: Option<(u32, u32)>,
+    options: &ProcessOptions,
 ) -> DisplayFile {
     let (lhs_changes, rhs_changes) = extract_changes(&file.chunks);
+    let line_based = is_line_based(&file.language);
+    let highlight = if options.token_regions {
+        token_highlights
+    } else {
+        compute_highlights
+    };
     let num_rows = file.aligned_lines.len();
 
     let mut rows = Vec::with_capacity(num_rows);
@@ -343,19 +972,39 @@ fn process_changed(
         let left_changes = lhs_ln.and_then(|ln| lhs_changes.get(&ln).copied());
         let right_changes = rhs_ln.and_then(|ln| rhs_changes.get(&ln).copied());
 
+        // Line-based diffs only say that a paired line changed; narrow that down to words.
+        let refined = match (left_changes, right_changes) {
+            (Some(left), Some(right))
+                if line_based
+                    && covers_line(&left_content, left)
+                    && covers_line(&right_content, right) =>
+            {
+                refine_changed_line(&left_content, &right_content)
+            }
+            _ => None,
+        };
+        let (left_changes, right_changes) = match &refined {
+            Some((left, right)) => (Some(left.as_slice()), Some(right.as_slice())),
+            None => (left_changes, right_changes),
+        };
+
         // Compute highlights based on change information
-        let left_highlights = left_changes.map_or_else(Highlights::new, |changes| {
-            compute_highlights(&left_content, changes)
-        });
+        let left_highlights =
+            left_changes.map_or_else(Highlights::new, |changes| highlight(&left_content, changes));
         let right_highlights = right_changes.map_or_else(Highlights::new, |changes| {
-            compute_highlights(&right_content, changes)
+            highlight(&right_content, changes)
         });
 
         // Determine if this row is part of a hunk (has changes or fillers)
-        let is_changed = lhs_ln.is_none()
-            || rhs_ln.is_none()
-            || !left_highlights.is_empty()
-            || !right_highlights.is_empty();
+        let mut row = Row {
+            left: Side::new(left_content, lhs_ln.is_none(), left_highlights),
+            right: Side::new(right_content, rhs_ln.is_none(), right_highlights),
+            whitespace_only: false,
+        };
+        if options.ignore_whitespace {
+            ignore_whitespace_changes(&mut row);
+        }
+        let is_changed = row.is_changed();
 
         // Track hunk boundaries for navigation
         if is_changed && !in_hunk {
@@ -365,10 +1014,7 @@ fn process_changed(
             in_hunk = false;
         }
 
-        rows.push(Row {
-            left: Side::new(left_content, lhs_ln.is_none(), left_highlights),
-            right: Side::new(right_content, rhs_ln.is_none(), right_highlights),
-        });
+        rows.push(row);
     }
 
     // Use VCS stats if available, otherwise default to 0
@@ -384,37 +1030,298 @@ fn process_changed(
         rows,
         hunk_starts,
         aligned_lines: file.aligned_lines,
+        unified: None,
+        folds: Vec::new(),
+        hunks: Vec::new(),
+        moves: Vec::new(),
+        whitespace_only: false,
+        error: None,
+    }
+}
+
+/// Hides changes that only touch whitespace, for [`ProcessOptions::ignore_whitespace`].
+///
+/// A changed row whose sides are equal once whitespace is removed, including a blank line
+/// opposite a filler, is marked [`Row::whitespace_only`] and loses its highlights. On
+/// other rows, regions covering nothing but whitespace are dropped.
+fn ignore_whitespace_changes(row: &mut Row) {
+    if !row.is_changed() {
+        return;
+    }
+
+    let visible = |content: &str| {
+        content
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<String>()
+    };
+    if visible(&row.left.content) == visible(&row.right.content) {
+        row.left.highlights.clear();
+        row.right.highlights.clear();
+        row.whitespace_only = true;
+        return;
+    }
+
+    for side in [&mut row.left, &mut row.right] {
+        side.highlights.retain(|region| {
+            region
+                .text
+                .as_deref()
+                .is_none_or(|text| !text.trim().is_empty())
+        });
+    }
+}
+
+/// Returns `true` for files difftastic diffed line by line instead of structurally: plain
+/// text, unsupported languages, and files that exceeded difftastic's parse limits.
+fn is_line_based(language: &str) -> bool {
+    language.starts_with("Text") || language.contains("exceeded")
+}
+
+/// Returns `true` if `changes` cover every non-whitespace character of `content`.
+fn covers_line(content: &str, changes: &[Change]) -> bool {
+    content
+        .char_indices()
+        .filter(|(_, c)| !c.is_whitespace())
+        .all(|(i, _)| {
+            changes
+                .iter()
+                .any(|c| c.start as usize <= i && i < c.end as usize)
+        })
+}
+
+/// Splits a line into tokens, as byte ranges: runs of word characters, runs of
+/// whitespace, and single other characters.
+fn tokenize(content: &str) -> Vec<(usize, usize)> {
+    #[derive(PartialEq)]
+    enum Class {
+        Word,
+        Space,
+        Other,
+    }
+    let class = |c: char| {
+        if c.is_alphanumeric() || c == '_' {
+            Class::Word
+        } else if c.is_whitespace() {
+            Class::Space
+        } else {
+            Class::Other
+        }
+    };
+
+    let mut tokens: Vec<(usize, usize)> = Vec::new();
+    let mut last = None;
+    for (i, c) in content.char_indices() {
+        let current = class(c);
+        let end = i + c.len_utf8();
+        match tokens.last_mut() {
+            Some((_, last_end)) if current != Class::Other && last.as_ref() == Some(&current) => {
+                *last_end = end;
+            }
+            _ => tokens.push((i, end)),
+        }
+        last = Some(current);
+    }
+    tokens
+}
+
+/// Computes word-level changes for a pair of lines that changed as a whole.
+///
+/// Tokens of both lines are matched with a longest common subsequence; the unmatched
+/// tokens become the changes of each side. Returns `None` when the lines share no tokens,
+/// where the whole-line change is already accurate, or when they are too long to compare.
+fn refine_changed_line(old: &str, new: &str) -> Option<(Vec<Change>, Vec<Change>)> {
+    let old_tokens = tokenize(old);
+    let new_tokens = tokenize(new);
+    let old_text = |i: usize| &old[old_tokens[i].0..old_tokens[i].1];
+    let new_text = |j: usize| &new[new_tokens[j].0..new_tokens[j].1];
+
+    // Common prefixes and suffixes are matched without the table.
+    let prefix = (0..old_tokens.len().min(new_tokens.len()))
+        .take_while(|&i| old_text(i) == new_text(i))
+        .count();
+    let suffix = (0..old_tokens.len().min(new_tokens.len()) - prefix)
+        .take_while(|&k| old_text(old_tokens.len() - 1 - k) == new_text(new_tokens.len() - 1 - k))
+        .count();
+    let (n, m) = (
+        old_tokens.len() - prefix - suffix,
+        new_tokens.len() - prefix - suffix,
+    );
+    if (n + 1).saturating_mul(m + 1) > MAX_REFINE_CELLS {
+        return None;
+    }
+
+    // lengths[i][j]: LCS length of the middle tokens from i and j onwards.
+    let mut lengths = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i][j] = if old_text(prefix + i) == new_text(prefix + j) {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+    if prefix + suffix == 0 && lengths[0][0] == 0 {
+        return None;
     }
+
+    let mut old_changed = vec![false; n];
+    let mut new_changed = vec![false; m];
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old_text(prefix + i) == new_text(prefix + j) {
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && lengths[i + 1][j] >= lengths[i][j + 1]) {
+            old_changed[i] = true;
+            i += 1;
+        } else {
+            new_changed[j] = true;
+            j += 1;
+        }
+    }
+
+    let changes = |content: &str, tokens: &[(usize, usize)], changed: &[bool]| -> Vec<Change> {
+        changed
+            .iter()
+            .zip(&tokens[prefix..])
+            .filter(|(changed, _)| **changed)
+            .map(|(_, &(start, end))| Change {
+                start: start as u32,
+                end: end as u32,
+                content: content[start..end].to_string(),
+                highlight: Highlight::Normal,
+            })
+            .collect()
+    };
+    Some((
+        changes(old, &old_tokens, &old_changed),
+        changes(new, &new_tokens, &new_changed),
+    ))
 }
 
 /// Computes highlight regions for a line based on its changes.
 ///
 /// Difftastic's JSON output reports the structural diff spans as `changes`.
 /// Preserve those spans; the renderer adds muted line context separately.
-fn compute_highlights(_content: &str, changes: &[Change]) -> Highlights {
+fn compute_highlights(content: &str, changes: &[Change]) -> Highlights {
     if changes.is_empty() {
         return Highlights::new();
     }
 
-    let mut regions: SmallVec<[(u32, u32); 4]> = changes.iter().map(|c| (c.start, c.end)).collect();
-    regions.sort_unstable_by_key(|r| r.0);
-    merge_overlapping_regions(&regions)
+    let mut changes: SmallVec<[&Change; 4]> = changes.iter().collect();
+    changes.sort_unstable_by_key(|c| c.start);
+    merge_overlapping_regions(&changes)
         .into_iter()
-        .map(|(start, end)| HighlightRegion::columns(start, end))
+        .map(|(start, end, kind, fallback)| token_region(content, start, end, kind, fallback))
         .collect()
 }
 
-fn merge_overlapping_regions(regions: &[(u32, u32)]) -> SmallVec<[(u32, u32); 4]> {
-    let mut merged: SmallVec<[(u32, u32); 4]> = SmallVec::with_capacity(regions.len());
+/// Computes one highlight region per change, keeping difftastic's token boundaries.
+///
+/// Unlike [`compute_highlights`], overlapping and touching changes are not merged.
+fn token_highlights(content: &str, changes: &[Change]) -> Highlights {
+    let mut highlights: Highlights = changes
+        .iter()
+        .map(|c| token_region(content, c.start, c.end, c.highlight, c.content.clone()))
+        .collect();
+    highlights.sort_by_key(|h| (h.start, h.end));
+    highlights
+}
+
+/// Creates the region for a changed token, taking its text from the line.
+///
+/// The offsets are normalized with [`char_range`]. `fallback`, difftastic's own text for
+/// the change, stands in when the original offsets don't fit the line.
+fn token_region(
+    content: &str,
+    start: u32,
+    end: u32,
+    kind: Highlight,
+    fallback: String,
+) -> HighlightRegion {
+    let text = content
+        .get(start as usize..end as usize)
+        .map_or(fallback, str::to_string);
+    let (start, end) = char_range(content, start, end);
+    HighlightRegion::token(start, end, kind, text)
+}
+
+/// Clamps a byte range to `content` and widens it to whole characters.
+///
+/// difftastic's offsets come from the file it parsed, which may not match the line we
+/// read (for instance after a decoding fallback), and a range that splits a multibyte
+/// character would put an extmark in the middle of it.
+fn char_range(content: &str, start: u32, end: u32) -> (u32, u32) {
+    let len = content.len();
+    let mut start = (start as usize).min(len);
+    let mut end = (end as usize).clamp(start, len);
+    while !content.is_char_boundary(start) {
+        start -= 1;
+    }
+    while !content.is_char_boundary(end) {
+        end += 1;
+    }
+    (start as u32, end as u32)
+}
+
+/// Returns the screen column at byte offset `byte` of `content`.
+///
+/// Tabs advance to the next multiple of `tab_width`, wide characters such as CJK and most
+/// emoji take two columns, and zero-width characters none. `byte` must be a character
+/// boundary; offsets past the end count the whole line.
+#[must_use]
+pub fn display_column(content: &str, byte: usize, tab_width: u32) -> u32 {
+    let tab_width = tab_width.max(1);
+    let prefix = content.get(..byte).unwrap_or(content);
+    prefix.chars().fold(0, |column, c| match c {
+        '\t' => (column / tab_width + 1) * tab_width,
+        c => column + UnicodeWidthChar::width(c).unwrap_or(0) as u32,
+    })
+}
+
+/// Sets the display columns of every token region in `rows`.
+fn add_display_columns(rows: &mut [Row], tab_width: u32) {
+    for side in rows
+        .iter_mut()
+        .flat_map(|row| [&mut row.left, &mut row.right])
+    {
+        for region in side.highlights.iter_mut().filter(|r| r.kind.is_some()) {
+            let end = usize::try_from(region.end).unwrap_or(usize::MAX);
+            region.display = Some((
+                display_column(&side.content, region.start as usize, tab_width),
+                display_column(&side.content, end, tab_width),
+            ));
+        }
+    }
+}
 
-    for &(start, end) in regions {
-        if let Some((_, last_end)) = merged.last_mut()
-            && *last_end >= start
+/// Merges sorted changes into `(start, end, kind, text)` regions.
+///
+/// Overlapping and touching changes merge, keeping the first change's class. The text of
+/// a merged region is each change's text beyond the end of the previous ones.
+fn merge_overlapping_regions(changes: &[&Change]) -> SmallVec<[(u32, u32, Highlight, String); 4]> {
+    let mut merged: SmallVec<[(u32, u32, Highlight, String); 4]> =
+        SmallVec::with_capacity(changes.len());
+
+    for change in changes {
+        if let Some((_, last_end, _, text)) = merged.last_mut()
+            && *last_end >= change.start
         {
-            *last_end = (*last_end).max(end);
+            if change.end > *last_end {
+                let overlap = (*last_end - change.start) as usize;
+                text.push_str(change.content.get(overlap..).unwrap_or_default());
+                *last_end = change.end;
+            }
             continue;
         }
-        merged.push((start, end));
+        merged.push((
+            change.start,
+            change.end,
+            change.highlight,
+            change.content.clone(),
+        ));
     }
 
     merged
@@ -425,6 +1332,12 @@ impl IntoLua for HighlightRegion {
         let table = lua.create_table()?;
         table.set("start", self.start)?;
         table.set("end", self.end)?;
+        table.set("kind", self.kind.map(Highlight::as_str))?;
+        table.set("text", self.text)?;
+        if let Some((start, end)) = self.display {
+            table.set("display_start", start)?;
+            table.set("display_end", end)?;
+        }
         Ok(LuaValue::Table(table))
     }
 }
@@ -454,30 +1367,122 @@ impl IntoLua for Row {
     }
 }
 
-impl IntoLua for DisplayFile {
+impl IntoLua for LineRange {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let table = lua.create_table_with_capacity(0, 2)?;
+        table.set("start", self.start)?;
+        table.set("end", self.end)?;
+        Ok(LuaValue::Table(table))
+    }
+}
+
+impl IntoLua for Hunk {
     fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
         let table = lua.create_table()?;
-        table.set("path", self.path.to_string_lossy().as_ref())?;
-        table.set(
-            "moved_from",
-            self.moved_from
-                .as_ref()
-                .map(|p| p.to_string_lossy().into_owned()),
-        )?;
-        table.set("language", self.language)?;
-        table.set(
-            "status",
-            match self.status {
-                Status::Unchanged => "unchanged",
-                Status::Created => "created",
-                Status::Deleted => "deleted",
-                Status::Changed => "changed",
-            },
-        )?;
+        table.set("start", self.start)?;
+        table.set("end", self.end)?;
+        table.set("left", self.left)?;
+        table.set("right", self.right)?;
         table.set("additions", self.additions)?;
         table.set("deletions", self.deletions)?;
+        table.set("left_scope", self.left_scope)?;
+        table.set("right_scope", self.right_scope)?;
 
-        let rows = lua.create_table_with_capacity(self.rows.len(), 0)?;
+        let kinds = lua.create_table_with_capacity(self.kinds.len(), 0)?;
+        for (i, kind) in self.kinds.into_iter().enumerate() {
+            kinds.set(i + 1, kind.as_str())?;
+        }
+        table.set("kinds", kinds)?;
+
+        Ok(LuaValue::Table(table))
+    }
+}
+
+impl IntoLua for MovedBlock {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let table = lua.create_table()?;
+        table.set("kind", self.kind.as_str())?;
+        table.set("start", self.start)?;
+        table.set("end", self.end)?;
+        table.set("lines", self.lines)?;
+        table.set(
+            "counterpart_path",
+            self.counterpart_path.to_string_lossy().as_ref(),
+        )?;
+        table.set("counterpart_lines", self.counterpart_lines)?;
+        Ok(LuaValue::Table(table))
+    }
+}
+
+impl IntoLua for UnifiedRow {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let table = lua.create_table()?;
+        table.set("kind", self.kind.as_str())?;
+        table.set("old_line", self.old_line)?;
+        table.set("new_line", self.new_line)?;
+        table.set("content", self.content)?;
+
+        let highlights = lua.create_table_with_capacity(self.highlights.len(), 0)?;
+        for (i, highlight) in self.highlights.into_iter().enumerate() {
+            highlights.set(i + 1, highlight.into_lua(lua)?)?;
+        }
+        table.set("highlights", highlights)?;
+
+        Ok(LuaValue::Table(table))
+    }
+}
+
+impl IntoLua for UnifiedLayout {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let table = lua.create_table()?;
+
+        let rows = lua.create_table_with_capacity(self.rows.len(), 0)?;
+        for (i, row) in self.rows.into_iter().enumerate() {
+            rows.set(i + 1, row.into_lua(lua)?)?;
+        }
+        table.set("rows", rows)?;
+
+        let hunk_starts = lua.create_table_with_capacity(self.hunk_starts.len(), 0)?;
+        for (i, hunk_start) in self.hunk_starts.into_iter().enumerate() {
+            hunk_starts.set(i + 1, hunk_start)?;
+        }
+        table.set("hunk_starts", hunk_starts)?;
+        table.set("folds", folds_to_lua(lua, self.folds)?)?;
+
+        Ok(LuaValue::Table(table))
+    }
+}
+
+/// Converts folds into a list of `{ start = ..., ["end"] = ... }` tables.
+fn folds_to_lua(lua: &Lua, folds: Vec<Fold>) -> LuaResult<LuaTable> {
+    let table = lua.create_table_with_capacity(folds.len(), 0)?;
+    for (i, fold) in folds.into_iter().enumerate() {
+        let entry = lua.create_table_with_capacity(0, 2)?;
+        entry.set("start", fold.start)?;
+        entry.set("end", fold.end)?;
+        table.set(i + 1, entry)?;
+    }
+    Ok(table)
+}
+
+impl IntoLua for DisplayFile {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let table = lua.create_table()?;
+        table.set("path", self.path.to_string_lossy().as_ref())?;
+        table.set(
+            "moved_from",
+            self.moved_from
+                .as_ref()
+                .map(|p| p.to_string_lossy().into_owned()),
+        )?;
+        table.set("language", self.language)?;
+        table.set("status", self.status.as_str())?;
+        table.set("additions", self.additions)?;
+        table.set("deletions", self.deletions)?;
+        table.set("whitespace_only", self.whitespace_only)?;
+        table.set("error", self.error)?;
+
+        let rows = lua.create_table_with_capacity(self.rows.len(), 0)?;
         for (i, row) in self.rows.into_iter().enumerate() {
             rows.set(i + 1, row.into_lua(lua)?)?;
         }
@@ -489,6 +1494,12 @@ impl IntoLua for DisplayFile {
         }
         table.set("hunk_starts", hunk_starts)?;
 
+        let hunks = lua.create_table_with_capacity(self.hunks.len(), 0)?;
+        for (i, hunk) in self.hunks.into_iter().enumerate() {
+            hunks.set(i + 1, hunk.into_lua(lua)?)?;
+        }
+        table.set("hunks", hunks)?;
+
         let aligned_lines = lua.create_table_with_capacity(self.aligned_lines.len(), 0)?;
         for (i, (left, right)) in self.aligned_lines.into_iter().enumerate() {
             let pair = lua.create_table_with_capacity(2, 0)?;
@@ -497,6 +1508,17 @@ impl IntoLua for DisplayFile {
             aligned_lines.set(i + 1, pair)?;
         }
         table.set("aligned_lines", aligned_lines)?;
+        table.set("folds", folds_to_lua(lua, self.folds)?)?;
+
+        let moves = lua.create_table_with_capacity(self.moves.len(), 0)?;
+        for (i, block) in self.moves.into_iter().enumerate() {
+            moves.set(i + 1, block.into_lua(lua)?)?;
+        }
+        table.set("moves", moves)?;
+
+        if let Some(unified) = self.unified {
+            table.set("unified", unified.into_lua(lua)?)?;
+        }
 
         Ok(LuaValue::Table(table))
     }
@@ -505,7 +1527,7 @@ impl IntoLua for DisplayFile {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::difftastic::{DiffLine, Highlight, Side as DiffSide};
+    use crate::difftastic::{DiffLine, Side as DiffSide};
 
     /// Helper to create a Change with only start/end (content and highlight empty).
     fn change(start: u32, end: u32) -> Change {
@@ -517,6 +1539,16 @@ mod tests {
         }
     }
 
+    /// Helper to create a Change with a token class and content.
+    fn token(start: u32, end: u32, highlight: Highlight, content: &str) -> Change {
+        Change {
+            start,
+            end,
+            content: content.to_string(),
+            highlight,
+        }
+    }
+
     /// Helper to create a DiffSide with given line number and changes.
     fn diff_side(line: u32, changes: Vec<Change>) -> DiffSide {
         DiffSide {
@@ -534,7 +1566,13 @@ mod tests {
             aligned_lines: vec![],
             chunks: vec![],
         };
-        let result = process_file(file, vec![], vec!["a".into(), "b".into()], Some((2, 0)));
+        let result = process_file(
+            file,
+            vec![],
+            vec!["a".into(), "b".into()],
+            Some((2, 0)),
+            &ProcessOptions::default(),
+        );
 
         assert_eq!(result.rows.len(), 2);
         assert!(result.rows[0].left.is_filler);
@@ -547,6 +1585,29 @@ mod tests {
         assert_eq!(result.deletions, 0);
     }
 
+    #[test]
+    fn summary_has_metadata_but_no_rows() {
+        let file = DifftFile {
+            path: "lib.rs".into(),
+            language: "Rust".into(),
+            status: Status::Changed,
+            aligned_lines: vec![(Some(0), Some(0))],
+            chunks: vec![vec![DiffLine {
+                lhs: Some(diff_side(0, vec![change(0, 3)])),
+                rhs: Some(diff_side(0, vec![change(0, 3)])),
+            }]],
+        };
+        let summary = summarize_file(&file, Some((4, 1)), &ProcessOptions::default());
+
+        assert_eq!(summary.path, file.path);
+        assert_eq!(summary.language, "Rust");
+        assert_eq!(summary.status, Status::Changed);
+        assert_eq!((summary.additions, summary.deletions), (4, 1));
+        assert!(summary.rows.is_empty());
+        assert!(summary.hunk_starts.is_empty());
+        assert!(summary.aligned_lines.is_empty());
+    }
+
     #[test]
     fn deleted_file_all_deletions() {
         let file = DifftFile {
@@ -556,7 +1617,13 @@ mod tests {
             aligned_lines: vec![],
             chunks: vec![],
         };
-        let result = process_file(file, vec!["x".into(), "y".into()], vec![], Some((0, 2)));
+        let result = process_file(
+            file,
+            vec!["x".into(), "y".into()],
+            vec![],
+            Some((0, 2)),
+            &ProcessOptions::default(),
+        );
 
         assert_eq!(result.rows.len(), 2);
         assert_eq!(result.rows[0].left.content, "x");
@@ -583,6 +1650,7 @@ mod tests {
             vec!["line1".into(), "foo".into(), "line3".into()],
             vec!["line1".into(), "foobar".into(), "line3".into()],
             Some((1, 1)),
+            &ProcessOptions::default(),
         );
 
         assert_eq!(result.rows.len(), 3);
@@ -609,6 +1677,7 @@ mod tests {
             vec!["line 1".into(), "line 3".into()],
             vec!["line 1".into(), "new line".into(), "line 3".into()],
             Some((1, 0)),
+            &ProcessOptions::default(),
         );
 
         assert_eq!(result.rows.len(), 3);
@@ -635,6 +1704,7 @@ mod tests {
             vec!["line 1".into(), "deleted".into(), "line 3".into()],
             vec!["line 1".into(), "line 3".into()],
             Some((0, 1)),
+            &ProcessOptions::default(),
         );
 
         assert_eq!(result.rows.len(), 3);
@@ -716,6 +1786,611 @@ mod tests {
         assert_eq!(highlights.len(), 2);
     }
 
+    #[test]
+    fn highlight_keeps_token_class_and_text() {
+        let changes = [
+            token(0, 2, Highlight::Keyword, "fn"),
+            token(8, 13, Highlight::String, "\"hi\""),
+        ];
+        let highlights = compute_highlights("fn f() \"hi\"", &changes);
+        assert_eq!(highlights.len(), 2);
+        assert_eq!(highlights[0].kind, Some(Highlight::Keyword));
+        assert_eq!(highlights[0].text.as_deref(), Some("fn"));
+        assert_eq!(highlights[1].kind, Some(Highlight::String));
+        assert_eq!(highlights[1].text.as_deref(), Some("\"hi\""));
+    }
+
+    #[test]
+    fn highlight_touching_regions_merge_keeping_the_first_class() {
+        let changes = [
+            token(0, 3, Highlight::Normal, "foo"),
+            token(3, 4, Highlight::Delimiter, "("),
+            token(4, 5, Highlight::Delimiter, ")"),
+        ];
+        let highlights = compute_highlights("foo()", &changes);
+        assert_eq!(highlights.len(), 1);
+        assert_eq!((highlights[0].start, highlights[0].end), (0, 5));
+        assert_eq!(highlights[0].kind, Some(Highlight::Normal));
+        assert_eq!(highlights[0].text.as_deref(), Some("foo()"));
+    }
+
+    #[test]
+    fn merged_fallback_text_does_not_repeat_overlaps() {
+        let changes = [
+            token(0, 3, Highlight::Normal, "foo"),
+            token(1, 3, Highlight::Normal, "oo"),
+            token(2, 5, Highlight::Normal, "o()"),
+        ];
+        let merged = merge_overlapping_regions(&changes.iter().collect::<Vec<_>>());
+        assert_eq!(merged.len(), 1);
+        assert_eq!((merged[0].0, merged[0].1), (0, 5));
+        assert_eq!(merged[0].3, "foo()");
+    }
+
+    #[test]
+    fn highlight_text_falls_back_to_change_content() {
+        let changes = [token(10, 13, Highlight::Type, "u32")];
+        let highlights = compute_highlights("short", &changes);
+        assert_eq!(highlights[0].text.as_deref(), Some("u32"));
+    }
+
+    #[test]
+    fn token_highlights_keep_each_change() {
+        let changes = [
+            token(4, 5, Highlight::Delimiter, ")"),
+            token(0, 3, Highlight::Normal, "foo"),
+            token(3, 4, Highlight::Delimiter, "("),
+        ];
+        let highlights = token_highlights("foo()", &changes);
+        let spans: Vec<_> = highlights.iter().map(|h| (h.start, h.end)).collect();
+        assert_eq!(spans, vec![(0, 3), (3, 4), (4, 5)]);
+        assert_eq!(highlights[1].text.as_deref(), Some("("));
+    }
+
+    #[test]
+    fn token_regions_option_skips_merging() {
+        let file = DifftFile {
+            path: "call.rs".into(),
+            language: "Rust".into(),
+            status: Status::Changed,
+            aligned_lines: vec![(Some(0), Some(0))],
+            chunks: vec![vec![DiffLine {
+                lhs: Some(diff_side(0, vec![change(0, 3), change(2, 5)])),
+                rhs: Some(diff_side(0, vec![change(0, 3), change(3, 5)])),
+            }]],
+        };
+        let lines = vec!["foo()".to_string()];
+        let options = ProcessOptions {
+            token_regions: true,
+            ..ProcessOptions::default()
+        };
+
+        let merged = process_file(
+            file.clone(),
+            lines.clone(),
+            lines.clone(),
+            None,
+            &ProcessOptions::default(),
+        );
+        let tokens = process_file(file, lines.clone(), lines, None, &options);
+
+        assert_eq!(merged.rows[0].left.highlights.len(), 1);
+        assert_eq!(merged.rows[0].right.highlights.len(), 1);
+        assert_eq!(tokens.rows[0].left.highlights.len(), 2);
+        assert_eq!(tokens.rows[0].right.highlights.len(), 2);
+    }
+
+    /// Builds a text file with one paired line that difftastic reports as wholly changed.
+    fn text_line_change(language: &str, old: &str, new: &str) -> DisplayFile {
+        let file = DifftFile {
+            path: "config.txt".into(),
+            language: language.into(),
+            status: Status::Changed,
+            aligned_lines: vec![(Some(0), Some(0))],
+            chunks: vec![vec![DiffLine {
+                lhs: Some(diff_side(0, vec![change(0, old.len() as u32)])),
+                rhs: Some(diff_side(0, vec![change(0, new.len() as u32)])),
+            }]],
+        };
+        process_file(
+            file,
+            vec![old.into()],
+            vec![new.into()],
+            None,
+            &ProcessOptions::default(),
+        )
+    }
+
+    fn spans(side: &Side) -> Vec<(u32, i32)> {
+        side.highlights.iter().map(|h| (h.start, h.end)).collect()
+    }
+
+    #[test]
+    fn text_lines_are_refined_to_changed_words() {
+        let result = text_line_change("Text", "timeout = 30 # seconds", "timeout = 45 # seconds");
+        assert_eq!(spans(&result.rows[0].left), vec![(10, 12)]);
+        assert_eq!(spans(&result.rows[0].right), vec![(10, 12)]);
+        assert_eq!(
+            result.rows[0].right.highlights[0].text.as_deref(),
+            Some("45")
+        );
+        assert_eq!(result.hunk_starts, vec![0]);
+    }
+
+    #[test]
+    fn text_line_insertions_highlight_one_side() {
+        let result = text_line_change("Text", "the quick fox", "the quick brown fox");
+        assert!(result.rows[0].left.highlights.is_empty());
+        assert_eq!(spans(&result.rows[0].right), vec![(10, 16)]);
+    }
+
+    #[test]
+    fn unrelated_text_lines_keep_whole_line_changes() {
+        let result = text_line_change("Text", "alpha", "omega");
+        assert_eq!(spans(&result.rows[0].left), vec![(0, 5)]);
+        assert_eq!(spans(&result.rows[0].right), vec![(0, 5)]);
+    }
+
+    #[test]
+    fn structural_languages_are_not_refined() {
+        let result = text_line_change("Rust", "let a = 1;", "let a = 2;");
+        assert_eq!(spans(&result.rows[0].left), vec![(0, 10)]);
+    }
+
+    #[test]
+    fn tokenize_splits_words_spaces_and_punctuation() {
+        let line = "a_b  c(é)";
+        let tokens: Vec<&str> = tokenize(line)
+            .into_iter()
+            .map(|(s, e)| &line[s..e])
+            .collect();
+        assert_eq!(tokens, vec!["a_b", "  ", "c", "(", "é", ")"]);
+    }
+
+    #[test]
+    fn unified_layout_groups_removals_before_additions() {
+        let file = DifftFile {
+            path: "lib.rs".into(),
+            language: "Rust".into(),
+            status: Status::Changed,
+            aligned_lines: vec![
+                (Some(0), Some(0)),
+                (Some(1), Some(1)),
+                (Some(2), Some(2)),
+                (None, Some(3)),
+                (Some(3), Some(4)),
+            ],
+            chunks: vec![vec![
+                DiffLine {
+                    lhs: Some(diff_side(1, vec![change(0, 1)])),
+                    rhs: Some(diff_side(1, vec![change(0, 1)])),
+                },
+                DiffLine {
+                    lhs: Some(diff_side(2, vec![change(0, 1)])),
+                    rhs: Some(diff_side(2, vec![change(0, 1)])),
+                },
+                DiffLine {
+                    lhs: None,
+                    rhs: Some(diff_side(3, vec![change(0, 1)])),
+                },
+            ]],
+        };
+        let options = ProcessOptions {
+            layout: Layout::Unified,
+            ..ProcessOptions::default()
+        };
+        let result = process_file(
+            file,
+            vec!["a".into(), "b".into(), "c".into(), "z".into()],
+            vec!["a".into(), "B".into(), "C".into(), "D".into(), "z".into()],
+            None,
+            &options,
+        );
+
+        let unified = result.unified.unwrap();
+        let rows: Vec<_> = unified
+            .rows
+            .iter()
+            .map(|r| (r.kind, r.old_line, r.new_line, r.content.as_str()))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                (LineKind::Context, Some(0), Some(0), "a"),
+                (LineKind::Removed, Some(1), None, "b"),
+                (LineKind::Removed, Some(2), None, "c"),
+                (LineKind::Added, None, Some(1), "B"),
+                (LineKind::Added, None, Some(2), "C"),
+                (LineKind::Added, None, Some(3), "D"),
+                (LineKind::Context, Some(3), Some(4), "z"),
+            ]
+        );
+        assert_eq!(unified.hunk_starts, vec![1]);
+        assert_eq!(unified.rows[3].highlights.len(), 1);
+    }
+
+    #[test]
+    fn unified_layout_of_created_file_is_all_additions() {
+        let file = DifftFile {
+            path: "new.rs".into(),
+            language: "Rust".into(),
+            status: Status::Created,
+            aligned_lines: vec![],
+            chunks: vec![],
+        };
+        let result = process_file(
+            file,
+            vec![],
+            vec!["a".into(), "b".into()],
+            None,
+            &ProcessOptions::default(),
+        );
+        assert!(result.unified.is_none());
+
+        let unified = unified_layout(&result);
+        assert_eq!(unified.rows.len(), 2);
+        assert!(unified.rows.iter().all(|r| r.kind == LineKind::Added));
+        assert_eq!(unified.rows[1].new_line, Some(1));
+        assert_eq!(unified.hunk_starts, vec![0]);
+    }
+
+    #[test]
+    fn folds_keep_context_around_changes() {
+        let changed = [
+            false, false, false, false, false, true, false, false, false, false,
+        ];
+        let folds = fold_unchanged(changed.into_iter(), 1);
+        assert_eq!(
+            folds,
+            vec![Fold { start: 0, end: 4 }, Fold { start: 7, end: 10 }]
+        );
+    }
+
+    #[test]
+    fn folds_skip_short_runs_and_unchanged_files() {
+        let changed = [true, false, false, false, true];
+        assert!(fold_unchanged(changed.into_iter(), 1).is_empty());
+        assert!(fold_unchanged([false; 10].into_iter(), 3).is_empty());
+    }
+
+    #[test]
+    fn context_lines_option_folds_rows() {
+        let file = DifftFile {
+            path: "long.rs".into(),
+            language: "Rust".into(),
+            status: Status::Changed,
+            aligned_lines: (0..6).map(|i| (Some(i), Some(i))).collect(),
+            chunks: vec![vec![DiffLine {
+                lhs: Some(diff_side(5, vec![change(0, 1)])),
+                rhs: Some(diff_side(5, vec![change(0, 1)])),
+            }]],
+        };
+        let lines: Vec<String> = (0..6).map(|i| i.to_string()).collect();
+        let options = ProcessOptions {
+            context_lines: Some(2),
+            layout: Layout::Unified,
+            ..ProcessOptions::default()
+        };
+        let result = process_file(file, lines.clone(), lines, None, &options);
+
+        assert_eq!(result.rows.len(), 6);
+        assert_eq!(result.folds, vec![Fold { start: 0, end: 3 }]);
+        // The unified layout has the changed row twice, once removed and once added.
+        assert_eq!(
+            result.unified.unwrap().folds,
+            vec![Fold { start: 0, end: 3 }]
+        );
+    }
+
+    #[test]
+    fn hunks_describe_line_ranges_stats_and_kinds() {
+        let file = DifftFile {
+            path: "hunks.rs".into(),
+            language: "Rust".into(),
+            status: Status::Changed,
+            aligned_lines: vec![
+                (Some(0), Some(0)),
+                (Some(1), Some(1)),
+                (None, Some(2)),
+                (Some(2), Some(3)),
+                (Some(3), None),
+            ],
+            chunks: vec![
+                vec![
+                    DiffLine {
+                        lhs: Some(diff_side(1, vec![change(3, 4)])),
+                        rhs: Some(diff_side(
+                            1,
+                            vec![
+                                token(0, 2, Highlight::Keyword, "fn"),
+                                token(3, 6, Highlight::String, "\"a\""),
+                            ],
+                        )),
+                    },
+                    DiffLine {
+                        lhs: None,
+                        rhs: Some(diff_side(2, vec![token(0, 3, Highlight::String, "\"b\"")])),
+                    },
+                ],
+                vec![DiffLine {
+                    lhs: Some(diff_side(3, vec![change(0, 1)])),
+                    rhs: None,
+                }],
+            ],
+        };
+        let result = process_file(
+            file,
+            vec!["a".into(), "fn x".into(), "c".into(), "d".into()],
+            vec!["a".into(), "fn \"a\"".into(), "\"b\"".into(), "c".into()],
+            None,
+            &ProcessOptions::default(),
+        );
+
+        assert_eq!(result.hunk_starts, vec![1, 4]);
+        assert_eq!(result.hunks.len(), 2);
+
+        let first = &result.hunks[0];
+        assert_eq!((first.start, first.end), (1, 3));
+        assert_eq!(first.left, Some(LineRange { start: 1, end: 2 }));
+        assert_eq!(first.right, Some(LineRange { start: 1, end: 3 }));
+        assert_eq!((first.additions, first.deletions), (2, 1));
+        assert_eq!(
+            first.kinds,
+            vec![Highlight::String, Highlight::Normal, Highlight::Keyword]
+        );
+
+        let second = &result.hunks[1];
+        assert_eq!((second.start, second.end), (4, 5));
+        assert_eq!(second.left, Some(LineRange { start: 3, end: 4 }));
+        assert_eq!(second.right, None);
+        assert_eq!((second.additions, second.deletions), (0, 1));
+    }
+
+    #[test]
+    fn hunks_name_their_enclosing_scope() {
+        let file = DifftFile {
+            path: "lib.rs".into(),
+            language: "Rust".into(),
+            status: Status::Changed,
+            aligned_lines: (0..4).map(|i| (Some(i), Some(i))).collect(),
+            chunks: vec![vec![DiffLine {
+                lhs: Some(diff_side(2, vec![change(12, 13)])),
+                rhs: Some(diff_side(2, vec![change(12, 13)])),
+            }]],
+        };
+        let old = ["impl A {", "    fn a() {", "        let x = 1;", "    }"];
+        let new = ["impl A {", "    fn b() {", "        let x = 2;", "    }"];
+        let result = process_file(
+            file,
+            old.iter().map(|l| l.to_string()).collect(),
+            new.iter().map(|l| l.to_string()).collect(),
+            None,
+            &ProcessOptions::default(),
+        );
+
+        assert_eq!(result.hunks[0].left_scope.as_deref(), Some("fn a()"));
+        assert_eq!(result.hunks[0].right_scope.as_deref(), Some("fn b()"));
+    }
+
+    /// A file whose first four lines move below the fifth.
+    fn moved_block_file() -> (DifftFile, Vec<String>, Vec<String>) {
+        let block = ["fn helper() {", "    let a = 1;", "    a + 1", "}"];
+        let old: Vec<String> = block
+            .iter()
+            .chain(&["fn main() {}"])
+            .map(|l| l.to_string())
+            .collect();
+        let new: Vec<String> = ["fn main() {}"]
+            .iter()
+            .chain(&block)
+            .map(|l| l.to_string())
+            .collect();
+        let removed = |ln: u32| DiffLine {
+            lhs: Some(diff_side(ln, vec![change(0, 1)])),
+            rhs: None,
+        };
+        let added = |ln: u32| DiffLine {
+            lhs: None,
+            rhs: Some(diff_side(ln, vec![change(0, 1)])),
+        };
+        let file = DifftFile {
+            path: "main.rs".into(),
+            language: "Rust".into(),
+            status: Status::Changed,
+            aligned_lines: vec![
+                (Some(0), None),
+                (Some(1), None),
+                (Some(2), None),
+                (Some(3), None),
+                (Some(4), Some(0)),
+                (None, Some(1)),
+                (None, Some(2)),
+                (None, Some(3)),
+                (None, Some(4)),
+            ],
+            chunks: vec![(0..4).map(removed).collect(), (1..5).map(added).collect()],
+        };
+        (file, old, new)
+    }
+
+    #[test]
+    fn detect_moves_option_finds_moves_within_a_file() {
+        let (file, old, new) = moved_block_file();
+        let options = ProcessOptions {
+            detect_moves: true,
+            ..ProcessOptions::default()
+        };
+        let result = process_file(file, old, new, None, &options);
+
+        assert_eq!(result.moves.len(), 2);
+        assert_eq!(result.moves[0].kind, LineKind::Removed);
+        assert_eq!((result.moves[0].start, result.moves[0].end), (0, 4));
+        assert_eq!(
+            result.moves[0].counterpart_lines,
+            LineRange { start: 1, end: 5 }
+        );
+        assert_eq!(result.moves[1].kind, LineKind::Added);
+        assert_eq!((result.moves[1].start, result.moves[1].end), (5, 9));
+        assert_eq!(result.moves[1].counterpart_path, PathBuf::from("main.rs"));
+    }
+
+    #[test]
+    fn collected_diffs_detect_moves_in_one_pass() {
+        let options = ProcessOptions {
+            detect_moves: true,
+            ..ProcessOptions::default()
+        };
+        let (file, old, new) = moved_block_file();
+        let mut files = vec![process_file(file, old, new, None, &options.without_moves())];
+        assert!(files[0].moves.is_empty());
+
+        moves::detect_moves(&mut files);
+        let (file, old, new) = moved_block_file();
+        assert_eq!(
+            files[0].moves,
+            process_file(file, old, new, None, &options).moves
+        );
+    }
+
+    #[test]
+    fn regions_never_split_multibyte_characters() {
+        // "日本語" is three 3-byte characters; difftastic offsets inside one are widened.
+        let content = "let s = \"日本語\";";
+        let regions = compute_highlights(content, &[token(10, 13, Highlight::String, "本")]);
+        assert_eq!((regions[0].start, regions[0].end), (9, 15));
+        assert_eq!(regions[0].text.as_deref(), Some("本"));
+
+        // Offsets past the end of the line are clamped to it.
+        let regions = compute_highlights("ab", &[token(1, 9, Highlight::Normal, "b")]);
+        assert_eq!((regions[0].start, regions[0].end), (1, 2));
+    }
+
+    #[test]
+    fn display_columns_expand_tabs_and_wide_characters() {
+        assert_eq!(display_column("\tx", 1, 4), 4);
+        assert_eq!(display_column("ab\tx", 3, 4), 4);
+        assert_eq!(display_column("ab\tx", 3, 8), 8);
+        assert_eq!(display_column("日本x", 6, 4), 4);
+        assert_eq!(display_column("🦀x", 4, 4), 2);
+        assert_eq!(display_column("e\u{301}x", 3, 4), 1);
+        assert_eq!(display_column("ab", 10, 4), 2);
+    }
+
+    #[test]
+    fn tab_width_option_sets_display_columns() {
+        let file = DifftFile {
+            path: "tabs.rs".into(),
+            language: "Rust".into(),
+            status: Status::Changed,
+            aligned_lines: vec![(Some(0), Some(0))],
+            chunks: vec![vec![DiffLine {
+                lhs: None,
+                rhs: Some(diff_side(
+                    0,
+                    vec![token(5, 10, Highlight::String, "\"日\"")],
+                )),
+            }]],
+        };
+        let options = ProcessOptions {
+            difft: DifftOptions {
+                tab_width: Some(8),
+                ..DifftOptions::default()
+            },
+            ..ProcessOptions::default()
+        };
+        let result = process_file(
+            file,
+            vec!["\tx = 1;".to_string()],
+            vec!["\tx = \"日\";".to_string()],
+            None,
+            &options,
+        );
+
+        let region = &result.rows[0].right.highlights[0];
+        assert_eq!((region.start, region.end), (5, 10));
+        assert_eq!(region.display, Some((12, 16)));
+    }
+
+    #[test]
+    fn ignore_whitespace_hides_whitespace_only_rows() {
+        let file = DifftFile {
+            path: "fmt.txt".into(),
+            language: "Text".into(),
+            status: Status::Changed,
+            aligned_lines: vec![(Some(0), Some(0)), (None, Some(1)), (Some(1), Some(2))],
+            chunks: vec![vec![
+                DiffLine {
+                    lhs: Some(diff_side(0, vec![change(0, 6)])),
+                    rhs: Some(diff_side(0, vec![change(0, 10)])),
+                },
+                DiffLine {
+                    lhs: None,
+                    rhs: Some(diff_side(1, vec![change(0, 2)])),
+                },
+                DiffLine {
+                    lhs: Some(diff_side(1, vec![change(0, 5)])),
+                    rhs: Some(diff_side(2, vec![change(0, 5)])),
+                },
+            ]],
+        };
+        let old = vec!["a  = 1".to_string(), "b = 2".to_string()];
+        let new = vec![
+            "    a = 1".to_string(),
+            "  ".to_string(),
+            "b = 3".to_string(),
+        ];
+        let options = ProcessOptions {
+            ignore_whitespace: true,
+            ..ProcessOptions::default()
+        };
+        let result = process_file(file, old, new, None, &options);
+
+        assert!(result.rows[0].whitespace_only);
+        assert!(result.rows[0].right.highlights.is_empty());
+        assert!(result.rows[1].whitespace_only);
+        assert!(!result.rows[1].is_changed());
+        assert!(result.rows[2].is_changed());
+        assert_eq!(result.hunk_starts, vec![2]);
+        assert!(!result.whitespace_only);
+    }
+
+    #[test]
+    fn files_with_only_whitespace_changes_are_marked() {
+        let file = DifftFile {
+            path: "fmt.rs".into(),
+            language: "Rust".into(),
+            status: Status::Changed,
+            aligned_lines: vec![(Some(0), Some(0))],
+            chunks: vec![vec![DiffLine {
+                lhs: Some(diff_side(0, vec![change(0, 9)])),
+                rhs: Some(diff_side(0, vec![change(0, 8)])),
+            }]],
+        };
+        let options = ProcessOptions {
+            ignore_whitespace: true,
+            ..ProcessOptions::default()
+        };
+        let summary = summarize_file(&file, Some((0, 0)), &options);
+        assert!(summary.whitespace_only);
+
+        let result = process_file(
+            file,
+            vec!["let x=1;\r".to_string()],
+            vec!["let x = 1;".to_string()],
+            None,
+            &options,
+        );
+        assert!(result.whitespace_only);
+        assert!(result.hunk_starts.is_empty());
+    }
+
+    #[test]
+    fn full_line_regions_have_no_class() {
+        let region = HighlightRegion::full_line();
+        assert_eq!(region.kind, None);
+        assert_eq!(region.text, None);
+    }
+
     #[test]
     fn expansion_multiline_to_single() {
         let file = DifftFile {
@@ -762,7 +2437,7 @@ mod tests {
             "}".into(),
         ];
 
-        let result = process_file(file, old_lines, new_lines, None);
+        let result = process_file(file, old_lines, new_lines, None, &ProcessOptions::default());
 
         assert_eq!(result.rows.len(), 5);
         assert_eq!(result.rows[0].left.content, "Self { a, b, c }");
@@ -817,7 +2492,7 @@ mod tests {
         ];
         let new_lines = vec!["Self { a, b, c }".into()];
 
-        let result = process_file(file, old_lines, new_lines, None);
+        let result = process_file(file, old_lines, new_lines, None, &ProcessOptions::default());
 
         assert_eq!(result.rows.len(), 5);
         assert_eq!(result.rows[0].left.content, "Self {");
@@ -874,7 +2549,7 @@ mod tests {
             "fff".into(),
         ];
 
-        let result = process_file(file, old_lines, new_lines, None);
+        let result = process_file(file, old_lines, new_lines, None, &ProcessOptions::default());
 
         // Should have two hunks: one starting at row 1, one at row 5
         assert_eq!(result.hunk_starts.len(), 2);
@@ -891,7 +2566,13 @@ mod tests {
             aligned_lines: vec![],
             chunks: vec![],
         };
-        let result = process_file(file, vec![], vec!["a".into(), "b".into(), "c".into()], None);
+        let result = process_file(
+            file,
+            vec![],
+            vec!["a".into(), "b".into(), "c".into()],
+            None,
+            &ProcessOptions::default(),
+        );
 
         // Created files: left is always None, right maps 0..n
         assert_eq!(result.aligned_lines.len(), 3);
@@ -909,7 +2590,13 @@ mod tests {
             aligned_lines: vec![],
             chunks: vec![],
         };
-        let result = process_file(file, vec!["x".into(), "y".into()], vec![], None);
+        let result = process_file(
+            file,
+            vec!["x".into(), "y".into()],
+            vec![],
+            None,
+            &ProcessOptions::default(),
+        );
 
         // Deleted files: left maps 0..n, right is always None
         assert_eq!(result.aligned_lines.len(), 2);
@@ -937,6 +2624,7 @@ mod tests {
             vec!["a".into(), "b".into(), "c".into()],
             vec!["a".into(), "b".into(), "new".into(), "c".into()],
             None,
+            &ProcessOptions::default(),
         );
 
         // Changed files: aligned_lines should be passed through from difftastic
@@ -962,6 +2650,7 @@ mod tests {
             vec!["a".into(), "deleted".into(), "b".into()],
             vec!["a".into(), "b".into()],
             None,
+            &ProcessOptions::default(),
         );
 
         assert_eq!(result.aligned_lines, aligned);
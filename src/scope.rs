@@ -0,0 +1,286 @@
+//! Enclosing definition names for hunk headers.
+//!
+//! Like `git diff`'s function context, each hunk is labelled with the definition it sits
+//! in: a function, impl, class or section heading. difftastic doesn't report syntax
+//! scopes, so the name is found with a heuristic over all lines above the hunk, changed
+//! or not:
+//!
+//! 1. A [`ScopeTracker`] reads the lines of one side of a file once, top to bottom.
+//! 2. A header is a line that looks like a definition: a definition keyword such as `fn`,
+//!    `def`, `class` or `function` after any modifiers, or for C-like languages an
+//!    unindented function signature. The tracker keeps a stack of open headers by indent;
+//!    a header closes those indented as much or more.
+//! 3. A hunk's scope is the innermost open header indented less than the hunk's first
+//!    non-blank line, so nested definitions resolve to their parent rather than to an
+//!    earlier sibling. In Markdown it is the last heading, since prose is not indented by
+//!    section.
+//!
+//! The header line itself is the scope name, trimmed of an opening `{` or trailing `:`.
+
+/// Modifiers that may precede a definition keyword.
+const MODIFIERS: &[&str] = &[
+    "pub",
+    "pub(crate)",
+    "pub(super)",
+    "export",
+    "default",
+    "async",
+    "unsafe",
+    "extern",
+    "static",
+    "public",
+    "private",
+    "protected",
+    "internal",
+    "abstract",
+    "final",
+    "override",
+    "local",
+    "const",
+];
+
+/// Keywords that start a definition.
+const KEYWORDS: &[&str] = &[
+    "fn",
+    "impl",
+    "trait",
+    "struct",
+    "enum",
+    "mod",
+    "union",
+    "macro_rules!",
+    "def",
+    "class",
+    "function",
+    "func",
+    "interface",
+    "module",
+    "namespace",
+    "object",
+    "fun",
+    "sub",
+    "proc",
+    "defn",
+    "defmacro",
+];
+
+/// Languages whose functions are declared without a keyword.
+const C_LIKE: &[&str] = &["C", "C++", "C#", "Java", "Objective-C", "CUDA"];
+
+/// Statements that look like signatures but aren't.
+const C_STATEMENTS: &[&str] = &[
+    "if", "for", "while", "switch", "return", "else", "do", "case",
+];
+
+/// Longest scope name reported; longer headers are cut at a character boundary.
+const MAX_SCOPE_LEN: usize = 120;
+
+/// Tracks the definitions open at each line of one side of a file.
+pub struct ScopeTracker<'a> {
+    language: &'a str,
+    /// Open headers with their indent, outermost first.
+    headers: Vec<(usize, &'a str)>,
+    /// The last Markdown heading.
+    heading: Option<&'a str>,
+}
+
+impl<'a> ScopeTracker<'a> {
+    pub fn new(language: &'a str) -> Self {
+        Self {
+            language,
+            headers: Vec::new(),
+            heading: None,
+        }
+    }
+
+    /// Reads the next line of the file.
+    pub fn push(&mut self, line: &'a str) {
+        if self.language == "Markdown" {
+            if line.starts_with('#') {
+                self.heading = Some(line);
+            }
+            return;
+        }
+        if line.trim().is_empty() || !is_header(line, self.language) {
+            return;
+        }
+        let line_indent = indent(line);
+        while self
+            .headers
+            .last()
+            .is_some_and(|&(open, _)| open >= line_indent)
+        {
+            self.headers.pop();
+        }
+        self.headers.push((line_indent, line));
+    }
+
+    /// The name of the definition enclosing a hunk that starts after the lines read so
+    /// far. `hunk` yields the hunk's lines on the same side, in order.
+    pub fn scope<'b>(&self, mut hunk: impl Iterator<Item = &'b str>) -> Option<String> {
+        if self.language == "Markdown" {
+            return self.heading.map(scope_name);
+        }
+        let hunk_indent = hunk
+            .find(|line| !line.trim().is_empty())
+            .map_or(usize::MAX, indent);
+        self.headers
+            .iter()
+            .rev()
+            .find(|&&(open, _)| open < hunk_indent)
+            .map(|&(_, line)| scope_name(line))
+    }
+}
+
+/// Width of a line's leading whitespace, counting a tab as four columns.
+fn indent(line: &str) -> usize {
+    line.chars()
+        .take_while(|c| c.is_whitespace())
+        .map(|c| if c == '\t' { 4 } else { 1 })
+        .sum()
+}
+
+/// Returns `true` if `line` looks like the header of a definition in `language`.
+fn is_header(line: &str, language: &str) -> bool {
+    let trimmed = line.trim_start();
+    if language == "Markdown" {
+        return trimmed.starts_with('#');
+    }
+
+    let mut rest = trimmed;
+    while let Some(stripped) = MODIFIERS
+        .iter()
+        .find_map(|m| rest.strip_prefix(m).filter(|r| r.starts_with(' ')))
+    {
+        rest = stripped.trim_start();
+    }
+    let starts_definition = KEYWORDS.iter().any(|keyword| {
+        rest.strip_prefix(keyword)
+            .is_some_and(|r| r.starts_with([' ', '(', '<', '{']))
+    });
+    if starts_definition {
+        return true;
+    }
+
+    C_LIKE.contains(&language) && is_c_signature(line)
+}
+
+/// Returns `true` for an unindented line like `static int parse(const char *s) {`.
+fn is_c_signature(line: &str) -> bool {
+    let Some(first) = line.chars().next() else {
+        return false;
+    };
+    if !(first.is_alphabetic() || first == '_') {
+        return false;
+    }
+    let Some(paren) = line.find('(') else {
+        return false;
+    };
+    let head = &line[..paren];
+    let first_word = head.split_whitespace().next().unwrap_or_default();
+    !line.trim_end().ends_with(';') && !head.contains('=') && !C_STATEMENTS.contains(&first_word)
+}
+
+/// Turns a header line into a scope name.
+fn scope_name(line: &str) -> String {
+    let name = line
+        .trim()
+        .trim_end_matches('{')
+        .trim_end()
+        .trim_end_matches(':');
+    let mut end = name.len().min(MAX_SCOPE_LEN);
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    name[..end].trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scope(before: &[&str], hunk: &[&str], language: &str) -> Option<String> {
+        let mut tracker = ScopeTracker::new(language);
+        for line in before {
+            tracker.push(line);
+        }
+        tracker.scope(hunk.iter().copied())
+    }
+
+    #[test]
+    fn finds_the_enclosing_rust_function() {
+        let before = [
+            "impl Parser {",
+            "    pub fn parse(&self) -> u32 {",
+            "        let a = 1;",
+        ];
+        assert_eq!(
+            scope(&before, &["        let b = 2;"], "Rust").as_deref(),
+            Some("pub fn parse(&self) -> u32")
+        );
+    }
+
+    #[test]
+    fn nested_definitions_resolve_to_their_parent() {
+        let before = [
+            "class Shape:",
+            "    def area(self):",
+            "        return 0",
+            "",
+        ];
+        assert_eq!(
+            scope(&before, &["    def perimeter(self):"], "Python").as_deref(),
+            Some("class Shape")
+        );
+    }
+
+    #[test]
+    fn markdown_uses_the_nearest_heading() {
+        let before = ["# Title", "", "## Install", "Run this:"];
+        assert_eq!(
+            scope(&before, &["cargo build"], "Markdown").as_deref(),
+            Some("## Install")
+        );
+    }
+
+    #[test]
+    fn c_signatures_are_headers() {
+        let before = [
+            "static int parse(const char *s) {",
+            "    if (s == NULL) {",
+            "        return 0;",
+            "    }",
+        ];
+        assert_eq!(
+            scope(&before, &["    return 1;"], "C").as_deref(),
+            Some("static int parse(const char *s)")
+        );
+        assert_eq!(scope(&["int x = f(1);"], &["  y"], "C"), None);
+    }
+
+    #[test]
+    fn closed_definitions_are_not_scopes() {
+        let before = [
+            "impl A {",
+            "    fn a() {",
+            "    }",
+            "}",
+            "impl B {",
+            "    const X: u32 = 1;",
+        ];
+        assert_eq!(
+            scope(&before, &["    const Y: u32 = 2;"], "Rust").as_deref(),
+            Some("impl B")
+        );
+        assert_eq!(
+            scope(&before, &["        let b = 2;"], "Rust").as_deref(),
+            Some("impl B")
+        );
+    }
+
+    #[test]
+    fn top_level_hunks_have_no_scope() {
+        let before = ["fn a() {}", ""];
+        assert_eq!(scope(&before, &["fn b() {}"], "Rust"), None);
+    }
+}
@@ -0,0 +1,489 @@
+//! Lazily materialized diffs.
+//!
+//! Building every [`Row`](crate::processor::Row) of every file, and converting all of them
+//! into Lua tables, is wasted work when the user only looks at a handful of files. A
+//! [`DiffSession`] splits a diff into two phases:
+//!
+//! 1. Opening the session runs difftastic and collects file metadata only: path, status,
+//!    language, line stats and renames. No file content is fetched yet.
+//! 2. [`DiffSession::file`] fetches the content of a single file and processes it into rows
+//!    the first time it is requested. The result is cached for later requests.
+//!
+//! With [`ProcessOptions::direct_difft`], opening the session only lists the changed files,
+//! and difftastic runs on each file's contents in the second phase (see [`crate::direct`]).
+//!
+//! Sessions handed to Lua are kept in a process-wide registry and addressed by id until
+//! Lua closes them with `close_session`.
+//!
+//! Sessions for commit ranges are also what the [`crate::cache`] stores: reopening a
+//! cached range hands out the same session, including every file already materialized.
+
+use crate::cache::{self, CacheKey};
+use crate::difftastic::DifftFile;
+use crate::direct;
+use crate::error::DiffError;
+use crate::exec::Runner;
+use crate::processor::{self, DisplayFile, ProcessOptions};
+use crate::{ContentSource, DiffMode, PreparedDiff};
+use mlua::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, OnceLock};
+
+/// Open sessions, keyed by the id handed to Lua.
+static SESSIONS: LazyLock<Mutex<HashMap<u64, Arc<DiffSession>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Id of the next registered session. Ids are never reused.
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// A file whose rows are materialized on first access.
+struct SessionFile {
+    /// Metadata reported up front; rows are empty.
+    summary: DisplayFile,
+
+    /// The difftastic entry, with rename display paths already resolved.
+    file: DifftFile,
+    old_path: PathBuf,
+    new_path: PathBuf,
+    stats: Option<(u32, u32)>,
+
+    /// The fully processed file, once requested.
+    display: OnceLock<DisplayFile>,
+}
+
+/// A [`SessionFile`] as persisted in the disk cache.
+#[derive(Serialize, Deserialize)]
+struct StoredFile {
+    summary: DisplayFile,
+    file: DifftFile,
+    old_path: PathBuf,
+    new_path: PathBuf,
+    stats: Option<(u32, u32)>,
+    display: Option<DisplayFile>,
+}
+
+/// A [`DiffSession`] as persisted in the disk cache.
+#[derive(Default, Serialize, Deserialize)]
+pub struct StoredSession {
+    files: Vec<StoredFile>,
+}
+
+/// A diff whose files are processed on demand.
+pub struct DiffSession {
+    source: ContentSource,
+    files: Vec<SessionFile>,
+
+    /// How files are processed when they are materialized.
+    options: ProcessOptions,
+
+    /// Key the session is cached under, for commit ranges.
+    cache_key: Option<CacheKey>,
+}
+
+impl DiffSession {
+    /// Runs difftastic for `mode` and collects the metadata of every file.
+    ///
+    /// Commit ranges are served from the cache when possible, in which case the returned
+    /// session is shared with earlier callers that used the same `options`.
+    pub fn open(
+        runner: &Runner,
+        mode: &DiffMode,
+        vcs: &str,
+        options: &ProcessOptions,
+    ) -> Result<Arc<Self>, DiffError> {
+        let root = crate::vcs_root(runner, vcs);
+        let cache_key = cache::key(runner, mode, vcs, &root, options);
+
+        if let Some(key) = &cache_key
+            && let Some(session) = cache::get(key, |stored| {
+                let source = ContentSource::new(runner, mode, vcs, root.clone());
+                Self::restore(stored, source, options.clone(), Some(key.clone()))
+            })
+        {
+            return Ok(session);
+        }
+
+        let prepared = crate::prepare_diff(runner, mode, vcs, root, &[], options)?;
+        let mut session = Self::from_prepared(prepared, options.clone());
+        session.cache_key = cache_key;
+        let session = Arc::new(session);
+        if let Some(key) = &session.cache_key {
+            cache::insert(key.clone(), Arc::clone(&session));
+            cache::persist(key, &session);
+        }
+        Ok(session)
+    }
+
+    /// Collects the file metadata of an already prepared diff. The session is not cached.
+    pub fn from_prepared(prepared: PreparedDiff, options: ProcessOptions) -> Self {
+        let PreparedDiff {
+            files,
+            stats,
+            source,
+            renames,
+            old_paths,
+        } = prepared;
+
+        let files = files
+            .into_iter()
+            .filter_map(|mut file| {
+                let (file_stats, old_path, new_path, moved_from) =
+                    crate::prepare_file_for_display(&mut file, &stats);
+
+                let mut summary = processor::summarize_file(&file, file_stats, &options);
+                summary.moved_from = moved_from;
+                let summary = crate::apply_renames(summary, &renames, &old_paths)?;
+
+                Some(SessionFile {
+                    summary,
+                    file,
+                    old_path,
+                    new_path,
+                    stats: file_stats,
+                    display: OnceLock::new(),
+                })
+            })
+            .collect();
+
+        Self {
+            source,
+            files,
+            options,
+            cache_key: None,
+        }
+    }
+
+    /// Rebuilds a session from a disk cache entry.
+    pub fn restore(
+        stored: StoredSession,
+        source: ContentSource,
+        options: ProcessOptions,
+        cache_key: Option<CacheKey>,
+    ) -> Self {
+        let files = stored
+            .files
+            .into_iter()
+            .map(|file| SessionFile {
+                summary: file.summary,
+                file: file.file,
+                old_path: file.old_path,
+                new_path: file.new_path,
+                stats: file.stats,
+                display: file.display.map(OnceLock::from).unwrap_or_default(),
+            })
+            .collect();
+
+        Self {
+            source,
+            files,
+            options,
+            cache_key,
+        }
+    }
+
+    /// Converts the session, including every materialized file, for the disk cache.
+    pub fn to_stored(&self) -> StoredSession {
+        StoredSession {
+            files: self
+                .files
+                .iter()
+                .map(|file| StoredFile {
+                    summary: file.summary.clone(),
+                    file: file.file.clone(),
+                    old_path: file.old_path.clone(),
+                    new_path: file.new_path.clone(),
+                    stats: file.stats,
+                    display: file.display.get().cloned(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Number of files in the diff.
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// File metadata in difftastic's output order, without rows.
+    pub fn summaries(&self) -> impl Iterator<Item = &DisplayFile> {
+        self.files.iter().map(|file| &file.summary)
+    }
+
+    /// Returns `true` once every file has been materialized.
+    pub fn is_materialized(&self) -> bool {
+        self.files.iter().all(|file| file.display.get().is_some())
+    }
+
+    /// Returns the fully processed file at `index`, fetching its content on first access.
+    ///
+    /// Content is read when the file is first requested, so working tree diffs reflect
+    /// the files as they are at that point. Returns `Ok(None)` if `index` is out of range.
+    pub fn file(&self, runner: &Runner, index: usize) -> Result<Option<&DisplayFile>, DiffError> {
+        let Some(entry) = self.files.get(index) else {
+            return Ok(None);
+        };
+        if let Some(display) = entry.display.get() {
+            return Ok(Some(display));
+        }
+
+        let (old, new) = self.source.fetch(runner, &entry.old_path, &entry.new_path);
+        // Content fetches cut short by cancellation come back empty; don't cache those.
+        if runner.is_cancelled() {
+            return Err(DiffError::Cancelled);
+        }
+
+        // Directly diffed files get their difftastic entry from the contents just read.
+        let (file, error) = if self.options.direct_difft {
+            match direct::diff_contents(
+                runner,
+                &entry.file.path,
+                &entry.old_path,
+                &entry.new_path,
+                old.as_deref(),
+                new.as_deref(),
+                &self.options,
+            ) {
+                Ok(file) => (file, None),
+                Err(e) if direct::is_fatal(&e) => return Err(e),
+                Err(e) => (entry.file.clone(), Some(e.to_string())),
+            }
+        } else {
+            (entry.file.clone(), None)
+        };
+
+        let mut display = processor::process_file(
+            file,
+            crate::into_lines(old),
+            crate::into_lines(new),
+            entry.stats,
+            &self.options,
+        );
+        display.error = error;
+        display.moved_from = entry.summary.moved_from.clone();
+        display.status = entry.summary.status;
+        Ok(Some(entry.display.get_or_init(|| display)))
+    }
+
+    /// Writes the session to the disk cache, if it is cacheable and a cache directory is
+    /// configured.
+    pub fn persist(&self) {
+        if let Some(key) = &self.cache_key {
+            cache::persist(key, self);
+        }
+    }
+
+    /// Stops any blob reader process; it is restarted if another file is requested.
+    pub fn release_readers(&self) {
+        self.source.release_readers();
+    }
+}
+
+/// Registers a session and returns the id Lua addresses it by.
+pub fn register(session: Arc<DiffSession>) -> u64 {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    sessions().insert(id, session);
+    id
+}
+
+/// Looks up an open session.
+pub fn get(id: u64) -> Option<Arc<DiffSession>> {
+    sessions().get(&id).cloned()
+}
+
+/// Closes a session, dropping its cached rows. Returns `false` if it was not open.
+pub fn close(id: u64) -> bool {
+    sessions().remove(&id).is_some()
+}
+
+fn sessions() -> std::sync::MutexGuard<'static, HashMap<u64, Arc<DiffSession>>> {
+    SESSIONS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Converts a file's metadata into a Lua table, without rows.
+fn summary_to_lua(lua: &Lua, file: &DisplayFile) -> LuaResult<LuaTable> {
+    let table = lua.create_table()?;
+    table.set("path", file.path.to_string_lossy().as_ref())?;
+    table.set(
+        "moved_from",
+        file.moved_from
+            .as_ref()
+            .map(|p| p.to_string_lossy().into_owned()),
+    )?;
+    table.set("language", file.language.as_str())?;
+    table.set("status", file.status.as_str())?;
+    table.set("additions", file.additions)?;
+    table.set("deletions", file.deletions)?;
+    table.set("whitespace_only", file.whitespace_only)?;
+    Ok(table)
+}
+
+/// Registers `session` and converts it into the `{ session = id, files = { ... } }`
+/// result table returned to Lua.
+pub fn session_to_lua(lua: &Lua, session: Arc<DiffSession>) -> LuaResult<LuaTable> {
+    let files = lua.create_table_with_capacity(session.len(), 0)?;
+    for (i, summary) in session.summaries().enumerate() {
+        files.set(i + 1, summary_to_lua(lua, summary)?)?;
+    }
+
+    let result = lua.create_table()?;
+    result.set("session", register(session))?;
+    result.set("files", files)?;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cat_file::CatFile;
+    use crate::difftastic::Status;
+    use crate::direct::TempDir;
+    use crate::exec::CancelToken;
+    use std::collections::HashSet;
+
+    fn difft_file(path: &str, status: Status) -> DifftFile {
+        DifftFile {
+            path: path.into(),
+            language: "Text".into(),
+            status,
+            aligned_lines: vec![],
+            chunks: vec![],
+        }
+    }
+
+    /// A session over a temporary working tree, diffed against an empty index. The tree
+    /// is removed when the returned directory is dropped.
+    fn session(
+        files: Vec<DifftFile>,
+        renames: HashMap<PathBuf, PathBuf>,
+    ) -> (DiffSession, TempDir) {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().to_path_buf();
+        std::fs::write(root.join("new.txt"), "one\ntwo\n").unwrap();
+
+        let old_paths: HashSet<PathBuf> = renames.values().cloned().collect();
+        let session = DiffSession::from_prepared(
+            PreparedDiff {
+                files,
+                stats: HashMap::new(),
+                source: ContentSource::GitUnstaged {
+                    blobs: CatFile::new(root.clone()),
+                    root,
+                },
+                renames,
+                old_paths,
+            },
+            ProcessOptions::default(),
+        );
+        (session, dir)
+    }
+
+    #[test]
+    fn summaries_have_no_rows() {
+        let (session, _dir) = session(vec![difft_file("new.txt", Status::Created)], HashMap::new());
+
+        let summaries: Vec<_> = session.summaries().collect();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].path, PathBuf::from("new.txt"));
+        assert!(summaries[0].rows.is_empty());
+    }
+
+    #[test]
+    fn file_materializes_rows_once() {
+        let (session, _dir) = session(vec![difft_file("new.txt", Status::Created)], HashMap::new());
+        let runner = Runner::default();
+
+        let file = session.file(&runner, 0).unwrap().unwrap();
+        assert_eq!(file.rows.len(), 2);
+        assert_eq!(file.rows[1].right.content, "two");
+        assert!(std::ptr::eq(
+            file,
+            session.file(&runner, 0).unwrap().unwrap()
+        ));
+        assert!(session.file(&runner, 1).unwrap().is_none());
+        assert!(session.is_materialized());
+    }
+
+    #[test]
+    fn cancelled_fetches_are_not_cached() {
+        let (session, _dir) = session(vec![difft_file("new.txt", Status::Created)], HashMap::new());
+        let cancel = CancelToken::default();
+        cancel.cancel();
+
+        assert_eq!(
+            session.file(&Runner::new(cancel), 0),
+            Err(DiffError::Cancelled)
+        );
+        assert!(!session.is_materialized());
+        assert_eq!(
+            session
+                .file(&Runner::default(), 0)
+                .unwrap()
+                .unwrap()
+                .rows
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn stored_sessions_keep_materialized_rows() {
+        let (original, _dir) = session(
+            vec![
+                difft_file("new.txt", Status::Created),
+                difft_file("other.txt", Status::Created),
+            ],
+            HashMap::new(),
+        );
+        let rows = original
+            .file(&Runner::default(), 0)
+            .unwrap()
+            .unwrap()
+            .rows
+            .clone();
+
+        let restored = DiffSession::restore(
+            original.to_stored(),
+            ContentSource::GitStaged {
+                blobs: CatFile::new(PathBuf::from(".")),
+            },
+            ProcessOptions::default(),
+            None,
+        );
+        assert_eq!(restored.len(), 2);
+        assert_eq!(restored.files[0].display.get().unwrap().rows, rows);
+        assert!(restored.files[1].display.get().is_none());
+    }
+
+    #[test]
+    fn renames_apply_to_summaries_and_rows() {
+        let renames = HashMap::from([(PathBuf::from("new.txt"), PathBuf::from("old.txt"))]);
+        let (session, _dir) = session(
+            vec![
+                difft_file("old.txt", Status::Deleted),
+                difft_file("new.txt", Status::Created),
+            ],
+            renames,
+        );
+
+        assert_eq!(session.len(), 1);
+        let summary = session.summaries().next().unwrap();
+        assert_eq!(summary.moved_from, Some(PathBuf::from("old.txt")));
+
+        let file = session.file(&Runner::default(), 0).unwrap().unwrap();
+        assert_eq!(file.moved_from, Some(PathBuf::from("old.txt")));
+        assert_eq!(file.status, Status::Created);
+    }
+
+    #[test]
+    fn registry_round_trip() {
+        let (session, _dir) = session(Vec::new(), HashMap::new());
+        let id = register(Arc::new(session));
+        assert!(get(id).is_some());
+        assert!(close(id));
+        assert!(get(id).is_none());
+        assert!(!close(id));
+    }
+}
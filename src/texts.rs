@@ -0,0 +1,128 @@
+//! Diffs of in-memory texts, such as unsaved buffers.
+//!
+//! [`diff_texts`] runs difftastic on two texts that need not exist on disk or in a
+//! repository: a modified buffer against its file on disk, or two scratch buffers. Each
+//! text comes with an optional file name and language hint (see [`Text`]). The texts are
+//! diffed like a directly diffed file (see [`crate::direct`]) and processed through the same
+//! pipeline as any other file.
+
+use crate::difftastic::MAX_OVERRIDES;
+use crate::direct;
+use crate::error::DiffError;
+use crate::exec::Runner;
+use crate::processor::{DisplayFile, ProcessOptions};
+use mlua::prelude::*;
+use std::path::{Path, PathBuf};
+
+/// Name a text without one is diffed and reported under.
+const UNNAMED: &str = "buffer";
+
+/// One side of a text diff.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Text {
+    pub text: String,
+
+    /// File name the text is diffed under; difftastic detects the language from it.
+    pub name: Option<PathBuf>,
+
+    /// difftastic language name, such as `Rust` or `C++`, taking precedence over the name.
+    pub language: Option<String>,
+}
+
+impl FromLua for Text {
+    /// Reads a string, or a `{ text = ..., name = ..., language = ... }` table.
+    fn from_lua(value: LuaValue, _: &Lua) -> LuaResult<Self> {
+        let opts = match value {
+            LuaValue::String(text) => {
+                return Ok(Self {
+                    text: text.to_string_lossy(),
+                    ..Self::default()
+                });
+            }
+            LuaValue::Table(opts) => opts,
+            other => {
+                return Err(LuaError::FromLuaConversionError {
+                    from: other.type_name(),
+                    to: "Text".to_string(),
+                    message: Some("expected a string or a { text = ... } table".to_string()),
+                });
+            }
+        };
+
+        let text: Option<LuaString> = opts.get("text")?;
+        let Some(text) = text else {
+            return Err(LuaError::RuntimeError(
+                "Missing `text` in text to diff".to_string(),
+            ));
+        };
+        Ok(Self {
+            text: text.to_string_lossy(),
+            name: opts.get::<Option<String>>("name")?.map(PathBuf::from),
+            language: opts.get("language")?,
+        })
+    }
+}
+
+/// Diffs the `old` text against the `new` one and processes the result.
+///
+/// The file is reported under the new text's name, or the old one's; if the two names
+/// differ, the old one is its `moved_from`. A language hint on either text, the new one's
+/// first, overrides language detection for both.
+pub fn diff_texts(
+    runner: &Runner,
+    old: &Text,
+    new: &Text,
+    options: &ProcessOptions,
+) -> Result<DisplayFile, DiffError> {
+    let old_name = old.name.as_deref().or(new.name.as_deref());
+    let new_name = new.name.as_deref().or(old_name);
+    let path = new_name.unwrap_or(Path::new(UNNAMED));
+
+    let options = with_language(options, new.language.as_deref().or(old.language.as_deref()));
+    let file = direct::diff_contents(
+        runner,
+        path,
+        old_name.unwrap_or(path),
+        path,
+        Some(&old.text),
+        Some(&new.text),
+        &options,
+    )?;
+    let mut display = direct::process(
+        file,
+        Some(old.text.clone()),
+        Some(new.text.clone()),
+        &options,
+    );
+    display.moved_from = old_name.filter(|&name| name != path).map(Path::to_path_buf);
+    Ok(display)
+}
+
+/// Makes difftastic parse every file as `language`, ahead of the configured overrides.
+fn with_language(options: &ProcessOptions, language: Option<&str>) -> ProcessOptions {
+    let mut options = options.clone();
+    if let Some(language) = language {
+        let overrides = &mut options.difft.overrides;
+        overrides.insert(0, format!("*:{language}"));
+        overrides.truncate(MAX_OVERRIDES);
+    }
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn language_hints_take_precedence_over_overrides() {
+        let mut options = ProcessOptions::default();
+        options.difft.overrides = (0..MAX_OVERRIDES).map(|i| format!("*.{i}:C")).collect();
+
+        let hinted = with_language(&options, Some("Rust"));
+        assert_eq!(hinted.difft.overrides.len(), MAX_OVERRIDES);
+        assert_eq!(hinted.difft.overrides[0], "*:Rust");
+        assert_eq!(hinted.difft.overrides[1], "*.0:C");
+
+        assert_eq!(with_language(&options, None), options);
+    }
+}
@@ -0,0 +1,576 @@
+//! Watching the working copy for changes that invalidate the unstaged diff.
+//!
+//! The unstaged diff compares the working tree against the index (git) or `@-` (jj), so it
+//! goes stale whenever a tracked file is written, and entirely whenever the index or the
+//! working-copy commit moves. A [`RepoWatcher`] watches the repository root on a background
+//! thread and accumulates what is stale until Lua drains it:
+//!
+//! - A change to a tracked file marks that file stale. jj also tracks new files on its next
+//!   snapshot, so for jj a change to any file its `.gitignore` rules don't exclude counts.
+//! - A change to the git index or to jj's working-copy state marks every file stale, but
+//!   only if it actually moved the base of the diff. Commands that merely rewrite that
+//!   state, such as the snapshot `jj diff` takes, are ignored.
+//!
+//! Changes to other files (ignored build output, editor swap files, and for git untracked
+//! files) are dropped, so a running build does not flood the view with refreshes.
+//!
+//! ## Wakeup
+//!
+//! Like a [`crate::job::DiffJob`], a watcher hands out the read end of a pipe through
+//! `wakeup_fd` that becomes readable when changes are pending. At most one byte is written
+//! between two calls to `changes`, so a burst of writes costs Lua a single wakeup.
+
+use crate::exec::{self, Program, Runner};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use mlua::prelude::*;
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io::{self, PipeReader, PipeWriter, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// What the unstaged diff of a repository is currently based on.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Baseline {
+    /// Changes whenever the old side of the diff changes.
+    fingerprint: Vec<u8>,
+    /// Repo-root-relative paths of the files the VCS tracks.
+    tracked: HashSet<PathBuf>,
+}
+
+/// Reads the baseline of the repository at `root`.
+///
+/// For git the index listing serves as both: it covers every tracked path and the blob
+/// each is compared against. For jj the fingerprint is the id of `@-`. Neither reads the
+/// working copy, so jj does not snapshot it.
+fn baseline(vcs: &str, root: &Path) -> Baseline {
+    let run = |program: Program, args: &[&str]| {
+        Runner::default()
+            .output(exec::command(program).args(args).current_dir(root))
+            .ok()
+            .filter(|o| o.status.success())
+            .map(|o| o.stdout)
+            .unwrap_or_default()
+    };
+
+    if vcs == "git" {
+        let index = run(Program::Git, &["ls-files", "-s", "-z"]);
+        let tracked = parse_ls_files(&index);
+        Baseline {
+            fingerprint: index,
+            tracked,
+        }
+    } else {
+        let parents = run(
+            Program::Jj,
+            &[
+                "log",
+                "-r",
+                "@-",
+                "--ignore-working-copy",
+                "--no-graph",
+                "-T",
+                "commit_id ++ \"\n\"",
+            ],
+        );
+        let files = run(Program::Jj, &["file", "list", "--ignore-working-copy"]);
+        Baseline {
+            fingerprint: parents,
+            tracked: String::from_utf8_lossy(&files)
+                .lines()
+                .map(PathBuf::from)
+                .collect(),
+        }
+    }
+}
+
+/// Parses the paths out of `git ls-files -s -z` output (`<mode> <oid> <stage>\t<path>\0`).
+fn parse_ls_files(output: &[u8]) -> HashSet<PathBuf> {
+    output
+        .split(|&b| b == 0)
+        .filter_map(|entry| {
+            let tab = entry.iter().position(|&b| b == b'\t')?;
+            Some(PathBuf::from(
+                String::from_utf8_lossy(&entry[tab + 1..]).into_owned(),
+            ))
+        })
+        .collect()
+}
+
+/// The `.gitignore` rules of a jj working copy, which decide what jj starts tracking.
+///
+/// Each directory's rules are read once, until a `.gitignore` changes.
+struct IgnoreRules {
+    root: PathBuf,
+    /// `core.excludesFile`, or git's default global excludes file.
+    global: Gitignore,
+    /// Rules of each directory read so far, by repo-root-relative path.
+    dirs: HashMap<PathBuf, Gitignore>,
+}
+
+impl IgnoreRules {
+    fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            global: GitignoreBuilder::new(root).build_global().0,
+            dirs: HashMap::new(),
+        }
+    }
+
+    /// Returns `true` if the file at repo-root-relative `path` is ignored.
+    ///
+    /// The `.gitignore` closest to the file decides, as in git, then the global excludes.
+    fn is_ignored(&mut self, path: &Path) -> bool {
+        let absolute = self.root.join(path);
+        for dir in path.ancestors().skip(1) {
+            let root = &self.root;
+            let rules = self
+                .dirs
+                .entry(dir.to_path_buf())
+                .or_insert_with(|| Gitignore::new(root.join(dir).join(".gitignore")).0);
+            let matched = rules.matched_path_or_any_parents(&absolute, false);
+            if !matched.is_none() {
+                return matched.is_ignore();
+            }
+        }
+        self.global
+            .matched_path_or_any_parents(&absolute, false)
+            .is_ignore()
+    }
+
+    /// Drops the rules read so far, after a `.gitignore` changed.
+    fn reset(&mut self) {
+        self.dirs.clear();
+    }
+}
+
+/// What a changed path means for the diff.
+#[derive(Debug, PartialEq, Eq)]
+enum Change {
+    /// A file in the working tree changed.
+    File(PathBuf),
+    /// The index or working-copy state changed; the baseline may have moved.
+    Baseline,
+    /// VCS internals or paths outside the repository.
+    Ignored,
+}
+
+/// Classifies a changed path relative to the repository root.
+fn classify(vcs: &str, root: &Path, path: &Path) -> Change {
+    let Ok(relative) = path.strip_prefix(root) else {
+        return Change::Ignored;
+    };
+    match relative.components().next() {
+        Some(Component::Normal(dir)) if dir == ".git" => {
+            if vcs == "git" && relative == Path::new(".git/index") {
+                Change::Baseline
+            } else {
+                Change::Ignored
+            }
+        }
+        Some(Component::Normal(dir)) if dir == ".jj" => {
+            if vcs != "git" && relative == Path::new(".jj/working_copy/checkout") {
+                Change::Baseline
+            } else {
+                Change::Ignored
+            }
+        }
+        Some(_) => Change::File(relative.to_path_buf()),
+        None => Change::Ignored,
+    }
+}
+
+/// Stale state accumulated since the last drain.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Changes {
+    /// Every file is stale; the whole diff should be recomputed.
+    pub all: bool,
+    /// Repo-root-relative paths of stale files.
+    pub paths: BTreeSet<PathBuf>,
+}
+
+impl Changes {
+    fn is_empty(&self) -> bool {
+        !self.all && self.paths.is_empty()
+    }
+}
+
+/// State shared between a [`RepoWatcher`] handle and the notify thread.
+struct Shared {
+    vcs: String,
+    root: PathBuf,
+    baseline: Mutex<Baseline>,
+    /// Only read for jj; git does not track new files on its own.
+    ignores: Mutex<IgnoreRules>,
+    pending: Mutex<Changes>,
+    wakeup: Mutex<Option<PipeWriter>>,
+}
+
+impl Shared {
+    fn pending(&self) -> MutexGuard<'_, Changes> {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn handle(&self, event: Event) {
+        // Reads don't change anything; they fire constantly while other tools scan the tree.
+        if event.kind.is_access() {
+            return;
+        }
+
+        let mut changes = Changes::default();
+        for path in &event.paths {
+            match classify(&self.vcs, &self.root, path) {
+                Change::File(path) => {
+                    if self.affects_diff(&path) {
+                        changes.paths.insert(path);
+                    }
+                }
+                Change::Baseline => changes.all |= self.reload_baseline(),
+                Change::Ignored => {}
+            }
+        }
+        self.push(changes);
+    }
+
+    /// Returns `true` if a change to the file at `path` can change the diff: it is tracked,
+    /// or, for jj, it will be tracked on the next snapshot because it isn't ignored.
+    fn affects_diff(&self, path: &Path) -> bool {
+        if self
+            .baseline
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .tracked
+            .contains(path)
+        {
+            return true;
+        }
+        if self.vcs == "git" {
+            return false;
+        }
+
+        let mut ignores = self.ignores.lock().unwrap_or_else(|e| e.into_inner());
+        if path.file_name().is_some_and(|name| name == ".gitignore") {
+            ignores.reset();
+        }
+        !self.root.join(path).is_dir() && !ignores.is_ignored(path)
+    }
+
+    /// Re-reads the baseline and returns `true` if the diff's base moved.
+    fn reload_baseline(&self) -> bool {
+        let fresh = baseline(&self.vcs, &self.root);
+        let mut current = self.baseline.lock().unwrap_or_else(|e| e.into_inner());
+        let moved = current.fingerprint != fresh.fingerprint;
+        *current = fresh;
+        moved
+    }
+
+    /// Merges changes into the pending set and signals the wakeup pipe if it was empty.
+    fn push(&self, changes: Changes) {
+        if changes.is_empty() {
+            return;
+        }
+        let needs_signal = {
+            let mut pending = self.pending();
+            let was_empty = pending.is_empty();
+            pending.all |= changes.all;
+            pending.paths.extend(changes.paths);
+            was_empty
+        };
+        if needs_signal
+            && let Some(writer) = self
+                .wakeup
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .as_mut()
+        {
+            let _ = writer.write_all(&[1]);
+        }
+    }
+}
+
+/// Watches a repository's working copy for changes to the unstaged diff.
+///
+/// Exposed to Lua as userdata with `changes`, `wakeup_fd` and `stop` methods.
+pub struct RepoWatcher {
+    shared: Arc<Shared>,
+    /// Dropping the watcher stops the notify thread.
+    watcher: Option<RecommendedWatcher>,
+    /// Read end of the wakeup pipe, until it is handed out via `wakeup_fd`.
+    wakeup: Option<PipeReader>,
+}
+
+impl RepoWatcher {
+    /// Starts watching the repository at `root`.
+    pub fn start(vcs: &str, root: &Path) -> io::Result<Self> {
+        // Events carry paths below the watched path as given, so watch the canonical root
+        // to match what the VCS reports.
+        let root = root.canonicalize()?;
+
+        let (reader, writer) = match io::pipe() {
+            Ok((reader, writer)) => (Some(reader), Some(writer)),
+            Err(_) => (None, None),
+        };
+
+        let shared = Arc::new(Shared {
+            vcs: vcs.to_string(),
+            baseline: Mutex::new(baseline(vcs, &root)),
+            ignores: Mutex::new(IgnoreRules::new(&root)),
+            root,
+            pending: Mutex::new(Changes::default()),
+            wakeup: Mutex::new(writer),
+        });
+
+        let handler = Arc::clone(&shared);
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            if let Ok(event) = event {
+                handler.handle(event);
+            }
+        })
+        .map_err(io::Error::other)?;
+        watcher
+            .watch(&shared.root, RecursiveMode::Recursive)
+            .map_err(io::Error::other)?;
+
+        Ok(Self {
+            shared,
+            watcher: Some(watcher),
+            wakeup: reader,
+        })
+    }
+
+    /// Takes the changes accumulated since the last call.
+    pub fn drain(&self) -> Changes {
+        std::mem::take(&mut *self.shared.pending())
+    }
+
+    /// Stops watching and closes the wakeup pipe. Pending changes can still be drained.
+    pub fn stop(&mut self) {
+        self.watcher = None;
+        self.shared
+            .wakeup
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+    }
+
+    /// Hands out the raw read end of the wakeup pipe, transferring ownership to the caller.
+    ///
+    /// Returns `None` if the pipe could not be created or was already handed out.
+    #[cfg(unix)]
+    pub fn take_wakeup_fd(&mut self) -> Option<i32> {
+        use std::os::fd::IntoRawFd;
+        self.wakeup.take().map(IntoRawFd::into_raw_fd)
+    }
+
+    /// Wakeup pipes are only handed out on Unix; other platforms poll instead.
+    #[cfg(not(unix))]
+    pub fn take_wakeup_fd(&mut self) -> Option<i32> {
+        None
+    }
+}
+
+impl IntoLua for Changes {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let paths = lua.create_table_with_capacity(self.paths.len(), 0)?;
+        for (i, path) in self.paths.iter().enumerate() {
+            paths.set(i + 1, path.to_string_lossy().as_ref())?;
+        }
+
+        let table = lua.create_table()?;
+        table.set("all", self.all)?;
+        table.set("paths", paths)?;
+        Ok(LuaValue::Table(table))
+    }
+}
+
+impl LuaUserData for RepoWatcher {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("changes", |lua, this, ()| this.drain().into_lua(lua));
+
+        methods.add_method_mut("stop", |_, this, ()| {
+            this.stop();
+            Ok(())
+        });
+
+        methods.add_method_mut("wakeup_fd", |_, this, ()| Ok(this.take_wakeup_fd()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+    use std::time::{Duration, Instant};
+
+    fn git(root: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(root)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {args:?} failed");
+    }
+
+    /// Creates a git repository with one committed file, `tracked.txt`.
+    fn fixture(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!(
+            "difftastic-nvim-watch-{name}-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        git(&root, &["init", "-q"]);
+        std::fs::write(root.join("tracked.txt"), "one\n").unwrap();
+        git(&root, &["add", "tracked.txt"]);
+        git(&root, &["commit", "-q", "-m", "init"]);
+        root
+    }
+
+    /// Drains changes until `done` accepts them or a timeout passes.
+    fn wait_for(watcher: &RepoWatcher, done: impl Fn(&Changes) -> bool) -> Changes {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut seen = Changes::default();
+        while Instant::now() < deadline {
+            let changes = watcher.drain();
+            seen.all |= changes.all;
+            seen.paths.extend(changes.paths);
+            if done(&seen) {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        seen
+    }
+
+    #[test]
+    fn classifies_paths() {
+        let root = Path::new("/repo");
+        assert_eq!(
+            classify("git", root, Path::new("/repo/src/lib.rs")),
+            Change::File(PathBuf::from("src/lib.rs"))
+        );
+        assert_eq!(
+            classify("git", root, Path::new("/repo/.git/index")),
+            Change::Baseline
+        );
+        assert_eq!(
+            classify("git", root, Path::new("/repo/.git/index.lock")),
+            Change::Ignored
+        );
+        assert_eq!(
+            classify("jj", root, Path::new("/repo/.jj/working_copy/checkout")),
+            Change::Baseline
+        );
+        assert_eq!(
+            classify("jj", root, Path::new("/repo/.jj/repo/op_heads/heads/abc")),
+            Change::Ignored
+        );
+        assert_eq!(
+            classify("git", root, Path::new("/elsewhere/file.rs")),
+            Change::Ignored
+        );
+    }
+
+    #[test]
+    fn parses_ls_files() {
+        let tracked = parse_ls_files(
+            b"100644 e69de29bb2d1d6434b8b29ae775ad8c2e48c5391 0\ta b.txt\0\
+              100644 e69de29bb2d1d6434b8b29ae775ad8c2e48c5391 0\tsrc/lib.rs\0",
+        );
+        assert_eq!(
+            tracked,
+            HashSet::from([PathBuf::from("a b.txt"), PathBuf::from("src/lib.rs")])
+        );
+    }
+
+    #[test]
+    fn reports_tracked_files_only() {
+        let root = fixture("tracked");
+        let watcher = RepoWatcher::start("git", &root).unwrap();
+
+        std::fs::write(root.join("untracked.txt"), "new\n").unwrap();
+        std::fs::write(root.join("tracked.txt"), "two\n").unwrap();
+        let changes = wait_for(&watcher, |c| !c.paths.is_empty());
+
+        assert!(!changes.all);
+        assert_eq!(
+            changes.paths,
+            BTreeSet::from([PathBuf::from("tracked.txt")])
+        );
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn jj_reports_new_files_unless_ignored() {
+        let root = std::env::temp_dir().join(format!(
+            "difftastic-nvim-watch-jj-new-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("target")).unwrap();
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::write(root.join(".gitignore"), "target/\n*.swp\n").unwrap();
+        let watcher = RepoWatcher::start("jj", &root).unwrap();
+
+        std::fs::write(root.join("target/out.o"), "built\n").unwrap();
+        std::fs::write(root.join(".new.txt.swp"), "swap\n").unwrap();
+        std::fs::write(root.join("src/new.txt"), "new\n").unwrap();
+        let changes = wait_for(&watcher, |c| !c.paths.is_empty());
+
+        assert!(!changes.all);
+        assert_eq!(
+            changes.paths,
+            BTreeSet::from([PathBuf::from("src/new.txt")])
+        );
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn ignore_rules_follow_the_closest_gitignore() {
+        let root = std::env::temp_dir().join(format!(
+            "difftastic-nvim-watch-ignore-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("keep")).unwrap();
+        std::fs::write(root.join(".gitignore"), "*.log\n").unwrap();
+        std::fs::write(root.join("keep/.gitignore"), "!*.log\n").unwrap();
+
+        let mut rules = IgnoreRules::new(&root);
+        assert!(rules.is_ignored(Path::new("build.log")));
+        assert!(!rules.is_ignored(Path::new("keep/build.log")));
+        assert!(!rules.is_ignored(Path::new("src/lib.rs")));
+
+        std::fs::write(root.join(".gitignore"), "").unwrap();
+        rules.reset();
+        assert!(!rules.is_ignored(Path::new("build.log")));
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn staging_invalidates_everything() {
+        let root = fixture("staging");
+        let watcher = RepoWatcher::start("git", &root).unwrap();
+
+        std::fs::write(root.join("tracked.txt"), "two\n").unwrap();
+        git(&root, &["add", "tracked.txt"]);
+        let changes = wait_for(&watcher, |c| c.all);
+
+        assert!(changes.all);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn stopped_watchers_report_nothing() {
+        let root = fixture("stopped");
+        let mut watcher = RepoWatcher::start("git", &root).unwrap();
+        watcher.stop();
+
+        std::fs::write(root.join("tracked.txt"), "two\n").unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(watcher.drain().is_empty());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -274,6 +274,181 @@ describe("diff", function()
             assert.is_false(has_added_range)
         end)
 
+        it("colors changed tokens by class when no treesitter parser is available", function()
+            local state = {
+                left_win = left_win,
+                left_buf = left_buf,
+                right_win = right_win,
+                right_buf = right_buf,
+            }
+            local file = {
+                path = "notes.unknownext",
+                language = "Text",
+                hunk_starts = { 0 },
+                rows = {
+                    {
+                        left = {
+                            content = "let x = 'a'",
+                            is_filler = false,
+                            highlights = { { start = 8, ["end"] = 11, kind = "string", text = "'a'" } },
+                        },
+                        right = {
+                            content = "const x = 'a'",
+                            is_filler = false,
+                            highlights = {
+                                { start = 0, ["end"] = 5, kind = "keyword", text = "const" },
+                                { start = 6, ["end"] = 7, kind = "normal", text = "x" },
+                            },
+                        },
+                    },
+                },
+            }
+
+            diff.render(state, file)
+
+            local function token_groups(buf)
+                local groups = {}
+                for _, mark in ipairs(vim.api.nvim_buf_get_extmarks(buf, -1, 0, -1, { details = true })) do
+                    local details = mark[4] or {}
+                    if details.priority == 150 then
+                        table.insert(groups, details.hl_group)
+                    end
+                end
+                return groups
+            end
+
+            assert.same({ "DifftTokenString" }, token_groups(left_buf))
+            assert.same({ "DifftTokenKeyword" }, token_groups(right_buf))
+        end)
+
+        it("folds unchanged runs reported for the file", function()
+            local rows = {}
+            for i = 1, 10 do
+                rows[i] = {
+                    left = { content = "line " .. i, is_filler = false, highlights = {} },
+                    right = { content = "line " .. i, is_filler = false, highlights = {} },
+                }
+            end
+            rows[8].right.highlights = { { start = 0, ["end"] = -1 } }
+
+            local state = {
+                left_win = left_win,
+                left_buf = left_buf,
+                right_win = right_win,
+                right_buf = right_buf,
+            }
+            diff.render(state, {
+                path = "folds.lua",
+                language = "Lua",
+                hunk_starts = { 7 },
+                folds = { { start = 0, ["end"] = 5 } },
+                rows = rows,
+            })
+
+            for _, win in ipairs({ left_win, right_win }) do
+                vim.api.nvim_win_call(win, function()
+                    assert.equals(1, vim.fn.foldclosed(1))
+                    assert.equals(5, vim.fn.foldclosedend(1))
+                    assert.equals(-1, vim.fn.foldclosed(6))
+                end)
+            end
+        end)
+
+        it("shows hunk scopes above hunks on both sides", function()
+            local state = {
+                left_win = left_win,
+                left_buf = left_buf,
+                right_win = right_win,
+                right_buf = right_buf,
+            }
+            diff.render(state, {
+                path = "scope.lua",
+                language = "Lua",
+                hunk_starts = { 1 },
+                hunks = { { start = 1, ["end"] = 2, right_scope = "function M.run()" } },
+                rows = {
+                    {
+                        left = { content = "function M.run()", is_filler = false, highlights = {} },
+                        right = { content = "function M.run()", is_filler = false, highlights = {} },
+                    },
+                    {
+                        left = { content = "", is_filler = true, highlights = {} },
+                        right = { content = "    go()", is_filler = false, highlights = { { start = 0, ["end"] = -1 } } },
+                    },
+                },
+            })
+
+            local function virt_lines(buf)
+                for _, mark in ipairs(vim.api.nvim_buf_get_extmarks(buf, -1, 0, -1, { details = true })) do
+                    local details = mark[4] or {}
+                    if details.virt_lines then
+                        return mark[2], details.virt_lines[1][1][1]
+                    end
+                end
+            end
+
+            local left_row, left_text = virt_lines(left_buf)
+            local right_row, right_text = virt_lines(right_buf)
+            assert.equals(1, left_row)
+            assert.equals("", left_text)
+            assert.equals(1, right_row)
+            assert.equals("@@ function M.run()", right_text)
+        end)
+
+        it("marks both ends of a moved block", function()
+            local state = {
+                left_win = left_win,
+                left_buf = left_buf,
+                right_win = right_win,
+                right_buf = right_buf,
+            }
+            local removed = { content = "go()", is_filler = false, highlights = { { start = 0, ["end"] = -1 } } }
+            local filler = { content = "", is_filler = true, highlights = {} }
+            diff.render(state, {
+                path = "moved.lua",
+                language = "Lua",
+                hunk_starts = { 0, 1 },
+                moves = {
+                    {
+                        kind = "removed",
+                        start = 0,
+                        ["end"] = 1,
+                        lines = { start = 0, ["end"] = 1 },
+                        counterpart_path = "other.lua",
+                        counterpart_lines = { start = 4, ["end"] = 5 },
+                    },
+                    {
+                        kind = "added",
+                        start = 1,
+                        ["end"] = 2,
+                        lines = { start = 0, ["end"] = 1 },
+                        counterpart_path = "moved.lua",
+                        counterpart_lines = { start = 0, ["end"] = 1 },
+                    },
+                },
+                rows = {
+                    { left = removed, right = filler },
+                    { left = filler, right = removed },
+                },
+            })
+
+            local function virt_text(buf)
+                for _, mark in ipairs(vim.api.nvim_buf_get_extmarks(buf, -1, 0, -1, { details = true })) do
+                    local details = mark[4] or {}
+                    if details.virt_text and details.virt_text[1][2] == "DifftMoved" then
+                        return mark[2], details.virt_text[1][1]
+                    end
+                end
+            end
+
+            local left_row, left_text = virt_text(left_buf)
+            local right_row, right_text = virt_text(right_buf)
+            assert.equals(0, left_row)
+            assert.equals("moved to other.lua:5", left_text)
+            assert.equals(1, right_row)
+            assert.equals("moved from line 1", right_text)
+        end)
+
         it("renders diffs with more lines than Neovim's auxiliary stack can reference at once", function()
             local row_count = 8100
             local rows = {}